@@ -0,0 +1,157 @@
+//! `stream_to_stream` のスループット計測
+//!
+//! 純粋なテキスト・単一のツール呼び出し・デモと同じ混在入力のそれぞれについて、
+//! 入力のチャンクサイズを変えて1秒あたりの処理文字数を計測します。
+//!
+//! ```bash
+//! cargo bench --bench stream_to_stream
+//! ```
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use futures::StreamExt;
+use std::hint::black_box;
+use tool_use_with_xml::stream_to_stream::{XmlStreamParser, parse_str, stream_to_stream};
+
+const PURE_TEXT: &str = "明日のニューヨークの天気ですね。承知いたしました。\n外部の天気予報ツールを使って最新の情報を確認しますね。\n";
+
+const SINGLE_TOOL: &str = r#"<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+  <unit>fahrenheit</unit>
+</get_weather>
+"#;
+
+const MIXED: &str = r#"明日のニューヨークの天気を確認します。
+
+<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+  <unit>fahrenheit</unit>
+</get_weather>
+
+天気予報を取得しました。次に、ファイルに書き込みます。
+
+<write_to_file>
+<path>weather_report.txt</path>
+<content>
+明日のニューヨークの天気予報：
+- 最高気温: 75°F
+- 最低気温: 60°F
+- 天候: 晴れ時々曇り
+</content>
+</write_to_file>
+
+処理が完了しました。
+"#;
+
+/// 約16KBになるまで入力を繰り返す
+fn repeat_to_size(input: &str) -> String {
+    input.repeat(16 * 1024 / input.len() + 1)
+}
+
+/// 入力を指定した文字数ごとのチャンクに分割する
+fn chunk_input(input: &str, chunk_size: usize) -> Vec<String> {
+    input
+        .chars()
+        .collect::<Vec<_>>()
+        .chunks(chunk_size)
+        .map(|chunk| chunk.iter().collect())
+        .collect()
+}
+
+fn bench_stream_to_stream(c: &mut Criterion) {
+    let mut group = c.benchmark_group("stream_to_stream");
+    for (name, input) in [
+        ("pure_text", PURE_TEXT),
+        ("single_tool", SINGLE_TOOL),
+        ("mixed", MIXED),
+    ] {
+        let input = repeat_to_size(input);
+        group.throughput(Throughput::Elements(input.chars().count() as u64));
+        for chunk_size in [1, 16, 256, 64 * 1024] {
+            let chunks = chunk_input(&input, chunk_size);
+            group.bench_with_input(BenchmarkId::new(name, chunk_size), &chunks, |b, chunks| {
+                b.iter(|| {
+                    let input_stream = futures::stream::iter(chunks.clone()).boxed();
+                    let stream = stream_to_stream(input_stream).unwrap();
+                    black_box(futures::executor::block_on(stream.count()))
+                });
+            });
+        }
+    }
+    group.finish();
+}
+
+/// 純粋なテキストについて、1文字ずつの処理と `coalesce_text` によるまとめての発行を比較する
+fn bench_coalesce_text(c: &mut Criterion) {
+    let mut group = c.benchmark_group("coalesce_text");
+    let input = repeat_to_size(PURE_TEXT);
+    group.throughput(Throughput::Elements(input.chars().count() as u64));
+    for chunk_size in [16, 256] {
+        let chunks = chunk_input(&input, chunk_size);
+        for coalesce in [false, true] {
+            let id = BenchmarkId::new(format!("pure_text_coalesce_{coalesce}"), chunk_size);
+            group.bench_with_input(id, &chunks, |b, chunks| {
+                b.iter(|| {
+                    let input_stream = futures::stream::iter(chunks.clone()).boxed();
+                    let stream = XmlStreamParser::new(input_stream).coalesce_text(coalesce);
+                    black_box(futures::executor::block_on(stream.count()))
+                });
+            });
+        }
+    }
+    group.finish();
+}
+
+/// 既定の（テキストをまとめない）同期パースについて、大きなパラメータの値と純粋なテキストを計測する
+///
+/// 入力の長さに対して処理時間が線形であることを、サイズごとの比較で確認する。
+fn bench_parse_str(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_str");
+    for size in [16 * 1024, 256 * 1024] {
+        let large_param = format!(
+            "<write_to_file><path>a.txt</path><content>{}</content></write_to_file>",
+            "x".repeat(size)
+        );
+        let pure_text = PURE_TEXT.repeat(size / PURE_TEXT.len() + 1);
+        for (name, input) in [("large_param", large_param), ("pure_text", pure_text)] {
+            group.throughput(Throughput::Bytes(input.len() as u64));
+            group.bench_with_input(BenchmarkId::new(name, size), &input, |b, input| {
+                b.iter(|| black_box(parse_str(input).count()));
+            });
+        }
+    }
+    group.finish();
+}
+
+/// 1MBの混在入力を大きなチャンクで渡す場合。処理が入力の大きさに対して線形であることを確認する
+fn bench_large_chunks(c: &mut Criterion) {
+    let mut group = c.benchmark_group("large_chunks");
+    group.sample_size(10);
+    let input = MIXED.repeat(1024 * 1024 / MIXED.len() + 1);
+    group.throughput(Throughput::Elements(input.chars().count() as u64));
+    for chunk_size in [64 * 1024, input.chars().count()] {
+        let chunks = chunk_input(&input, chunk_size);
+        group.bench_with_input(
+            BenchmarkId::new("mixed_1mb", chunk_size),
+            &chunks,
+            |b, chunks| {
+                b.iter(|| {
+                    let input_stream = futures::stream::iter(chunks.clone()).boxed();
+                    let stream = stream_to_stream(input_stream).unwrap();
+                    black_box(futures::executor::block_on(stream.count()))
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_stream_to_stream,
+    bench_coalesce_text,
+    bench_parse_str,
+    bench_large_chunks
+);
+criterion_main!(benches);
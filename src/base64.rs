@@ -0,0 +1,48 @@
+//! パラメータ値のbase64デコード
+//!
+//! 標準のアルファベット（RFC 4648）のみを対象とした簡易実装です。
+//! モデルが長い値を折り返して出力することがあるため、ASCIIの空白は無視します。
+
+/// 1文字を6ビットの値に変換する
+fn sextet(byte: u8) -> Option<u32> {
+    let value = match byte {
+        b'A'..=b'Z' => byte - b'A',
+        b'a'..=b'z' => byte - b'a' + 26,
+        b'0'..=b'9' => byte - b'0' + 52,
+        b'+' => 62,
+        b'/' => 63,
+        _ => return None,
+    };
+    Some(u32::from(value))
+}
+
+/// base64文字列をデコードする。不正な文字や長さの場合は `None` を返す
+pub(crate) fn decode(text: &str) -> Option<Vec<u8>> {
+    let symbols: Vec<u8> = text
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    if !symbols.len().is_multiple_of(4) {
+        return None;
+    }
+    let data = symbols
+        .strip_suffix(b"==")
+        .or_else(|| symbols.strip_suffix(b"="))
+        .unwrap_or(&symbols);
+
+    let mut bytes = Vec::with_capacity(data.len() * 3 / 4);
+    for chunk in data.chunks(4) {
+        let mut block = 0;
+        for &byte in chunk {
+            block = (block << 6) | sextet(byte)?;
+        }
+        // 末尾の不完全なブロックは、余ったビットを捨てて1〜2バイトにする
+        match chunk.len() {
+            4 => bytes.extend_from_slice(&block.to_be_bytes()[1..]),
+            3 => bytes.extend_from_slice(&(block >> 2).to_be_bytes()[2..]),
+            2 => bytes.push((block >> 4) as u8),
+            _ => return None,
+        }
+    }
+    Some(bytes)
+}
@@ -0,0 +1,26 @@
+use tokio_stream::StreamExt;
+use tool_use_with_xml::stream::{ToolCallEvent, ToolCallStream};
+
+#[tokio::main]
+async fn main() {
+    let xml = r#"<get_weather><location>Tokyo</location><date>2024-03-21</date></get_weather>"#;
+
+    let stream = ToolCallStream::new(xml.as_bytes());
+    let mut stream = Box::pin(stream);
+
+    while let Some(result) = stream.next().await {
+        match result {
+            Ok(event) => match event {
+                ToolCallEvent::ToolStart(name) => println!("ツール開始: {}", name),
+                ToolCallEvent::Parameter { name, value } => {
+                    println!("パラメータ: {} = {}", name, value)
+                }
+                ToolCallEvent::ToolEnd => println!("ツール終了"),
+                ToolCallEvent::Error(err) => println!("ツールエラー: {}", err),
+            },
+            Err(e) => println!("エラー: {}", e),
+        }
+    }
+
+    println!("---イベントストリーム終了---");
+}
@@ -0,0 +1,74 @@
+use futures::StreamExt;
+use tool_use_with_xml::stream_to_stream::{ToolCallEvent, stream_to_stream};
+
+#[tokio::main]
+async fn main() {
+    // サンプルの入力テキスト
+    let input = r#"明日のニューヨークの天気を確認します。
+
+<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+  <unit>fahrenheit</unit>
+</get_weather>
+
+天気予報を取得しました。次に、ファイルに書き込みます。
+
+<write_to_file>
+<path>weather_report.txt</path>
+<content>
+明日のニューヨークの天気予報：
+- 最高気温: 75°F
+- 最低気温: 60°F
+- 天候: 晴れ時々曇り
+</content>
+</write_to_file>
+
+処理が完了しました。"#;
+
+    // 入力テキストを1文字ずつのストリームに変換
+    let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+
+    // ストリームを処理
+    match stream_to_stream(input_stream) {
+        Ok(mut stream) => {
+            // イベントを順番に処理
+            while let Some(event) = stream.next().await {
+                match event {
+                    ToolCallEvent::Text(text) => {
+                        // テキストイベントの処理
+                        print!("{}", text);
+                    }
+                    ToolCallEvent::ToolStart { id, name } => {
+                        // ツール開始イベントの処理
+                        println!("\n[ツール開始: {} (ID: {})]", name, id);
+                    }
+                    ToolCallEvent::Parameter { id, arguments } => {
+                        // パラメータイベントの処理
+                        println!(
+                            "[パラメータ (ID: {}): {}]",
+                            id,
+                            serde_json::to_string_pretty(&arguments).unwrap()
+                        );
+                    }
+                    ToolCallEvent::ToolEnd { id } => {
+                        // ツール終了イベントの処理
+                        println!("[ツール終了 (ID: {})]\n", id);
+                    }
+                    ToolCallEvent::Error(err) => {
+                        eprintln!("エラー: {}", err);
+                    }
+                    ToolCallEvent::ParameterStart { .. }
+                    | ToolCallEvent::ParameterEnd { .. }
+                    | ToolCallEvent::ParameterDelta { .. }
+                    | ToolCallEvent::TentativeParameter { .. }
+                    | ToolCallEvent::SuspiciousValue { .. }
+                    | ToolCallEvent::StreamSummary { .. } => {}
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("ストリームの作成に失敗しました: {}", e);
+        }
+    }
+}
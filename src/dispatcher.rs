@@ -0,0 +1,229 @@
+//! パースしたツール呼び出しを、登録済みのハンドラーに振り分けて実行する最小限のルーター
+
+use crate::{ToolCall, ToolParseError, ToolParser};
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::future::Future;
+
+/// ツール呼び出しを処理するハンドラー
+pub type Handler<E> = Box<dyn Fn(&ToolCall) -> Result<String, E> + Send + Sync>;
+
+/// `Dispatcher::dispatch` のエラー
+#[derive(thiserror::Error, Debug)]
+pub enum DispatchError<E> {
+    #[error(transparent)]
+    Parse(#[from] ToolParseError),
+    #[error("Unknown tool: {0}")]
+    UnknownTool(String),
+    #[error("Invalid arguments for {tool_name}: {source}")]
+    InvalidArgs {
+        tool_name: String,
+        source: serde_json::Error,
+    },
+    #[error("Handler error: {0}")]
+    Handler(E),
+}
+
+/// ツール名ごとにハンドラーを登録し、応答テキスト中のツール呼び出しを実行する
+pub struct Dispatcher<E> {
+    parser: ToolParser,
+    handlers: HashMap<String, Handler<E>>,
+}
+
+impl<E> Default for Dispatcher<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E> Dispatcher<E> {
+    pub fn new() -> Self {
+        Self {
+            parser: ToolParser::new(),
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// ツール呼び出しのパースに使用するパーサーを設定する
+    pub fn parser(mut self, parser: ToolParser) -> Self {
+        self.parser = parser;
+        self
+    }
+
+    /// ツール名に対するハンドラーを登録する。同じ名前で登録した場合は上書きする
+    pub fn register<F>(mut self, tool_name: &str, handler: F) -> Self
+    where
+        F: Fn(&ToolCall) -> Result<String, E> + Send + Sync + 'static,
+    {
+        self.handlers
+            .insert(tool_name.to_string(), Box::new(handler));
+        self
+    }
+
+    /// テキストから最初のツール呼び出しをパースし、対応するハンドラーを実行する
+    pub fn dispatch(&self, text: &str) -> Result<String, DispatchError<E>> {
+        let tool_call = self.parser.parse(text)?;
+        let handler = self
+            .handlers
+            .get(&tool_call.tool_name)
+            .ok_or_else(|| DispatchError::UnknownTool(tool_call.tool_name.clone()))?;
+        handler(&tool_call).map_err(DispatchError::Handler)
+    }
+}
+
+/// パラメータを型付きの引数に変換して呼び出す非同期ハンドラー
+type TypedHandler<E> =
+    Box<dyn Fn(&ToolCall) -> BoxFuture<'static, Result<String, DispatchError<E>>> + Send + Sync>;
+
+/// ツール名ごとに引数の型とハンドラーを登録し、パラメータを引数の型に変換してから実行する
+///
+/// パラメータの値はすべて文字列として変換するため、引数の型のフィールドは文字列で受け取る必要がある。
+pub struct TypedDispatcher<E> {
+    parser: ToolParser,
+    handlers: HashMap<String, TypedHandler<E>>,
+}
+
+impl<E> Default for TypedDispatcher<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E> TypedDispatcher<E> {
+    pub fn new() -> Self {
+        Self {
+            parser: ToolParser::new(),
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// ツール呼び出しのパースに使用するパーサーを設定する
+    pub fn parser(mut self, parser: ToolParser) -> Self {
+        self.parser = parser;
+        self
+    }
+
+    /// ツール名に対する引数の型とハンドラーを登録する。同じ名前で登録した場合は上書きする
+    pub fn register<A, F, Fut>(mut self, tool_name: &str, handler: F) -> Self
+    where
+        A: DeserializeOwned,
+        F: Fn(A) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String, E>> + Send + 'static,
+        E: Send + 'static,
+    {
+        let handler = move |tool_call: &ToolCall| {
+            let params =
+                serde_json::to_value(&tool_call.parameters).and_then(serde_json::from_value::<A>);
+            match params {
+                Ok(args) => handler(args)
+                    .map(|result| result.map_err(DispatchError::Handler))
+                    .boxed(),
+                Err(source) => {
+                    let error = DispatchError::InvalidArgs {
+                        tool_name: tool_call.tool_name.clone(),
+                        source,
+                    };
+                    futures::future::ready(Err(error)).boxed()
+                }
+            }
+        };
+        self.handlers
+            .insert(tool_name.to_string(), Box::new(handler));
+        self
+    }
+
+    /// テキストから最初のツール呼び出しをパースし、対応するハンドラーを実行する
+    pub async fn dispatch(&self, text: &str) -> Result<String, DispatchError<E>> {
+        let tool_call = self.parser.parse(text)?;
+        let handler = self
+            .handlers
+            .get(&tool_call.tool_name)
+            .ok_or_else(|| DispatchError::UnknownTool(tool_call.tool_name.clone()))?;
+        handler(&tool_call).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_dispatch_calls_registered_handler() {
+        let received = Arc::new(Mutex::new(None));
+        let dispatcher: Dispatcher<String> = Dispatcher::new().register("get_weather", {
+            let received = received.clone();
+            move |tool_call| {
+                *received.lock().unwrap() = Some(tool_call.parameters.clone());
+                Ok(format!("{}は晴れです", tool_call.parameters["location"]))
+            }
+        });
+
+        let result = dispatcher
+            .dispatch("<get_weather><location>Tokyo</location><date>tomorrow</date></get_weather>")
+            .unwrap();
+        assert_eq!(result, "Tokyoは晴れです");
+        assert_eq!(
+            received.lock().unwrap().clone(),
+            Some(HashMap::from([
+                ("location".to_string(), "Tokyo".to_string()),
+                ("date".to_string(), "tomorrow".to_string()),
+            ]))
+        );
+
+        assert!(matches!(
+            dispatcher.dispatch("<search><query>rust</query></search>"),
+            Err(DispatchError::UnknownTool(name)) if name == "search"
+        ));
+        assert!(matches!(
+            dispatcher.dispatch("ツール呼び出しはありません"),
+            Err(DispatchError::Parse(ToolParseError::NoToolXmlFound))
+        ));
+    }
+
+    #[derive(serde::Deserialize)]
+    struct WeatherArgs {
+        location: String,
+        date: Option<String>,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct SearchArgs {
+        query: String,
+    }
+
+    #[tokio::test]
+    async fn test_typed_dispatch() {
+        let dispatcher: TypedDispatcher<String> = TypedDispatcher::new()
+            .register("get_weather", |args: WeatherArgs| async move {
+                Ok(format!(
+                    "{}の{}の天気は晴れです",
+                    args.location,
+                    args.date.as_deref().unwrap_or("今日")
+                ))
+            })
+            .register("search", |args: SearchArgs| async move {
+                Ok(format!("「{}」の検索結果", args.query))
+            });
+
+        let result = dispatcher
+            .dispatch("<get_weather><location>Tokyo</location><date>明日</date></get_weather>")
+            .await
+            .unwrap();
+        assert_eq!(result, "Tokyoの明日の天気は晴れです");
+        let result = dispatcher
+            .dispatch("<search><query>rust</query></search>")
+            .await
+            .unwrap();
+        assert_eq!(result, "「rust」の検索結果");
+
+        // 必須の引数がない場合はハンドラーを呼び出さない
+        assert!(matches!(
+            dispatcher.dispatch("<search><q>rust</q></search>").await,
+            Err(DispatchError::InvalidArgs { tool_name, .. }) if tool_name == "search"
+        ));
+    }
+}
@@ -0,0 +1,122 @@
+//! ツール名とパラメータ名のインターン
+//!
+//! 同じツールを大量にパースする場合に、`location` や `date` のような繰り返し現れる名前を
+//! 1つの `Arc<str>` で共有し、保持するツール呼び出しのメモリ使用量を抑えます。
+
+use crate::ToolCall;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// ツール名とパラメータ名を共有するためのインターナー
+#[derive(Debug, Default, Clone)]
+pub struct KeyInterner {
+    names: HashSet<Arc<str>>,
+}
+
+impl KeyInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 名前を登録し、同じ名前に対しては常に同じ領域を指す `Arc<str>` を返す
+    pub fn intern(&mut self, name: &str) -> Arc<str> {
+        if let Some(interned) = self.names.get(name) {
+            return interned.clone();
+        }
+        let interned: Arc<str> = Arc::from(name);
+        self.names.insert(interned.clone());
+        interned
+    }
+
+    /// 登録済みの名前の数
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// ツール呼び出しの名前をインターンした表現に変換する
+    pub fn intern_call(&mut self, tool_call: ToolCall) -> InternedToolCall {
+        let mut intern_keys = |map: HashMap<String, String>| {
+            map.into_iter()
+                .map(|(key, value)| (self.intern(&key), value))
+                .collect()
+        };
+        let parameters = intern_keys(tool_call.parameters);
+        let attributes = intern_keys(tool_call.attributes);
+        InternedToolCall {
+            tool_name: self.intern(&tool_call.tool_name),
+            parameters,
+            attributes,
+        }
+    }
+}
+
+/// ツール名とパラメータ名を `KeyInterner` で共有したツール呼び出し
+#[derive(Debug, PartialEq, Clone)]
+pub struct InternedToolCall {
+    pub tool_name: Arc<str>,
+    pub parameters: HashMap<Arc<str>, String>,
+    /// ツールタグの属性（`AttributeMode::Separate` の場合のみ格納される）
+    pub attributes: HashMap<Arc<str>, String>,
+}
+
+impl InternedToolCall {
+    /// パラメータの値を取得する
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.parameters.get(key).map(String::as_str)
+    }
+}
+
+impl From<InternedToolCall> for ToolCall {
+    fn from(call: InternedToolCall) -> Self {
+        let to_strings = |map: HashMap<Arc<str>, String>| {
+            map.into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect()
+        };
+        ToolCall {
+            tool_name: call.tool_name.to_string(),
+            parameters: to_strings(call.parameters),
+            attributes: to_strings(call.attributes),
+            spans: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ToolParser;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_interned_keys_are_shared_across_parses() {
+        let parser = ToolParser::new();
+        let mut interner = KeyInterner::new();
+        let calls: Vec<_> = (0..1000)
+            .map(|i| {
+                let text = format!(
+                    "<get_weather><location>City {i}</location><date>tomorrow</date><unit>celsius</unit></get_weather>"
+                );
+                parser.parse_interned(&text, &mut interner).unwrap()
+            })
+            .collect();
+
+        // ツール名と3つのパラメータ名のみを保持し、すべての呼び出しで同じ領域を共有する
+        assert_eq!(interner.len(), 4);
+        let location = interner.intern("location");
+        assert_eq!(Arc::strong_count(&location), calls.len() + 2);
+        for call in &calls {
+            let (key, _) = call.parameters.get_key_value("location").unwrap();
+            assert!(Arc::ptr_eq(key, &location));
+        }
+        assert_eq!(calls[999].get("location"), Some("City 999"));
+
+        let tool_call = ToolCall::from(calls[0].clone());
+        assert_eq!(tool_call.tool_name, "get_weather");
+        assert_eq!(tool_call.parameters["location"], "City 0");
+    }
+}
@@ -0,0 +1,3039 @@
+//! LLMの応答テキストからXML形式のツール呼び出しを抽出・パースするライブラリ
+
+#[cfg(feature = "quick-xml")]
+use quick_xml::Reader;
+#[cfg(feature = "quick-xml")]
+use quick_xml::events::{BytesStart, Event};
+#[cfg(feature = "quick-xml")]
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "quick-xml")]
+use std::borrow::Borrow;
+use std::borrow::Cow;
+#[cfg(feature = "quick-xml")]
+use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "quick-xml")]
+use std::hash::Hash;
+use std::ops::Range;
+use std::time::Duration;
+#[cfg(feature = "quick-xml")]
+use std::time::Instant;
+
+mod base64;
+#[cfg(feature = "quick-xml")]
+pub mod dispatcher;
+pub mod intern;
+#[cfg(feature = "minimal")]
+pub mod minimal;
+#[cfg(feature = "async-openai")]
+pub mod openai;
+pub mod schema;
+pub mod session;
+#[cfg(feature = "simple-schema")]
+pub mod simple_schema;
+pub mod sse;
+#[cfg(feature = "quick-xml")]
+pub mod stream;
+pub mod stream_to_stream;
+#[cfg(feature = "quick-xml")]
+mod yaml_fallback;
+
+// パースエラーを表すEnum
+#[derive(thiserror::Error, Debug)]
+pub enum ToolParseError {
+    #[cfg(feature = "quick-xml")]
+    #[error("XML parsing error: {0}")]
+    XmlError(#[from] quick_xml::Error),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Expected start tag, found {0:?}")]
+    ExpectedStartTag(String),
+    #[error("Expected end tag {expected}, found {found}")]
+    MismatchedEndTag {
+        expected: String,
+        found: String,
+        /// 入力テキスト中の、不一致だった終了タグのバイト位置
+        position: usize,
+    },
+    #[error("Unexpected end of file")]
+    UnexpectedEof,
+    #[error(
+        "Unexpected end of file in {tool_name}{}",
+        param_name.as_ref().map(|name| format!(" (inside parameter {name})")).unwrap_or_default()
+    )]
+    UnexpectedEofIn {
+        tool_name: String,
+        /// 終端に達した時点で開いていたパラメータ名
+        param_name: Option<String>,
+    },
+    #[error("Tool name not found")]
+    ToolNameNotFound,
+    #[error("Invalid XML structure")]
+    InvalidStructure,
+    #[error("No tool XML found in the input text")]
+    NoToolXmlFound,
+    #[error("JSON parsing error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("No string value found at JSON pointer {0:?}")]
+    JsonPointerNotFound(String),
+    #[error("Invalid value {value:?} for parameter {key}, expected one of {allowed:?}")]
+    InvalidEnumValue {
+        key: String,
+        value: String,
+        allowed: Vec<String>,
+    },
+    #[error("Too few parameters for {tool_name}: expected {expected}, found {found}")]
+    TooFewParams {
+        tool_name: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Too many parameters for {tool_name}: expected {expected}, found {found}")]
+    TooManyParams {
+        tool_name: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Invalid base64 value for parameter {key}")]
+    InvalidBase64 { key: String },
+    #[error("Tool {opened} was closed by a different tag </{closed}>")]
+    ConflictingToolTags { opened: String, closed: String },
+    #[cfg(feature = "quick-xml")]
+    #[error("Deserialize error: {0}")]
+    DeserializeError(#[from] quick_xml::DeError),
+}
+
+impl ToolParseError {
+    /// エラー箇所を含む入力の行を、キャレット付きで整形して返す
+    ///
+    /// 位置情報を持たないエラーの場合はエラーメッセージのみを返す。
+    /// `accept_generic_close` を有効にした場合、位置は `</>` を補完した後のテキスト上の位置になる。
+    pub fn context(&self, input: &str) -> String {
+        let (position, len) = match self {
+            ToolParseError::MismatchedEndTag {
+                found, position, ..
+            } => {
+                // 終了タグの `>` までを示す。見つからない場合はタグ名から長さを求める
+                let len = input
+                    .get(*position..)
+                    .and_then(|tag| tag.find('>').map(|end| tag[..=end].chars().count()))
+                    .unwrap_or(found.chars().count() + "</>".len());
+                (*position, len)
+            }
+            _ => return self.to_string(),
+        };
+        let Some(before) = input.get(..position) else {
+            return self.to_string();
+        };
+
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = input[position..]
+            .find('\n')
+            .map_or(input.len(), |i| position + i);
+        let line_number = before.matches('\n').count() + 1;
+        let column = input[line_start..position].chars().count();
+        let gutter = " ".repeat(line_number.to_string().len());
+
+        format!(
+            "{message}\n{gutter}--> {line_number}:{column}\n{gutter} |\n{line_number} | {line}\n{gutter} | {padding}{caret}",
+            message = self,
+            column = column + 1,
+            line = &input[line_start..line_end],
+            padding = " ".repeat(column),
+            caret = "^".repeat(len),
+        )
+    }
+}
+
+/// パーサーが使用する quick-xml の `Reader` の設定
+///
+/// 既定値は従来の動作と同じ。終了タグの不一致はパーサー側で検出するため、quick-xml の検査は常に無効にする。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReaderConfig {
+    /// テキストの先頭の空白をトリムするかどうか
+    pub trim_text_start: bool,
+    /// テキストの末尾の空白をトリムするかどうか
+    pub trim_text_end: bool,
+    /// 空要素 `<param/>` を `<param></param>` として扱い、値が空のパラメータにするかどうか
+    pub expand_empty_elements: bool,
+    /// コメント内の `--` を検査するかどうか
+    pub check_comments: bool,
+}
+
+impl Default for ReaderConfig {
+    fn default() -> Self {
+        Self {
+            trim_text_start: true,
+            trim_text_end: true,
+            expand_empty_elements: false,
+            check_comments: false,
+        }
+    }
+}
+
+impl ReaderConfig {
+    /// 設定を quick-xml の `Reader` に適用する
+    #[cfg(feature = "quick-xml")]
+    pub fn apply<R>(&self, reader: &mut Reader<R>) {
+        reader
+            .trim_text(self.trim_text_start)
+            .trim_text_end(self.trim_text_end)
+            .expand_empty_elements(self.expand_empty_elements)
+            .check_comments(self.check_comments)
+            .check_end_names(false);
+    }
+}
+
+/// タグの開始・終了を表す区切り文字
+///
+/// コードを多く含む文脈でXMLとの衝突を避けるため、`«get_weather»` のような独自の区切り文字を使う場合に指定する。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delimiters {
+    /// タグの開始文字（既定は `<`）
+    pub open: char,
+    /// タグの終了文字（既定は `>`）
+    pub close: char,
+    /// 終了タグを表す接頭辞（既定は `/`）
+    pub close_prefix: char,
+}
+
+impl Default for Delimiters {
+    fn default() -> Self {
+        Self {
+            open: '<',
+            close: '>',
+            close_prefix: '/',
+        }
+    }
+}
+
+impl Delimiters {
+    /// 指定した名前の終了タグ
+    pub(crate) fn close_tag(&self, name: &str) -> String {
+        format!("{}{}{}{}", self.open, self.close_prefix, name, self.close)
+    }
+
+    /// 区切り文字を `<`、`/`、`>` に置き換え、元のテキスト中の `<`、`>`、`&` をエスケープする
+    #[cfg(feature = "quick-xml")]
+    fn translate<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if *self == Self::default() {
+            return Cow::Borrowed(text);
+        }
+        let mut xml = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                c if c == self.open => {
+                    xml.push('<');
+                    if chars.next_if_eq(&self.close_prefix).is_some() {
+                        xml.push('/');
+                    }
+                }
+                c if c == self.close => xml.push('>'),
+                '<' => xml.push_str("&lt;"),
+                '>' => xml.push_str("&gt;"),
+                '&' => xml.push_str("&amp;"),
+                c => xml.push(c),
+            }
+        }
+        Cow::Owned(xml)
+    }
+}
+
+// パースされたツール呼び出しを表す構造体
+#[derive(Debug, Deserialize, Clone)]
+pub struct ToolCall {
+    pub tool_name: String,
+    pub parameters: HashMap<String, String>,
+    /// ツールタグの属性（`AttributeMode::Separate` の場合のみ格納される）
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
+    /// パラメータ名ごとの、入力テキスト中の値のバイト範囲（比較の対象外）
+    #[serde(skip)]
+    spans: HashMap<String, Range<usize>>,
+}
+
+// 値の位置はパースした入力によって変わるため、比較には含めない
+impl PartialEq for ToolCall {
+    fn eq(&self, other: &Self) -> bool {
+        self.tool_name == other.tool_name
+            && self.parameters == other.parameters
+            && self.attributes == other.attributes
+    }
+}
+
+impl ToolCall {
+    /// パラメータ名ごとの、入力テキスト中の値のバイト範囲
+    ///
+    /// 範囲はエスケープされたままの値を指す。属性から取得した値は含まない。
+    /// `ToolParser` でパースした場合のみ格納され、それ以外の方法で作成した場合は空になる。
+    /// 既定以外の区切り文字や `accept_generic_close` によってパース前にテキストを書き換えた場合や、
+    /// `parse_with_name` がパラメータ要素をツールタグで囲んでパースした場合は、元の入力上の位置が
+    /// 分からないため空になる。
+    pub fn param_spans(&self) -> &HashMap<String, Range<usize>> {
+        &self.spans
+    }
+
+    /// base64でエンコードされたパラメータの値をデコードして返す
+    ///
+    /// パラメータが存在しない場合や、値が不正なbase64の場合は `None` を返す。
+    /// `ToolParser::decode_base64` を指定してパースした場合、値が正しいことは検証済みとなる。
+    pub fn get_bytes(&self, key: &str) -> Option<Vec<u8>> {
+        base64::decode(self.parameters.get(key)?)
+    }
+
+    /// 2つのツール呼び出しの差分を求める
+    ///
+    /// `self` を期待値、`other` を実際の値として比較するテスト用途を想定している。
+    pub fn diff(&self, other: &ToolCall) -> ToolCallDiff {
+        let mut diff = ToolCallDiff::default();
+        if self.tool_name != other.tool_name {
+            diff.tool_name = Some((self.tool_name.clone(), other.tool_name.clone()));
+        }
+        for (key, value) in &self.parameters {
+            match other.parameters.get(key) {
+                None => {
+                    diff.only_in_self.insert(key.clone(), value.clone());
+                }
+                Some(other_value) if other_value != value => {
+                    diff.changed
+                        .insert(key.clone(), (value.clone(), other_value.clone()));
+                }
+                Some(_) => {}
+            }
+        }
+        for (key, value) in &other.parameters {
+            if !self.parameters.contains_key(key) {
+                diff.only_in_other.insert(key.clone(), value.clone());
+            }
+        }
+        diff
+    }
+
+    /// ツール名とパラメータから計算する、実行環境に依存しないハッシュ値
+    ///
+    /// ツール実行結果のキャッシュのキーとして使用する。パラメータはキー順に計算するため、
+    /// 挿入順序に依存しない。属性は含めない。アルゴリズムは 64ビットの FNV-1a。
+    pub fn canonical_hash(&self) -> u64 {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let mut parameters: Vec<_> = self.parameters.iter().collect();
+        parameters.sort();
+        // 区切りが曖昧にならないよう、各文字列の前にバイト長を入れる
+        let fields = std::iter::once(self.tool_name.as_str()).chain(
+            parameters
+                .into_iter()
+                .flat_map(|(key, value)| [key.as_str(), value.as_str()]),
+        );
+        let mut hash = OFFSET_BASIS;
+        for field in fields {
+            for byte in (field.len() as u64)
+                .to_le_bytes()
+                .iter()
+                .chain(field.as_bytes())
+            {
+                hash ^= u64::from(*byte);
+                hash = hash.wrapping_mul(PRIME);
+            }
+        }
+        hash
+    }
+
+    /// パラメータをURLのクエリ文字列（`k=v&k2=v2`）に変換する
+    ///
+    /// HTTPのGETリクエストに対応するツール向け。キー順に出力し、キーと値は
+    /// RFC 3986 の非予約文字以外をパーセントエンコードする（空白は `%20`）。
+    pub fn to_query_string(&self) -> String {
+        let mut parameters: Vec<_> = self.parameters.iter().collect();
+        parameters.sort();
+        parameters
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    /// `defaults` のうち、まだ存在しないパラメータだけを追加する
+    ///
+    /// 既存のパラメータの値は上書きしない。
+    pub fn merge_defaults(&mut self, defaults: &HashMap<String, String>) {
+        for (key, value) in defaults {
+            self.parameters
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+    }
+
+    /// ツール呼び出しをXML形式の文字列に変換する
+    ///
+    /// 属性とパラメータはキー順に出力し、値は `parse_tool_call` で元に戻るようにエスケープする。
+    pub fn to_xml(&self) -> String {
+        let mut parameters: Vec<_> = self.parameters.iter().collect();
+        parameters.sort();
+        let mut attributes: Vec<_> = self.attributes.iter().collect();
+        attributes.sort();
+
+        let mut xml = format!("<{}", self.tool_name);
+        for (key, value) in attributes {
+            xml.push_str(&format!(" {key}=\"{}\"", escape_attr_value(value)));
+        }
+        xml.push_str(">\n");
+        for (key, value) in parameters {
+            xml.push_str(&format!("<{key}>{}</{key}>\n", escape_xml(value, false)));
+        }
+        xml.push_str(&format!("</{}>", self.tool_name));
+        xml
+    }
+}
+
+/// `ToolCall::diff` の結果。キーはソート済みで保持する
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ToolCallDiff {
+    /// ツール名が異なる場合の (self, other) の組
+    pub tool_name: Option<(String, String)>,
+    /// `self` にのみ存在するパラメータ
+    pub only_in_self: BTreeMap<String, String>,
+    /// `other` にのみ存在するパラメータ
+    pub only_in_other: BTreeMap<String, String>,
+    /// 両方に存在し、値が異なるパラメータの (self, other) の組
+    pub changed: BTreeMap<String, (String, String)>,
+}
+
+impl ToolCallDiff {
+    /// 差分がないかどうか
+    pub fn is_empty(&self) -> bool {
+        self.tool_name.is_none()
+            && self.only_in_self.is_empty()
+            && self.only_in_other.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+impl std::fmt::Display for ToolCallDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "(no differences)");
+        }
+        if let Some((expected, actual)) = &self.tool_name {
+            writeln!(f, "tool_name: {:?} != {:?}", expected, actual)?;
+        }
+        for (key, value) in &self.only_in_self {
+            writeln!(f, "- {}: {:?}", key, value)?;
+        }
+        for (key, value) in &self.only_in_other {
+            writeln!(f, "+ {}: {:?}", key, value)?;
+        }
+        for (key, (expected, actual)) in &self.changed {
+            writeln!(f, "~ {}: {:?} -> {:?}", key, expected, actual)?;
+        }
+        Ok(())
+    }
+}
+
+// ツールの実行結果を表す構造体。`<tool_result name="...">...</tool_result>` の形式で送受信する
+#[derive(Debug, PartialEq, Eq, Deserialize, Clone)]
+pub struct ToolResult {
+    pub tool_name: String,
+    pub content: String,
+}
+
+impl ToolResult {
+    /// ツールの実行結果をXML形式の文字列に変換する
+    ///
+    /// 内容は `parse_tool_result` で元に戻るようにエスケープする。
+    pub fn to_xml(&self) -> String {
+        format!(
+            "<tool_result name=\"{}\">\n{}\n</tool_result>",
+            escape_attr_value(&self.tool_name),
+            escape_xml(&self.content, false)
+        )
+    }
+}
+
+/// テキスト中で見つかったツール呼び出しXMLブロックの位置
+struct ToolBlock {
+    tool_name: String,
+    start: usize,
+    end: usize,
+}
+
+/// `<` で始まるタグの終端 `>` の位置を返す。引用符で囲まれた属性値中の `>` は無視する
+///
+/// 地の文のアポストロフィを引用符と誤認しないよう、`=` の直後の引用符のみを属性値の開始とみなす。
+fn find_tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut after_equals = false;
+    for (i, c) in tag.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if q == c => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') if after_equals => quote = Some(c),
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+        if !c.is_whitespace() {
+            after_equals = c == '=';
+        }
+    }
+    None
+}
+
+/// 開始タグの直後から、入れ子の深さを考慮して対応する終了タグを探し、その終端の位置を返す
+///
+/// ツールと同名のパラメータ `<param><param>v</param></param>` でも、ツール自身の終了タグを見つけられる。
+fn find_matching_end_tag(body: &str, name: &str) -> Option<usize> {
+    let open_tag = format!("<{}", name);
+    let end_tag = format!("</{}>", name);
+    let mut depth = 0;
+    let mut position = 0;
+    loop {
+        let end_tag_start = position + body[position..].find(&end_tag)?;
+        // 終了タグまでに現れた同名の開始タグ（空要素を除く）の数だけ深くなる
+        let mut search_from = position;
+        while let Some(i) = body[search_from..end_tag_start].find(&open_tag) {
+            let after_name = search_from + i + open_tag.len();
+            let tag_end = find_tag_end(&body[search_from + i..]).map(|end| search_from + i + end);
+            let is_same_name =
+                body[after_name..].starts_with(|c: char| c == '>' || c.is_whitespace());
+            if is_same_name && tag_end.is_some_and(|end| !body[..end].ends_with('/')) {
+                depth += 1;
+            }
+            search_from = after_name;
+        }
+        position = end_tag_start + end_tag.len();
+        if depth == 0 {
+            return Some(position);
+        }
+        depth -= 1;
+    }
+}
+
+/// 終了タグのないツールの開始タグの直後 `body` が、パラメータらしい子要素の並びと別の名前の終了タグで
+/// 構成されている場合、その終了タグの名前を返す
+///
+/// `<get_weather><location>Tokyo</location></write_to_file>` の場合、`write_to_file` を返す。
+/// 子要素で始まらない地の文中のタグ（`<b>bold</i>` など）は対象外。
+fn conflicting_close<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = body.trim_start();
+    let mut has_children = false;
+    while rest.starts_with('<') {
+        let tag_end = find_tag_end(rest)?;
+        let tag = &rest[1..tag_end];
+        if let Some(closed) = tag.strip_prefix('/') {
+            let closed = closed.trim_end();
+            return (has_children && closed != name).then_some(closed);
+        }
+        let child = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        if !is_default_tool_name(child) {
+            return None;
+        }
+        let after = &rest[tag_end + 1..];
+        let child_end = if tag.ends_with('/') {
+            0
+        } else {
+            find_matching_end_tag(after, child)?
+        };
+        has_children = true;
+        rest = after[child_end..].trim_start();
+    }
+    None
+}
+
+/// 既定のツール名の判定。先頭が文字またはアンダースコアであるものをツール名とみなす
+///
+/// 番号付きリストや数式中の `<123>` などをツール呼び出しと誤認しないようにする。
+pub fn is_default_tool_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+}
+
+/// `is_tool_name` でツール名を判定して、`from` 以降のテキストから最初のツール呼び出しXMLブロックを探す
+///
+/// 対応する終了タグのない開始タグ（地の文の `<br>` など）や終了タグは読み飛ばし、その後ろを探し続ける。
+/// 終了タグのないツールが、子要素のあとで別の要素の終了タグで閉じられている場合は
+/// `ToolParseError::ConflictingToolTags` を返す。
+fn find_tool_block(
+    text: &str,
+    from: usize,
+    is_tool_name: fn(&str) -> bool,
+) -> Result<Option<ToolBlock>, ToolParseError> {
+    // 簡易的なXMLブロック抽出（より堅牢な方法も検討可）
+    // < で始まり > で終わるタグを探し、そのタグ名で囲まれたブロックを探す
+    let mut search_from = from;
+    while let Some(offset) = text[search_from..].find('<') {
+        let start_tag_start = search_from + offset;
+        let Some(start_tag_end) = find_tag_end(&text[start_tag_start..]) else {
+            search_from = start_tag_start + 1;
+            continue;
+        };
+        let tag_content = &text[start_tag_start + 1..start_tag_start + start_tag_end];
+        // 属性を含む場合は空白までをタグ名とする
+        let potential_tool_name = tag_content
+            .split(char::is_whitespace)
+            .next()
+            .unwrap_or_default();
+        // 簡単のため、パラメータを持たないタグやコメントなどは無視
+        if !potential_tool_name.starts_with('/')
+            && !potential_tool_name.starts_with('?')
+            && !potential_tool_name.starts_with('!')
+            && is_tool_name(potential_tool_name)
+        {
+            let body_start = start_tag_start + start_tag_end + 1;
+            if let Some(end) = find_matching_end_tag(&text[body_start..], potential_tool_name) {
+                return Ok(Some(ToolBlock {
+                    tool_name: potential_tool_name.to_string(),
+                    start: start_tag_start,
+                    end: body_start + end,
+                }));
+            }
+            if let Some(closed) = conflicting_close(&text[body_start..], potential_tool_name) {
+                return Err(ToolParseError::ConflictingToolTags {
+                    opened: potential_tool_name.to_string(),
+                    closed: closed.to_string(),
+                });
+            }
+        }
+        search_from = start_tag_start + 1;
+    }
+    Ok(None)
+}
+
+/// パラメータの取り出しやアンエスケープを行わずに、開始タグと終了タグの対応だけを確認する関数
+///
+/// すべての開始タグが入れ子の順に同名の終了タグで閉じられ、要素を1つ以上含む場合に `true` を返す。
+/// 巨大な応答に対して、完全なパースを試みるかどうかを安価に判定するために使用する。
+/// 開いている要素があるときの対応しない終了タグ（値中の `</div>` など）は無視する。
+pub fn quick_balance_check(text: &str) -> bool {
+    let mut open: Vec<&str> = Vec::new();
+    let mut has_element = false;
+    let mut position = 0;
+    while let Some(offset) = text[position..].find('<') {
+        let tag_start = position + offset;
+        // `a < b` のような地の文の `<` は、タグの終わりを探さずに読み飛ばす
+        if !text[tag_start + 1..]
+            .starts_with(|c: char| c.is_alphabetic() || matches!(c, '_' | ':' | '/' | '!' | '?'))
+        {
+            position = tag_start + 1;
+            continue;
+        }
+        let Some(tag_end) = find_tag_end(&text[tag_start..]).map(|end| tag_start + end) else {
+            return false;
+        };
+        let tag = &text[tag_start + 1..tag_end];
+        position = tag_end + 1;
+        if tag.starts_with("!--") && !tag.ends_with("--") {
+            let Some(end) = text[tag_start..].find("-->") else {
+                return false;
+            };
+            position = tag_start + end + "-->".len();
+        } else if tag.starts_with(['!', '?']) || tag.ends_with('/') {
+            has_element |= !tag.starts_with(['!', '?']);
+        } else if let Some(name) = tag.strip_prefix('/') {
+            if open.last() == Some(&name) {
+                open.pop();
+            } else if open.is_empty() || open.contains(&name) {
+                // 内側の要素が閉じられないまま外側の要素が閉じられている
+                return false;
+            }
+        } else {
+            let name = tag.split(char::is_whitespace).next().unwrap_or_default();
+            // `a < b` のような地の文の `<` はタグとみなさない
+            if is_default_tool_name(name) {
+                open.push(name);
+                has_element = true;
+            }
+        }
+    }
+    has_element && open.is_empty()
+}
+
+/// LLMの応答テキストから最初のツール呼び出しXMLを抽出しパースする関数
+#[cfg(feature = "quick-xml")]
+pub fn parse_tool_call(text: &str) -> Result<ToolCall, ToolParseError> {
+    ToolParser::new().parse(text)
+}
+
+/// テキストから最初の `<tool_result>` ブロックを抽出しパースする関数
+///
+/// 内容の前後の空白は取り除く。不正な実体参照を含む場合は、内容をエスケープされたまま返す。
+#[cfg(feature = "quick-xml")]
+pub fn parse_tool_result(text: &str) -> Result<ToolResult, ToolParseError> {
+    const TAG: &str = "tool_result";
+    let start = text
+        .match_indices("<tool_result")
+        .map(|(i, _)| i)
+        .find(|&i| text[i + 1 + TAG.len()..].starts_with(|c: char| c == '>' || c.is_whitespace()))
+        .ok_or(ToolParseError::NoToolXmlFound)?;
+    let body_start = start + find_tag_end(&text[start..]).ok_or(ToolParseError::UnexpectedEof)? + 1;
+    let body_len = find_matching_end_tag(&text[body_start..], TAG)
+        .ok_or(ToolParseError::UnexpectedEof)?
+        - "</tool_result>".len();
+
+    let mut reader = Reader::from_str(&text[start..body_start]);
+    let Event::Start(e) = reader.read_event()? else {
+        return Err(ToolParseError::InvalidStructure);
+    };
+    let mut tool_name = None;
+    for attr in e.attributes() {
+        let attr = attr.map_err(quick_xml::Error::from)?;
+        if attr.key.as_ref() == b"name" {
+            tool_name = Some(attr.unescape_value()?.to_string());
+        }
+    }
+
+    let raw = text[body_start..body_start + body_len].trim();
+    let content =
+        quick_xml::escape::unescape(raw).map_or_else(|_| raw.to_string(), Cow::into_owned);
+    Ok(ToolResult {
+        tool_name: tool_name.ok_or(ToolParseError::ToolNameNotFound)?,
+        content,
+    })
+}
+
+/// ツール名を指定して、ツールタグのないパラメータ要素だけの出力もパースする関数
+#[cfg(feature = "quick-xml")]
+pub fn parse_tool_call_with_name(text: &str, tool_name: &str) -> Result<ToolCall, ToolParseError> {
+    ToolParser::new().parse_with_name(text, tool_name)
+}
+
+/// LLMの応答テキストから最初のツール呼び出しXMLを抽出し、パラメータを `T` にデシリアライズする関数
+#[cfg(feature = "quick-xml")]
+pub fn deserialize_params<T: DeserializeOwned>(text: &str) -> Result<T, ToolParseError> {
+    ToolParser::new().deserialize_params(text)
+}
+
+/// LLMの応答テキストから最後のツール呼び出しXMLを抽出しパースする関数
+#[cfg(feature = "quick-xml")]
+pub fn parse_last_tool_call(text: &str) -> Result<ToolCall, ToolParseError> {
+    ToolParser::new().parse_last(text)
+}
+
+/// LLMの応答テキストから最初のツール呼び出しをパースし、各フェーズの所要時間も返す関数
+#[cfg(feature = "quick-xml")]
+pub fn parse_timed(text: &str) -> Result<(ToolCall, ParseTiming), ToolParseError> {
+    ToolParser::new().parse_timed(text)
+}
+
+/// JSON中の文字列フィールドに埋め込まれた応答テキストから、最初のツール呼び出しをパースする関数
+///
+/// `pointer` は `/message/content` のような JSON Pointer で、文字列の値を指している必要がある。
+#[cfg(feature = "quick-xml")]
+pub fn parse_tool_call_from_json(json: &str, pointer: &str) -> Result<ToolCall, ToolParseError> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+    let text = value
+        .pointer(pointer)
+        .and_then(serde_json::Value::as_str)
+        .ok_or_else(|| ToolParseError::JsonPointerNotFound(pointer.to_string()))?;
+    parse_tool_call(text)
+}
+
+/// LLMの応答テキストに含まれるすべてのツール呼び出しXMLを順番にパースする関数
+#[cfg(feature = "quick-xml")]
+pub fn parse_all_tool_calls(text: &str) -> Result<Vec<ToolCall>, ToolParseError> {
+    ToolParser::new()
+        .parse_all(text)
+        .map(|parsed| parsed.tool_calls)
+}
+
+/// パラメータの値として出力できるように文字列をエスケープする
+///
+/// `&`、`<`、`>` を実体参照に、タブと改行以外の制御文字を文字参照に置き換える。
+/// `ToolCall::to_xml` と同じ規則で、`parse_tool_call` で元の文字列に戻る。
+pub fn escape_param_value(value: &str) -> String {
+    escape_xml(value, false).into_owned()
+}
+
+/// 属性値として出力できるように文字列をエスケープする
+///
+/// `escape_param_value` の規則に加えて、引用符とタブ・改行も置き換える。
+pub fn escape_attr_value(value: &str) -> String {
+    escape_xml(value, true).into_owned()
+}
+
+/// 値全体を囲む ```` ``` ```` または `"""` のフェンスの内側を返す。囲まれていない場合は `None`
+///
+/// 開始フェンスと同じ行の言語名（```` ```rust ```` など）と、終了フェンスの直前の改行も取り除く。
+pub(crate) fn strip_code_fence(value: &str) -> Option<&str> {
+    let value = value.trim();
+    let fence = ["```", "\"\"\""]
+        .into_iter()
+        .find(|fence| value.starts_with(fence))?;
+    let inner = value.strip_prefix(fence)?.strip_suffix(fence)?;
+    let (info, body) = inner.split_once('\n')?;
+    if info.contains(fence) {
+        return None;
+    }
+    Some(
+        body.strip_suffix('\n')
+            .unwrap_or(body)
+            .trim_end_matches('\r'),
+    )
+}
+
+/// RFC 3986 の非予約文字以外をUTF-8のバイト単位でパーセントエンコードする
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+/// テキストノードまたは属性値として出力できるようにエスケープする
+fn escape_xml(value: &str, attr: bool) -> Cow<'_, str> {
+    let needs_escape = |c: char| {
+        matches!(c, '&' | '<' | '>')
+            || (attr && matches!(c, '"' | '\''))
+            || (c.is_control() && (attr || !matches!(c, '\t' | '\n' | '\r')))
+    };
+    if !value.contains(needs_escape) {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if attr => escaped.push_str("&quot;"),
+            '\'' if attr => escaped.push_str("&apos;"),
+            c if needs_escape(c) => escaped.push_str(&format!("&#x{:X};", u32::from(c))),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// 指定したタグとその内容を、改行を残して同じバイト数の空白に置き換える
+///
+/// 属性付きの `<tag attr="x">` や `<tag >` も対象とし、自己終了タグ `<tag/>` はタグ自体のみを置き換える。
+/// 終了タグがない場合は、開始タグからテキストの末尾までを置き換える。
+#[cfg(feature = "quick-xml")]
+fn blank_out_tags<'a>(text: &'a str, tags: &[String]) -> Cow<'a, str> {
+    let mut ranges = Vec::new();
+    for tag in tags {
+        let mut from = 0;
+        while let Some(start) = find_tag_named(text, from, &format!("<{}", tag)) {
+            let Some(tag_end) = find_tag_end(&text[start..]).map(|end| start + end + 1) else {
+                ranges.push(start..text.len());
+                break;
+            };
+            let end = if text[..tag_end].ends_with("/>") {
+                tag_end
+            } else {
+                find_tag_named(text, tag_end, &format!("</{}", tag))
+                    .and_then(|close| text[close..].find('>').map(|end| close + end + 1))
+                    .unwrap_or(text.len())
+            };
+            ranges.push(start..end);
+            from = end;
+        }
+    }
+    if ranges.is_empty() {
+        return Cow::Borrowed(text);
+    }
+
+    let mut blanked = text.to_string().into_bytes();
+    for range in ranges {
+        for byte in &mut blanked[range] {
+            if *byte != b'\n' {
+                *byte = b' ';
+            }
+        }
+    }
+    // ASCIIの空白と改行のみで置き換えているため、UTF-8として有効なまま
+    Cow::Owned(String::from_utf8(blanked).unwrap_or_default())
+}
+
+/// `from` 以降で、`<tag` や `</tag` の直後にタグ名の終わり（空白、`>`、`/>`）が続く位置を探す
+#[cfg(feature = "quick-xml")]
+fn find_tag_named(text: &str, from: usize, prefix: &str) -> Option<usize> {
+    text[from..]
+        .match_indices(prefix)
+        .map(|(i, _)| from + i)
+        .find(|&i| {
+            let rest = &text[i + prefix.len()..];
+            rest.starts_with(|c: char| c == '>' || c.is_whitespace()) || rest.starts_with("/>")
+        })
+}
+
+/// `</>` を、その時点で開いている直近のタグの終了タグに置き換える
+#[cfg(feature = "quick-xml")]
+fn resolve_generic_close_tags(text: &str) -> Cow<'_, str> {
+    if !text.contains("</>") {
+        return Cow::Borrowed(text);
+    }
+
+    let mut resolved = String::with_capacity(text.len());
+    let mut open_tags: Vec<&str> = Vec::new();
+    let mut rest = text;
+    while let Some(tag_start) = rest.find('<') {
+        let Some(tag_len) = rest[tag_start..].find('>') else {
+            break;
+        };
+        let tag = &rest[tag_start + 1..tag_start + tag_len];
+        resolved.push_str(&rest[..tag_start]);
+        match tag.strip_prefix('/') {
+            Some("") => match open_tags.pop() {
+                Some(name) => {
+                    resolved.push_str("</");
+                    resolved.push_str(name);
+                    resolved.push('>');
+                }
+                None => resolved.push_str("</>"),
+            },
+            Some(name) => {
+                // 対応する開始タグまでスタックを巻き戻す
+                if let Some(index) = open_tags.iter().rposition(|open| *open == name) {
+                    open_tags.truncate(index);
+                }
+                resolved.push_str(&rest[tag_start..=tag_start + tag_len]);
+            }
+            None => {
+                if !tag.starts_with(['!', '?']) && !tag.ends_with('/') {
+                    open_tags.push(tag.split(char::is_whitespace).next().unwrap_or_default());
+                }
+                resolved.push_str(&rest[tag_start..=tag_start + tag_len]);
+            }
+        }
+        rest = &rest[tag_start + tag_len + 1..];
+    }
+    resolved.push_str(rest);
+    Cow::Owned(resolved)
+}
+
+/// `parse_timed` で計測した各フェーズの所要時間
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseTiming {
+    /// 前処理とツール呼び出しXMLブロックの探索にかかった時間
+    pub scan: Duration,
+    /// quick-xml による抽出済みブロックのパースにかかった時間
+    pub parse: Duration,
+}
+
+impl ParseTiming {
+    /// 全体の所要時間
+    pub fn total(&self) -> Duration {
+        self.scan + self.parse
+    }
+}
+
+// ブロックのパース結果。スキーマなどの検証の前の段階
+#[cfg(feature = "quick-xml")]
+struct BlockParts<K> {
+    tool_name: String,
+    parameters: HashMap<K, String>,
+    attributes: HashMap<K, String>,
+    spans: HashMap<K, Range<usize>>,
+}
+
+/// 複数のツール呼び出しのパースを打ち切った理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// 入力の終端まで読み切った
+    EndOfInput,
+    /// `max_tool_calls` の上限に達し、残りのツール呼び出しを読み飛ばした
+    LimitReached,
+}
+
+/// `ToolParser::parse_all` の結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedToolCalls {
+    pub tool_calls: Vec<ToolCall>,
+    pub stop_reason: StopReason,
+}
+
+impl ParsedToolCalls {
+    /// 上限に達して途中で打ち切られたかどうか
+    pub fn is_truncated(&self) -> bool {
+        self.stop_reason == StopReason::LimitReached
+    }
+}
+
+/// ツールタグの属性の扱い
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AttributeMode {
+    /// 属性を無視する。属性経由でパラメータを注入されるのを防ぎたい場合に使用する
+    #[default]
+    Ignore,
+    /// 属性をパラメータとして扱う。同名の子要素がある場合は子要素の値を優先する
+    MergeAsParams,
+    /// 属性を `ToolCall::attributes` に格納し、パラメータとは区別する
+    Separate,
+}
+
+/// パラメータの値の空白の扱い
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WhitespaceMode {
+    /// 前後の空白を取り除く
+    #[default]
+    Trim,
+    /// 前後の空白を取り除き、内部の連続する空白（改行を含む）を1つの半角スペースにまとめる
+    Collapse,
+    /// 前後の空白も含めてそのまま保持する。空白のみの値も捨てない
+    Preserve,
+}
+
+impl WhitespaceMode {
+    /// 値に空白の扱いを適用する
+    pub(crate) fn apply(self, value: &str) -> Cow<'_, str> {
+        match self {
+            WhitespaceMode::Trim => Cow::Borrowed(value.trim()),
+            WhitespaceMode::Collapse => {
+                Cow::Owned(value.split_whitespace().collect::<Vec<_>>().join(" "))
+            }
+            WhitespaceMode::Preserve => Cow::Borrowed(value),
+        }
+    }
+}
+
+/// 属性でキーと値を指定する汎用パラメータ要素（`<param name="location" value="Tokyo"/>` など）
+#[derive(Debug, Clone)]
+#[cfg(feature = "quick-xml")]
+struct GenericParam {
+    /// 要素名
+    element: String,
+    /// パラメータ名を持つ属性名
+    key_attr: String,
+    /// 値を持つ属性名。この属性がない場合は要素のテキストを値とする
+    value_attr: String,
+}
+
+/// オプションを指定してツール呼び出しをパースするためのビルダー
+#[derive(Debug, Clone, Default)]
+#[cfg(feature = "quick-xml")]
+pub struct ToolParser {
+    /// 一度にパースするツール呼び出しの最大数
+    max_tool_calls: Option<usize>,
+    /// XMLのツール呼び出しが見つからない場合にYAML風ブロックを解釈するかどうか
+    yaml_fallback: bool,
+    /// ツールタグの属性の扱い
+    attribute_mode: AttributeMode,
+    /// `</>` を直近の開始タグの終了タグとして扱うかどうか
+    accept_generic_close: bool,
+    /// 取得対象のパラメータ名（`None` の場合はすべて取得する）
+    only_params: Option<HashSet<String>>,
+    /// 子要素を持たないツールのテキストを格納するパラメータ名
+    single_text_param: Option<String>,
+    /// ツール呼び出しの探索対象から除外するタグ名
+    exclude_tags: Vec<String>,
+    /// quick-xml の `Reader` の設定
+    reader_config: ReaderConfig,
+    /// タグの区切り文字
+    delimiters: Delimiters,
+    /// ツール名ごとのパラメータの制約
+    schemas: HashMap<String, schema::ToolSchema>,
+    /// 汎用パラメータ要素の形式
+    generic_param: Option<GenericParam>,
+    /// 値をbase64としてデコードするパラメータ名
+    base64_params: HashSet<String>,
+    /// 区切り文字を `/` に正規化するパス形式のパラメータ名
+    path_params: HashSet<String>,
+    /// ツール名とみなすタグ名の判定（`None` の場合は `is_default_tool_name`）
+    tool_name_filter: Option<fn(&str) -> bool>,
+    /// パラメータの値の空白の扱い
+    whitespace_mode: WhitespaceMode,
+    /// ツール呼び出しの直前に必要な目印
+    marker: Option<String>,
+    /// 値全体を囲むコードフェンスを取り除くかどうか
+    strip_content_fence: bool,
+}
+
+#[cfg(feature = "quick-xml")]
+impl ToolParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// パースするツール呼び出しの最大数を設定する
+    ///
+    /// 小さなツールブロックを大量に含む入力に対して処理量を制限するために使用する。
+    pub fn max_tool_calls(mut self, max: usize) -> Self {
+        self.max_tool_calls = Some(max);
+        self
+    }
+
+    /// XMLのツール呼び出しが見つからない場合に、YAML風のブロックを解釈する
+    ///
+    /// XMLを避けるよう指示されたモデルが出力する、次のような形式を対象とする。
+    /// XMLのツール呼び出しが1つでも見つかった場合は使用されない。
+    /// `parse_all` では、テキスト中のすべてのブロックを順番に解釈する。
+    ///
+    /// ```text
+    /// tool: get_weather
+    /// params:
+    ///   location: Tokyo
+    ///   date: tomorrow
+    /// ```
+    pub fn yaml_fallback(mut self, enabled: bool) -> Self {
+        self.yaml_fallback = enabled;
+        self
+    }
+
+    /// ツールタグの属性の扱いを設定する（デフォルトは `AttributeMode::Ignore`）
+    pub fn attribute_mode(mut self, mode: AttributeMode) -> Self {
+        self.attribute_mode = mode;
+        self
+    }
+
+    /// `</>` を、直近に開いているタグ（パラメータまたはツール）の終了タグとして扱う
+    ///
+    /// 終了タグにタグ名を繰り返さない生成器向けの寛容なオプション。
+    /// `</>` は常に直近の開始タグと対応付けられるため、その部分では終了タグの不一致を検出できなくなる。
+    pub fn accept_generic_close(mut self, enabled: bool) -> Self {
+        self.accept_generic_close = enabled;
+        self
+    }
+
+    /// 指定したパラメータのみを取得し、それ以外は値を保持せずに読み飛ばす
+    ///
+    /// 大きなパラメータを含むツールで、一部のパラメータだけが必要な場合のメモリ使用量を抑える。
+    pub fn only_params(mut self, names: &[&str]) -> Self {
+        self.only_params = Some(names.iter().map(|name| name.to_string()).collect());
+        self
+    }
+
+    /// 子要素を持たないツールの直下のテキストを、指定した名前のパラメータとして扱う
+    ///
+    /// `<search>rust async</search>` のように、名前のない引数を1つだけ取るツール向け。
+    pub fn single_text_param(mut self, name: Option<&str>) -> Self {
+        self.single_text_param = name.map(str::to_string);
+        self
+    }
+
+    /// 指定したタグ（`<thinking>` など）の内容をツール呼び出しの探索対象から除外する
+    ///
+    /// 推論ブロック内に例として書かれたツール呼び出しを実行しないために使用する。
+    /// 除外した範囲は空白に置き換えるため、エラー位置は元の入力上の位置のままになる。
+    pub fn exclude_tags(mut self, tags: &[&str]) -> Self {
+        self.exclude_tags = tags.iter().map(|tag| tag.to_string()).collect();
+        self
+    }
+
+    /// quick-xml の `Reader` の設定を変更する
+    pub fn reader_config(mut self, config: ReaderConfig) -> Self {
+        self.reader_config = config;
+        self
+    }
+
+    /// タグの区切り文字を変更する
+    ///
+    /// 既定以外の区切り文字を使う場合、入力中の `<`、`>`、`&` は通常の文字として扱われ、実体参照は展開しない。
+    /// エラー位置は区切り文字を置き換えた後のテキスト上の位置になる。
+    pub fn delimiters(mut self, delimiters: Delimiters) -> Self {
+        self.delimiters = delimiters;
+        self
+    }
+
+    /// ツールのパラメータの制約を登録する。同じツールに対して登録した場合は上書きする
+    ///
+    /// パース結果が制約を満たさない場合は `ToolParseError::InvalidEnumValue` などのエラーを返す。
+    pub fn schema(mut self, schema: schema::ToolSchema) -> Self {
+        self.schemas.insert(schema.tool_name().to_string(), schema);
+        self
+    }
+
+    /// ツールのパラメータの個数を固定する。`ToolSchema::param_count` の簡易版
+    ///
+    /// 個数が異なる場合は `TooFewParams` または `TooManyParams` を返す。
+    pub fn expect_param_count(mut self, tool_name: &str, count: usize) -> Self {
+        let schema = self
+            .schemas
+            .remove(tool_name)
+            .unwrap_or_else(|| schema::ToolSchema::new(tool_name));
+        self.schema(schema.param_count(count))
+    }
+
+    /// `<param name="location" value="Tokyo"/>` のような汎用パラメータ要素を認識する
+    ///
+    /// `key_attr` 属性の値をパラメータ名、`value_attr` 属性の値を値とする。
+    /// `value_attr` 属性がない場合は `<param name="query">rust</param>` のように要素のテキストを値とする。
+    pub fn generic_param(mut self, element: &str, key_attr: &str, value_attr: &str) -> Self {
+        self.generic_param = Some(GenericParam {
+            element: element.to_string(),
+            key_attr: key_attr.to_string(),
+            value_attr: value_attr.to_string(),
+        });
+        self
+    }
+
+    /// パラメータの値の空白の扱いを設定する（デフォルトは `WhitespaceMode::Trim`）
+    ///
+    /// `Trim` の場合のトリムは `ReaderConfig` の設定に従う。`Preserve` の場合は `ReaderConfig` に関わらずトリムしない。
+    pub fn whitespace_mode(mut self, mode: WhitespaceMode) -> Self {
+        self.whitespace_mode = mode;
+        self
+    }
+
+    /// 値全体が ```` ``` ```` や `"""` のフェンスで囲まれている場合に、フェンスを取り除く
+    ///
+    /// 値の途中にあるフェンスはそのまま残す。
+    pub fn strip_content_fence(mut self, enabled: bool) -> Self {
+        self.strip_content_fence = enabled;
+        self
+    }
+
+    /// タグを含むパラメータの値を、元のテキストから取り出して正規化する
+    fn raw_param_value(&self, raw: &str) -> Result<String, ToolParseError> {
+        let reader_config = self.effective_reader_config();
+        let mut raw = raw;
+        if reader_config.trim_text_start {
+            raw = raw.trim_start();
+        }
+        if reader_config.trim_text_end {
+            raw = raw.trim_end();
+        }
+        let value = quick_xml::escape::unescape(raw).map_err(quick_xml::Error::from)?;
+        Ok(self.normalize_value(value))
+    }
+
+    /// 取得したパラメータの値に空白の扱いとフェンスの除去を適用する
+    fn normalize_value(&self, mut value: Cow<'_, str>) -> String {
+        if self.strip_content_fence
+            && let Some(inner) = strip_code_fence(&value)
+        {
+            value = Cow::Owned(inner.to_string());
+        }
+        match self.whitespace_mode {
+            WhitespaceMode::Trim | WhitespaceMode::Preserve => value.into_owned(),
+            WhitespaceMode::Collapse => WhitespaceMode::Collapse.apply(&value).into_owned(),
+        }
+    }
+
+    /// 空白の扱いを反映した `Reader` の設定
+    fn effective_reader_config(&self) -> ReaderConfig {
+        match self.whitespace_mode {
+            WhitespaceMode::Preserve => ReaderConfig {
+                trim_text_start: false,
+                trim_text_end: false,
+                ..self.reader_config
+            },
+            _ => self.reader_config,
+        }
+    }
+
+    /// ツール名とみなすタグ名の判定を変更する
+    ///
+    /// 既定では `is_default_tool_name` を使用し、`<123>` のように数字で始まるタグは無視する。
+    /// 終了タグや `<!`、`<?` で始まるタグは、この設定に関わらずツールとみなさない。
+    pub fn tool_name_filter(mut self, filter: fn(&str) -> bool) -> Self {
+        self.tool_name_filter = Some(filter);
+        self
+    }
+
+    /// 目印（`TOOL_CALL:` など）の直後にあるツール呼び出しのみを対象にする
+    ///
+    /// 目印とツールタグの間には空白（改行を含む）のみを許す。目印のないXML風のテキストは無視する。
+    pub fn require_marker(mut self, marker: &str) -> Self {
+        self.marker = Some(marker.to_string());
+        self
+    }
+
+    /// `from` 以降のテキストから、設定に従って最初のツール呼び出しXMLブロックを探す
+    fn find_block(&self, text: &str, from: usize) -> Result<Option<ToolBlock>, ToolParseError> {
+        let is_tool_name = self.tool_name_filter.unwrap_or(is_default_tool_name);
+        let Some(marker) = &self.marker else {
+            return find_tool_block(text, from, is_tool_name);
+        };
+        let mut search_from = from;
+        while let Some(offset) = text[search_from..].find(marker.as_str()) {
+            let after_marker = search_from + offset + marker.len();
+            let Some(block) = find_tool_block(text, after_marker, is_tool_name)? else {
+                return Ok(None);
+            };
+            if text[after_marker..block.start].trim().is_empty() {
+                return Ok(Some(block));
+            }
+            search_from = after_marker;
+        }
+        Ok(None)
+    }
+
+    /// 指定したパラメータの値をbase64として検証する
+    ///
+    /// デコードできない場合は `ToolParseError::InvalidBase64` を返す。
+    /// デコードしたバイト列は `ToolCall::get_bytes` で取得する。
+    pub fn decode_base64(mut self, key: &str) -> Self {
+        self.base64_params.insert(key.to_string());
+        self
+    }
+
+    /// 指定したパラメータの値をパスとして扱い、`\` の区切り文字を `/` に正規化する
+    ///
+    /// `write_to_file` の `path` のように、Windows形式で出力されたパスを扱うために使用する。
+    pub fn normalize_path(mut self, key: &str) -> Self {
+        self.path_params.insert(key.to_string());
+        self
+    }
+
+    /// 汎用パラメータ要素であれば、属性からパラメータ名と値（値属性がある場合）を取り出す
+    fn generic_param_entry(
+        &self,
+        e: &BytesStart,
+    ) -> Result<Option<(String, Option<String>)>, ToolParseError> {
+        let Some(spec) = self
+            .generic_param
+            .as_ref()
+            .filter(|spec| e.name().as_ref() == spec.element.as_bytes())
+        else {
+            return Ok(None);
+        };
+        let mut key = None;
+        let mut value = None;
+        for attr in e.attributes() {
+            let attr = attr.map_err(quick_xml::Error::from)?;
+            if attr.key.as_ref() == spec.key_attr.as_bytes() {
+                key = Some(attr.unescape_value()?.to_string());
+            } else if attr.key.as_ref() == spec.value_attr.as_bytes() {
+                value = Some(attr.unescape_value()?.to_string());
+            }
+        }
+        // パラメータ名の属性がない要素は構造として不正
+        let key = key.ok_or(ToolParseError::InvalidStructure)?;
+        Ok(Some((key, value)))
+    }
+
+    /// パラメータを取得対象とするかどうか
+    fn captures_param(&self, name: &str) -> bool {
+        self.only_params
+            .as_ref()
+            .is_none_or(|names| names.contains(name))
+    }
+
+    /// オプションに応じて、パース前に入力テキストを正規化する
+    ///
+    /// 2つ目の値は、正規化後のテキスト上の位置が元の入力の位置と一致するかどうか。
+    /// 除外タグは同じバイト数の空白に置き換えるため、位置は変わらない。
+    fn preprocess<'a>(&self, text: &'a str) -> (Cow<'a, str>, bool) {
+        let (text, mut offsets_kept) = match self.delimiters.translate(text) {
+            Cow::Borrowed(text) => (blank_out_tags(text, &self.exclude_tags), true),
+            Cow::Owned(xml) => (
+                Cow::Owned(blank_out_tags(&xml, &self.exclude_tags).into_owned()),
+                false,
+            ),
+        };
+        if !self.accept_generic_close {
+            return (text, offsets_kept);
+        }
+        let text = match resolve_generic_close_tags(&text) {
+            Cow::Borrowed(_) => text,
+            Cow::Owned(resolved) => {
+                offsets_kept = false;
+                Cow::Owned(resolved)
+            }
+        };
+        (text, offsets_kept)
+    }
+
+    /// テキストから最初のツール呼び出しをパースする
+    pub fn parse(&self, text: &str) -> Result<ToolCall, ToolParseError> {
+        self.parse_timed(text).map(|(tool_call, _)| tool_call)
+    }
+
+    /// `parse` と同様にパースし、ツール名とパラメータ名を `interner` で共有した形で返す
+    ///
+    /// パラメータ名はパース中に直接インターンするため、名前ごとの `String` は作られない。
+    pub fn parse_interned(
+        &self,
+        text: &str,
+        interner: &mut intern::KeyInterner,
+    ) -> Result<intern::InternedToolCall, ToolParseError> {
+        let text = &*self.preprocess(text).0;
+        let Some(block) = self.find_block(text, 0)? else {
+            return self
+                .yaml_fallback
+                .then(|| yaml_fallback::parse_yaml_tool_call(text))
+                .flatten()
+                .map(|tool_call| interner.intern_call(tool_call))
+                .ok_or(ToolParseError::NoToolXmlFound);
+        };
+        let BlockParts {
+            tool_name,
+            parameters,
+            attributes,
+            ..
+        } = self.parse_block_parts(text, block, &mut |name| interner.intern(name))?;
+        if let Some(schema) = self.schemas.get(&tool_name) {
+            schema.validate_params(&parameters)?;
+        }
+        self.check_base64(&parameters)?;
+        Ok(intern::InternedToolCall {
+            tool_name: interner.intern(&tool_name),
+            parameters,
+            attributes,
+        })
+    }
+
+    /// `parse` と同様にパースし、探索とパースの各フェーズの所要時間も返す
+    pub fn parse_timed(&self, text: &str) -> Result<(ToolCall, ParseTiming), ToolParseError> {
+        let scan_start = Instant::now();
+        let (text, offsets_kept) = self.preprocess(text);
+        let text = &*text;
+        let block = self.find_block(text, 0)?;
+        let scan = scan_start.elapsed();
+
+        let parse_start = Instant::now();
+        let tool_call = match block {
+            Some(block) => self.parse_block(text, block, offsets_kept),
+            None if self.yaml_fallback => {
+                yaml_fallback::parse_yaml_tool_call(text).ok_or(ToolParseError::NoToolXmlFound)
+            }
+            None => Err(ToolParseError::NoToolXmlFound),
+        }?;
+        let parse = parse_start.elapsed();
+
+        Ok((tool_call, ParseTiming { scan, parse }))
+    }
+
+    /// テキストから最後のツール呼び出しをパースする
+    ///
+    /// それより前のツール呼び出しはブロックの範囲を探すだけで、パースはしない。
+    pub fn parse_last(&self, text: &str) -> Result<ToolCall, ToolParseError> {
+        let (text, offsets_kept) = self.preprocess(text);
+        let text = &*text;
+        let mut last = None;
+        let mut position = 0;
+        while let Some(block) = self.find_block(text, position)? {
+            position = block.end;
+            last = Some(block);
+        }
+        match last {
+            Some(block) => self.parse_block(text, block, offsets_kept),
+            None if self.yaml_fallback => {
+                yaml_fallback::parse_yaml_tool_call(text).ok_or(ToolParseError::NoToolXmlFound)
+            }
+            None => Err(ToolParseError::NoToolXmlFound),
+        }
+    }
+
+    /// ツール名を指定してパースする。ツールタグがなくパラメータ要素だけが出力された場合も、そのツールの呼び出しとみなす
+    ///
+    /// `tool_name` のツールタグがあればそのブロックをパースする。ない場合は、最初の開始タグから
+    /// 最後の終了タグまでを `tool_name` のタグで囲んだものとしてパースする。
+    pub fn parse_with_name(&self, text: &str, tool_name: &str) -> Result<ToolCall, ToolParseError> {
+        let (text, offsets_kept) = self.preprocess(text);
+        let text = &*text;
+        let mut position = 0;
+        while let Some(block) = self.find_block(text, position)? {
+            if block.tool_name == tool_name {
+                return self.parse_block(text, block, offsets_kept);
+            }
+            position = block.end;
+        }
+
+        let start = text
+            .match_indices('<')
+            .map(|(i, _)| i)
+            .find(|&i| text[i + 1..].starts_with(|c: char| c.is_alphabetic() || c == '_'))
+            .ok_or(ToolParseError::NoToolXmlFound)?;
+        let end = text
+            .rfind("</")
+            .and_then(|i| text[i..].find('>').map(|end| i + end + 1))
+            .filter(|&end| end > start)
+            .ok_or(ToolParseError::NoToolXmlFound)?;
+        let wrapped = format!("<{tool_name}>{}</{tool_name}>", &text[start..end]);
+        let block = ToolBlock {
+            tool_name: tool_name.to_string(),
+            start: 0,
+            end: wrapped.len(),
+        };
+        self.parse_block(&wrapped, block, false)
+    }
+
+    /// テキストから最初のツール呼び出しを探し、パラメータを `T` にデシリアライズする
+    ///
+    /// quick-xml の serde 対応を使用するため、繰り返される `<item>` 要素を `Vec<T>` のフィールドに、
+    /// 数値や真偽値のパラメータを対応する型のフィールドに格納できる。要素名とフィールド名が異なる場合は
+    /// `#[serde(rename = "item")]` を指定する。ツール名は無視する。
+    pub fn deserialize_params<T: DeserializeOwned>(&self, text: &str) -> Result<T, ToolParseError> {
+        let text = &*self.preprocess(text).0;
+        let block = self
+            .find_block(text, 0)?
+            .ok_or(ToolParseError::NoToolXmlFound)?;
+        Ok(quick_xml::de::from_str(&text[block.start..block.end])?)
+    }
+
+    /// テキストに含まれるツール呼び出しを順番にパースする
+    pub fn parse_all(&self, text: &str) -> Result<ParsedToolCalls, ToolParseError> {
+        let (text, offsets_kept) = self.preprocess(text);
+        let text = &*text;
+        let mut tool_calls = Vec::new();
+        let mut position = 0;
+
+        while let Some(block) = self.find_block(text, position)? {
+            if self.max_tool_calls == Some(tool_calls.len()) {
+                return Ok(ParsedToolCalls {
+                    tool_calls,
+                    stop_reason: StopReason::LimitReached,
+                });
+            }
+            position = block.end;
+            tool_calls.push(self.parse_block(text, block, offsets_kept)?);
+        }
+
+        if tool_calls.is_empty() && self.yaml_fallback {
+            tool_calls = yaml_fallback::parse_yaml_tool_calls(text);
+            if let Some(max) = self.max_tool_calls
+                && tool_calls.len() > max
+            {
+                tool_calls.truncate(max);
+                return Ok(ParsedToolCalls {
+                    tool_calls,
+                    stop_reason: StopReason::LimitReached,
+                });
+            }
+        }
+
+        Ok(ParsedToolCalls {
+            tool_calls,
+            stop_reason: StopReason::EndOfInput,
+        })
+    }
+
+    /// 抽出済みのツール呼び出しXMLブロックを、パラメータの値の位置とともにパースする
+    ///
+    /// `keep_spans` が `false` の場合、`text` 上の位置は元の入力と一致しないため位置を捨てる。
+    fn parse_block(
+        &self,
+        text: &str,
+        block: ToolBlock,
+        keep_spans: bool,
+    ) -> Result<ToolCall, ToolParseError> {
+        let BlockParts {
+            tool_name,
+            parameters,
+            attributes,
+            mut spans,
+        } = self.parse_block_parts(text, block, &mut |name| name.to_string())?;
+        if !keep_spans {
+            spans.clear();
+        }
+        let tool_call = ToolCall {
+            tool_name,
+            parameters,
+            attributes,
+            spans,
+        };
+        if let Some(schema) = self.schemas.get(&tool_call.tool_name) {
+            schema.validate(&tool_call)?;
+        }
+        self.check_base64(&tool_call.parameters)?;
+        Ok(tool_call)
+    }
+
+    /// `base64_params` のパラメータの値がbase64としてデコードできるか検査する
+    fn check_base64<K>(&self, params: &HashMap<K, String>) -> Result<(), ToolParseError>
+    where
+        K: Borrow<str> + Eq + Hash,
+    {
+        for key in &self.base64_params {
+            if let Some(value) = params.get(key.as_str())
+                && base64::decode(value).is_none()
+            {
+                return Err(ToolParseError::InvalidBase64 { key: key.clone() });
+            }
+        }
+        Ok(())
+    }
+
+    /// 抽出済みのツール呼び出しXMLブロックをパースする
+    ///
+    /// タグ名と属性名は `key` で直接パラメータのキーに変換する。
+    /// `KeyInterner` を使う場合に、キーごとの `String` を作らずに済ませるためのもの。
+    fn parse_block_parts<K>(
+        &self,
+        text: &str,
+        block: ToolBlock,
+        key: &mut impl FnMut(&str) -> K,
+    ) -> Result<BlockParts<K>, ToolParseError>
+    where
+        K: Borrow<str> + Clone + Eq + Hash,
+    {
+        let ToolBlock {
+            tool_name,
+            start,
+            end,
+        } = block;
+        // quick-xml でパース
+        let mut reader = Reader::from_str(&text[start..end]);
+        self.effective_reader_config().apply(&mut reader);
+
+        let mut params = HashMap::new();
+        let mut current_param_name: Option<K> = None;
+        // 汎用パラメータ要素の中にいる場合のパラメータ名
+        let mut generic_key: Option<K> = None;
+        // 現在のパラメータの値の開始位置（ブロック先頭からのバイト位置）
+        let mut value_start: Option<usize> = None;
+        let mut spans = HashMap::new();
+        let mut attributes = HashMap::new();
+        let mut tool_text: Option<String> = None;
+        let mut has_children = false;
+        // 現在のパラメータの値にタグが含まれているかどうか
+        let mut raw_value = false;
+        // 現在のパラメータの値に含まれる、最初の対応しない終了タグの名前と位置
+        let mut foreign_close: Option<(String, usize)> = None;
+        let block_text = &text[start..end];
+
+        // ルート要素の開始タグを読み飛ばす
+        loop {
+            match reader.read_event()? {
+                Event::Start(e) if e.name().as_ref() == tool_name.as_bytes() => {
+                    if self.attribute_mode != AttributeMode::Ignore {
+                        for attr in e.attributes() {
+                            let attr = attr.map_err(quick_xml::Error::from)?;
+                            attributes.insert(
+                                key(&String::from_utf8_lossy(attr.key.as_ref())),
+                                attr.unescape_value()?.to_string(),
+                            );
+                        }
+                    }
+                    break;
+                }
+                Event::Eof => return Err(ToolParseError::ToolNameNotFound), // 予期せぬ終了
+                // 自己終了タグ `<tool />` や、タグ名の解釈の違いで別名になった開始タグなど
+                Event::Start(e) => {
+                    let found = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                    return Err(ToolParseError::ExpectedStartTag(format!("<{}>", found)));
+                }
+                Event::Empty(e) => {
+                    let found = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                    return Err(ToolParseError::ExpectedStartTag(format!("<{}/>", found)));
+                }
+                _ => {} // 他のイベント（コメントなど）は無視
+            }
+        }
+
+        // ブロック内で終端に達した場合に、開いていたツールとパラメータを示すエラー
+        let eof_error = |param_name: &Option<K>| ToolParseError::UnexpectedEofIn {
+            tool_name: tool_name.clone(),
+            param_name: param_name.as_ref().map(|name| name.borrow().to_string()),
+        };
+
+        // パラメータ要素を読み取るループ
+        loop {
+            let event_start = reader.buffer_position();
+            // 属性値やコメント中の終了タグでブロックが途中で切れた場合、quick-xml も終端エラーを返す
+            let event = match reader.read_event() {
+                Err(quick_xml::Error::UnexpectedEof(_)) => {
+                    return Err(eof_error(&current_param_name));
+                }
+                event => event?,
+            };
+            match event {
+                // パラメータの値の中のタグ。対応する終了タグまでの値の一部としてそのまま保持する
+                Event::Start(_) | Event::Empty(_) if current_param_name.is_some() => {
+                    raw_value = true;
+                }
+                Event::End(e)
+                    if current_param_name
+                        .as_ref()
+                        .is_some_and(|name| e.name().as_ref() != name.borrow().as_bytes()) =>
+                {
+                    let found = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                    let tag_start = event_start + block_text[event_start..].find('<').unwrap_or(0);
+                    if found != tool_name {
+                        foreign_close.get_or_insert((found, start + tag_start));
+                        raw_value = true;
+                        continue;
+                    }
+                    // パラメータが閉じられないままツールの終了タグに達した
+                    let (found, position) =
+                        foreign_close.take().unwrap_or((found, start + tag_start));
+                    return Err(ToolParseError::MismatchedEndTag {
+                        expected: current_param_name
+                            .map(|name| name.borrow().to_string())
+                            .unwrap_or_default(),
+                        found,
+                        position,
+                    });
+                }
+                // パラメータの開始タグ <param_name>
+                Event::Start(e) => {
+                    let tag_name = key(
+                        std::str::from_utf8(e.name().as_ref())
+                            .map_err(|_| ToolParseError::InvalidStructure)?, // UTF-8エラーは想定しにくいが念のため
+                    );
+                    if let Some((name, value)) = self.generic_param_entry(&e)? {
+                        let name = key(&name);
+                        if self.captures_param(name.borrow()) {
+                            params.insert(name.clone(), value.unwrap_or_default());
+                        }
+                        generic_key = Some(name);
+                        value_start = Some(reader.buffer_position());
+                        current_param_name = Some(tag_name);
+                        has_children = true;
+                        raw_value = false;
+                        foreign_close = None;
+                        continue;
+                    }
+                    // 空要素を展開する場合は、テキストがなくても空の値を持つパラメータとする
+                    if self.reader_config.expand_empty_elements
+                        && self.captures_param(tag_name.borrow())
+                    {
+                        params.entry(tag_name.clone()).or_default();
+                    }
+                    current_param_name = Some(tag_name);
+                    value_start = Some(reader.buffer_position());
+                    has_children = true;
+                    raw_value = false;
+                    foreign_close = None;
+                }
+                // パラメータの値 (テキスト)
+                Event::Text(e) => {
+                    if let Some(param_name) = generic_key
+                        .as_ref()
+                        .or(current_param_name.as_ref())
+                        .filter(|name| self.captures_param((*name).borrow()))
+                    {
+                        let param_value = self.normalize_value(e.unescape()?);
+                        params.insert(param_name.clone(), param_value);
+                    } else if current_param_name.is_none() && self.single_text_param.is_some() {
+                        tool_text = Some(self.normalize_value(e.unescape()?));
+                    }
+                }
+                // パラメータの終了タグ </param_name>
+                Event::End(e) => {
+                    if let Some(param_name) = &current_param_name {
+                        let name = generic_key.take().unwrap_or_else(|| param_name.clone());
+                        // `</date >` のように空白を含む終了タグもあるため、タグの長さからは求めない
+                        let value_end =
+                            event_start + block_text[event_start..].find('<').unwrap_or(0);
+                        if let (true, Some(value_start)) = (raw_value, value_start)
+                            && self.captures_param(name.borrow())
+                        {
+                            let value =
+                                self.raw_param_value(&block_text[value_start..value_end])?;
+                            params.insert(name.clone(), value);
+                        }
+                        if let Some(value_start) = value_start.take()
+                            && value_start <= value_end
+                            && self.captures_param(name.borrow())
+                        {
+                            spans.insert(
+                                name,
+                                self.value_span(text, start + value_start..start + value_end),
+                            );
+                        }
+                        current_param_name = None; // 現在のパラメータ処理を終了
+                    } else if e.name().as_ref() == tool_name.as_bytes() {
+                        // ルート要素の終了タグ </tool_name> ならループ終了
+                        break;
+                    }
+                }
+                // 汎用パラメータの空要素 <param name="..." value="..."/>
+                Event::Empty(e) => {
+                    if let Some((name, value)) = self.generic_param_entry(&e)? {
+                        if self.captures_param(&name) {
+                            params.insert(key(&name), value.unwrap_or_default());
+                        }
+                        has_children = true;
+                    }
+                }
+                // ファイル終端 (予期せぬ終了)
+                Event::Eof => return Err(eof_error(&current_param_name)),
+                _ => {} // 他のイベント (コメント、DTDなど) は無視
+            }
+        }
+
+        if let (Some(name), Some(text), false) = (&self.single_text_param, tool_text, has_children)
+        {
+            params.insert(key(name), text);
+        }
+
+        if self.attribute_mode == AttributeMode::MergeAsParams {
+            for (key, value) in attributes.drain() {
+                params.entry(key).or_insert(value);
+            }
+        }
+
+        for name in &self.path_params {
+            if let Some(value) = params.get_mut(name.as_str())
+                && value.contains('\\')
+            {
+                *value = value.replace('\\', "/");
+            }
+        }
+
+        Ok(BlockParts {
+            tool_name,
+            parameters: params,
+            attributes,
+            spans,
+        })
+    }
+
+    /// 開始タグと終了タグの間の範囲から、トリミングされる前後の空白を除いた範囲を返す
+    fn value_span(&self, text: &str, span: Range<usize>) -> Range<usize> {
+        let raw = &text[span.clone()];
+        let mut start = span.start;
+        let mut end = span.end;
+        let reader_config = self.effective_reader_config();
+        if reader_config.trim_text_start {
+            start += raw.len() - raw.trim_start().len();
+        }
+        if reader_config.trim_text_end {
+            end -= raw.len() - raw.trim_end().len();
+        }
+        start..end.max(start)
+    }
+}
+
+// --- テスト ---
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_get_weather() {
+        let llm_response = r#"
+明日のニューヨークの天気ですね。承知いたしました。
+外部の天気予報ツールを使って最新の情報を確認しますね。
+
+<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+  <unit>fahrenheit</unit>
+</get_weather>
+
+結果が取得でき次第、すぐにお知らせします。
+"#;
+        let expected_params: HashMap<String, String> = [
+            ("location".to_string(), "New York".to_string()),
+            ("date".to_string(), "tomorrow".to_string()),
+            ("unit".to_string(), "fahrenheit".to_string()),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+
+        let expected_tool_call = ToolCall {
+            tool_name: "get_weather".to_string(),
+            parameters: expected_params,
+            attributes: HashMap::new(),
+            spans: HashMap::new(),
+        };
+
+        match parse_tool_call(llm_response) {
+            Ok(tool_call) => assert_eq!(tool_call, expected_tool_call),
+            Err(e) => panic!("Parse failed: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_parse_write_file() {
+        let llm_response = r#"
+Okay, I will write the following content to the file.
+<write_to_file>
+<path>src/main.rs</path>
+<content>
+fn main() {
+    println!("Hello, world!");
+}
+</content>
+</write_to_file>
+Let me know if that looks correct.
+"#;
+        let expected_content = r#"fn main() {
+    println!("Hello, world!");
+}"#;
+        let expected_params: HashMap<String, String> = [
+            ("path".to_string(), "src/main.rs".to_string()),
+            ("content".to_string(), expected_content.to_string()),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+
+        let expected_tool_call = ToolCall {
+            tool_name: "write_to_file".to_string(),
+            parameters: expected_params,
+            attributes: HashMap::new(),
+            spans: HashMap::new(),
+        };
+
+        match parse_tool_call(llm_response) {
+            Ok(tool_call) => assert_eq!(tool_call, expected_tool_call),
+            Err(e) => panic!("Parse failed: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_no_tool_found() {
+        let llm_response = "明日の天気は晴れでしょう。";
+        match parse_tool_call(llm_response) {
+            Err(ToolParseError::NoToolXmlFound) => {} // Expected error
+            Ok(_) => panic!("Should have failed, but parsed successfully."),
+            Err(e) => panic!("Expected NoToolXmlFound, but got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_tags_in_value_kept_verbatim() {
+        let tool_call = parse_tool_call(
+            "<write_to_file><path>a.html</path><content>\n<div>a &amp; <b>b</b></div>\n</content></write_to_file>",
+        )
+        .unwrap();
+        assert_eq!(
+            tool_call.parameters,
+            HashMap::from([
+                ("path".to_string(), "a.html".to_string()),
+                ("content".to_string(), "<div>a & <b>b</b></div>".to_string()),
+            ])
+        );
+
+        // 対応しない終了タグも値に含める
+        let tool_call =
+            parse_tool_call("<write_to_file><content>a</div>b</content></write_to_file>").unwrap();
+        assert_eq!(tool_call.parameters["content"], "a</div>b");
+    }
+
+    #[test]
+    fn test_malformed_xml() {
+        let llm_response = "<get_weather><location>New York</date></get_weather>"; // Mismatched tag
+        match parse_tool_call(llm_response) {
+            Err(_) => {} // Expected some error (likely MismatchedEndTag or XmlError)
+            Ok(_) => panic!("Should have failed due to malformed XML."),
+        }
+    }
+
+    #[test]
+    fn test_single_text_param() {
+        let parser = ToolParser::new().single_text_param(Some("query"));
+        let tool_call = parser.parse("<search>rust async</search>").unwrap();
+        assert_eq!(
+            tool_call.parameters,
+            HashMap::from([("query".to_string(), "rust async".to_string())])
+        );
+
+        // 設定しない場合は従来どおり無視する
+        let tool_call = parse_tool_call("<search>rust async</search>").unwrap();
+        assert!(tool_call.parameters.is_empty());
+    }
+
+    #[test]
+    fn test_only_opening_tool_tag() {
+        // 終了タグのない開始タグだけの入力は、パニックせずにエラーとなる
+        assert!(matches!(
+            parse_tool_call("<get_weather>"),
+            Err(ToolParseError::NoToolXmlFound)
+        ));
+        assert!(parse_all_tool_calls("<get_weather>").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_whitespace_mode_collapse() {
+        let input = "<search><query>  hello    world\n  rust\t async  </query></search>";
+        let parser = ToolParser::new().whitespace_mode(WhitespaceMode::Collapse);
+        assert_eq!(
+            parser.parse(input).unwrap().parameters["query"],
+            "hello world rust async"
+        );
+        // 既定ではトリムのみ
+        assert_eq!(
+            parse_tool_call(input).unwrap().parameters["query"],
+            "hello    world\n  rust\t async"
+        );
+    }
+
+    #[test]
+    fn test_expected_start_tag() {
+        // 自己終了したツールタグのあとに終了タグがある場合、ブロックの先頭が開始タグにならない
+        let input = "<get_weather /><location>Tokyo</location></get_weather>";
+        assert!(matches!(
+            parse_tool_call(input),
+            Err(ToolParseError::ExpectedStartTag(found)) if found == "<get_weather/>"
+        ));
+    }
+
+    #[test]
+    fn test_unexpected_eof_names_open_parameter() {
+        // 属性値中の終了タグでブロックが切り出され、パラメータの開始タグの途中で終端に達する
+        let input = r#"<get_weather><location>Tokyo</location><unit note="</get_weather>">celsius</unit></get_weather>"#;
+        let err = parse_tool_call(input).unwrap_err();
+        assert!(matches!(
+            &err,
+            ToolParseError::UnexpectedEofIn { tool_name, param_name: None } if tool_name == "get_weather"
+        ));
+
+        // コメント中の終了タグで、パラメータの値の途中で終端に達する
+        let input = "<get_weather><location>Tokyo<!-- </get_weather> --></location></get_weather>";
+        let err = parse_tool_call(input).unwrap_err();
+        assert!(matches!(
+            &err,
+            ToolParseError::UnexpectedEofIn { tool_name, param_name: Some(param_name) }
+                if tool_name == "get_weather" && param_name == "location"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Unexpected end of file in get_weather (inside parameter location)"
+        );
+    }
+
+    #[test]
+    fn test_to_query_string() {
+        let tool_call = parse_tool_call(
+            "<get_weather><location>New York</location><date>tomorrow</date><unit>fahrenheit</unit></get_weather>",
+        )
+        .unwrap();
+        assert_eq!(
+            tool_call.to_query_string(),
+            "date=tomorrow&location=New%20York&unit=fahrenheit"
+        );
+
+        let tool_call = parse_tool_call("<search><q>a&amp;b=c 東京</q></search>").unwrap();
+        assert_eq!(
+            tool_call.to_query_string(),
+            "q=a%26b%3Dc%20%E6%9D%B1%E4%BA%AC"
+        );
+    }
+
+    #[test]
+    fn test_merge_defaults() {
+        let mut tool_call = parse_tool_call(
+            "<get_weather><location>Tokyo</location><unit>fahrenheit</unit></get_weather>",
+        )
+        .unwrap();
+        let defaults = HashMap::from([
+            ("unit".to_string(), "celsius".to_string()),
+            ("date".to_string(), "today".to_string()),
+        ]);
+        tool_call.merge_defaults(&defaults);
+        assert_eq!(
+            tool_call.parameters,
+            HashMap::from([
+                ("location".to_string(), "Tokyo".to_string()),
+                ("unit".to_string(), "fahrenheit".to_string()),
+                ("date".to_string(), "today".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_whitespace_only_value_preserved() {
+        let input = "<set_indent><indent>    </indent><name> a </name></set_indent>";
+        let parser = ToolParser::new().whitespace_mode(WhitespaceMode::Preserve);
+        let tool_call = parser.parse(input).unwrap();
+        assert_eq!(tool_call.parameters["indent"], "    ");
+        assert_eq!(tool_call.parameters["name"], " a ");
+        assert_eq!(&input[tool_call.param_spans()["indent"].clone()], "    ");
+
+        // 既定ではトリムにより空になった値は捨てる
+        assert!(
+            !parse_tool_call(input)
+                .unwrap()
+                .parameters
+                .contains_key("indent")
+        );
+    }
+
+    #[test]
+    fn test_require_marker() {
+        let input = "例えば <get_weather><location>Osaka</location></get_weather> のように呼び出します。\n\
+                     TOOL_CALL:\n<get_weather><location>Tokyo</location></get_weather>";
+        let parser = ToolParser::new().require_marker("TOOL_CALL:");
+        assert_eq!(parser.parse(input).unwrap().parameters["location"], "Tokyo");
+        assert_eq!(parser.parse_all(input).unwrap().tool_calls.len(), 1);
+
+        // 目印とツールタグの間にテキストがある場合は対象外
+        let input =
+            "TOOL_CALL: 天気を調べます <get_weather><location>Tokyo</location></get_weather>";
+        assert!(matches!(
+            parser.parse(input),
+            Err(ToolParseError::NoToolXmlFound)
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_repeated_items_into_vec() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct AddItems {
+            list: String,
+            #[serde(rename = "item")]
+            items: Vec<String>,
+            count: u32,
+        }
+
+        let input = "追加します。\n<add_items>\n  <list>買い物</list>\n  <item>牛乳</item>\n  <item>卵</item>\n  <item>パン</item>\n  <count>3</count>\n</add_items>";
+        assert_eq!(
+            deserialize_params::<AddItems>(input).unwrap(),
+            AddItems {
+                list: "買い物".to_string(),
+                items: vec!["牛乳".to_string(), "卵".to_string(), "パン".to_string()],
+                count: 3,
+            }
+        );
+        assert!(matches!(
+            deserialize_params::<AddItems>("<add_items><list>a</list></add_items>"),
+            Err(ToolParseError::DeserializeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_tool_call_with_name() {
+        let expected = ToolCall {
+            tool_name: "get_weather".to_string(),
+            parameters: HashMap::from([
+                ("location".to_string(), "Tokyo".to_string()),
+                ("date".to_string(), "tomorrow".to_string()),
+            ]),
+            attributes: HashMap::new(),
+            spans: HashMap::new(),
+        };
+        // ツールタグのないパラメータ要素だけの出力
+        let input =
+            "調べます。\n<location>Tokyo</location>\n<date>tomorrow</date>\nお待ちください。";
+        assert_eq!(
+            parse_tool_call_with_name(input, "get_weather").unwrap(),
+            expected
+        );
+
+        // ツールタグがある場合はそのブロックをパースする
+        let input = "<get_weather><location>Tokyo</location><date>tomorrow</date></get_weather>";
+        assert_eq!(
+            parse_tool_call_with_name(input, "get_weather").unwrap(),
+            expected
+        );
+        assert!(matches!(
+            parse_tool_call_with_name("パラメータなし", "get_weather"),
+            Err(ToolParseError::NoToolXmlFound)
+        ));
+    }
+
+    #[test]
+    fn test_strip_content_fence() {
+        let input = "<write_to_file><path>main.rs</path><content>\n```rust\nfn main() {\n    println!(\"```\");\n}\n```\n</content></write_to_file>";
+        let tool_call = ToolParser::new()
+            .strip_content_fence(true)
+            .parse(input)
+            .unwrap();
+        assert_eq!(
+            tool_call.parameters["content"],
+            "fn main() {\n    println!(\"```\");\n}"
+        );
+        assert_eq!(tool_call.parameters["path"], "main.rs");
+
+        // 無効の場合はフェンスをそのまま残す
+        assert_eq!(
+            parse_tool_call(input).unwrap().parameters["content"],
+            "```rust\nfn main() {\n    println!(\"```\");\n}\n```"
+        );
+
+        assert_eq!(strip_code_fence("\"\"\"\nquoted\n\"\"\""), Some("quoted"));
+        // 値の途中のフェンスは対象外
+        assert_eq!(strip_code_fence("説明\n```\ncode\n```"), None);
+    }
+
+    #[test]
+    fn test_hyphen_and_dot_names() {
+        let input = "検索します。<web-search><search-query>rust xml</search-query><max.results>5</max.results></web-search>";
+        let tool_call = parse_tool_call(input).unwrap();
+        assert_eq!(
+            tool_call,
+            ToolCall {
+                tool_name: "web-search".to_string(),
+                parameters: HashMap::from([
+                    ("search-query".to_string(), "rust xml".to_string()),
+                    ("max.results".to_string(), "5".to_string()),
+                ]),
+                attributes: HashMap::new(),
+                spans: HashMap::new(),
+            }
+        );
+        // XMLに変換しても名前はそのまま残り、元のツール呼び出しに戻る
+        assert_eq!(parse_tool_call(&tool_call.to_xml()).unwrap(), tool_call);
+
+        let tool_call = parse_tool_call("<fs.read><file.path>a.txt</file.path></fs.read>").unwrap();
+        assert_eq!(tool_call.tool_name, "fs.read");
+        assert_eq!(tool_call.parameters["file.path"], "a.txt");
+    }
+
+    #[test]
+    fn test_conflicting_tool_tags() {
+        let err =
+            parse_tool_call("<get_weather><location>Tokyo</location></write_to_file>").unwrap_err();
+        assert!(matches!(
+            &err,
+            ToolParseError::ConflictingToolTags { opened, closed }
+                if opened == "get_weather" && closed == "write_to_file"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Tool get_weather was closed by a different tag </write_to_file>"
+        );
+
+        // 値の中の対応しない終了タグは、開いている要素がないときの終了タグとみなさない
+        assert!(matches!(
+            parse_tool_call("<write_to_file><content>a</div>b</content></get_weather>"),
+            Err(ToolParseError::ConflictingToolTags { opened, closed })
+                if opened == "write_to_file" && closed == "get_weather"
+        ));
+
+        // ツールの前にある、閉じられていないタグは対象外
+        let input = "<b>注意</i>\n<write_to_file><content>return 1;</content></write_to_file>";
+        assert_eq!(parse_tool_call(input).unwrap().tool_name, "write_to_file");
+        // 途中で打ち切られたツール呼び出しは従来どおり
+        assert!(matches!(
+            parse_tool_call("<get_weather><location>Tok"),
+            Err(ToolParseError::NoToolXmlFound)
+        ));
+
+        // parse_all と parse_last も同じ規則で検出する
+        let input = "<get_weather><location>Tokyo</location></write_to_file>";
+        let parser = ToolParser::new();
+        assert!(matches!(
+            parser.parse_all(input),
+            Err(ToolParseError::ConflictingToolTags { .. })
+        ));
+        assert!(matches!(
+            parser.parse_last(input),
+            Err(ToolParseError::ConflictingToolTags { .. })
+        ));
+    }
+
+    #[test]
+    fn test_prose_tags_are_not_conflicting_tool_tags() {
+        // 子要素を持たない地の文中のタグは、ツール呼び出しがないものとして扱う
+        let parser = ToolParser::new();
+        for input in ["Use <b>bold</i> for emphasis.", "if a <b && c> d"] {
+            assert!(
+                matches!(parser.parse(input), Err(ToolParseError::NoToolXmlFound)),
+                "{input}"
+            );
+            assert!(
+                matches!(
+                    parser.parse_last(input),
+                    Err(ToolParseError::NoToolXmlFound)
+                ),
+                "{input}"
+            );
+            assert!(
+                parser.parse_all(input).unwrap().tool_calls.is_empty(),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_quick_balance_check() {
+        let input = "天気を確認します。\n<get_weather>\n<location>Tokyo</location>\n<date>2025-01-01</date>\n</get_weather>";
+        assert!(quick_balance_check(input));
+        // 値の中の対応しない終了タグは無視する
+        assert!(quick_balance_check(
+            "<write_to_file><content>a</div>b</content></write_to_file>"
+        ));
+        // 地の文の `<` の後にツール呼び出しが続く
+        assert!(quick_balance_check(
+            "if a < b then\n<get_weather><location>Tokyo</location></get_weather>"
+        ));
+
+        // 終了タグがない
+        assert!(!quick_balance_check(
+            "<get_weather><location>Tokyo</location>"
+        ));
+        assert!(!quick_balance_check(
+            "<get_weather><location>Tokyo</get_weather>"
+        ));
+        assert!(!quick_balance_check("</get_weather>"));
+        assert!(!quick_balance_check("ツール呼び出しはありません"));
+    }
+
+    #[test]
+    fn test_canonical_hash() {
+        let call = |params: &[(&str, &str)]| ToolCall {
+            tool_name: "get_weather".to_string(),
+            parameters: params
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            attributes: HashMap::new(),
+            spans: HashMap::new(),
+        };
+        let a = call(&[("location", "Tokyo"), ("date", "tomorrow")]);
+        let b = call(&[("date", "tomorrow"), ("location", "Tokyo")]);
+        assert_eq!(a.canonical_hash(), b.canonical_hash());
+        // 実行環境やバージョンに依存しない固定の値になる
+        assert_eq!(a.canonical_hash(), 0xd6cb_ac6c_a24c_0d8a);
+
+        assert_ne!(
+            a.canonical_hash(),
+            call(&[("location", "Osaka"), ("date", "tomorrow")]).canonical_hash()
+        );
+        // キーと値の境界が異なる場合も区別する
+        assert_ne!(
+            call(&[("ab", "c")]).canonical_hash(),
+            call(&[("a", "bc")]).canonical_hash()
+        );
+    }
+
+    #[test]
+    fn test_numeric_tag_is_not_tool() {
+        let input = "手順 <1> と <123>を参照</123> のあと <search><query>rust</query></search>";
+        let tool_call = parse_tool_call(input).unwrap();
+        assert_eq!(tool_call.tool_name, "search");
+        assert!(matches!(
+            parse_tool_call("<123>value</123>"),
+            Err(ToolParseError::NoToolXmlFound)
+        ));
+
+        // 判定を差し替えれば数字のタグもツールとして扱える
+        let parser = ToolParser::new().tool_name_filter(|name| !name.is_empty());
+        assert_eq!(parser.parse("<123>value</123>").unwrap().tool_name, "123");
+    }
+
+    #[test]
+    fn test_scan_skips_tags_without_end_tag() {
+        // 最初のタグがツール呼び出しでなくても、その後ろのツール呼び出しを探す
+        let input = "<br>確認します。</p>\n<get_weather><location>Tokyo</location></get_weather>";
+        let tool_call = parse_tool_call(input).unwrap();
+        assert_eq!(tool_call.tool_name, "get_weather");
+        assert_eq!(tool_call.parameters["location"], "Tokyo");
+
+        // 複数のツール呼び出しの間にある場合も同様
+        let input = "<search><query>a</query></search><br><search><query>b</query></search>";
+        let tool_calls = parse_all_tool_calls(input).unwrap();
+        assert_eq!(tool_calls.len(), 2);
+        assert_eq!(tool_calls[1].parameters["query"], "b");
+    }
+
+    #[test]
+    fn test_parse_last_tool_call() {
+        let input = r#"候補を検討します。
+<get_weather><location>Tokyo</location></get_weather>
+<get_weather><location>Osaka</location></get_weather>
+最終的な判断:
+<get_weather><location>Sapporo</location></get_weather>"#;
+        let tool_call = parse_last_tool_call(input).unwrap();
+        assert_eq!(tool_call.parameters["location"], "Sapporo");
+
+        assert!(matches!(
+            parse_last_tool_call("ツール呼び出しはありません"),
+            Err(ToolParseError::NoToolXmlFound)
+        ));
+    }
+
+    #[test]
+    fn test_parse_tool_result() {
+        let input = r#"結果を返します。
+<tool_result name="get_weather">
+晴れ、最高気温 25&#176;C &amp; 湿度 40%
+</tool_result>"#;
+        let result = parse_tool_result(input).unwrap();
+        assert_eq!(
+            result,
+            ToolResult {
+                tool_name: "get_weather".to_string(),
+                content: "晴れ、最高気温 25°C & 湿度 40%".to_string(),
+            }
+        );
+
+        assert!(matches!(
+            parse_tool_result("<tool_result>ok</tool_result>"),
+            Err(ToolParseError::ToolNameNotFound)
+        ));
+        assert!(matches!(
+            parse_tool_result("<tool_results name=\"a\">ok</tool_results>"),
+            Err(ToolParseError::NoToolXmlFound)
+        ));
+    }
+
+    #[test]
+    fn test_tool_result_round_trip() {
+        let result = ToolResult {
+            tool_name: "read_file".to_string(),
+            content: "<html>\n  <body>A & B</body>\n</html>".to_string(),
+        };
+        assert_eq!(parse_tool_result(&result.to_xml()).unwrap(), result);
+    }
+
+    #[test]
+    fn test_param_spans() {
+        let input = r#"明日のニューヨークの天気を確認します。
+<get_weather>
+  <location>New York</location>
+  <date>
+    tomorrow
+  </date>
+</get_weather>"#;
+        let tool_call = parse_tool_call(input).unwrap();
+        let spans = tool_call.param_spans();
+        assert_eq!(&input[spans["location"].clone()], "New York");
+        assert_eq!(&input[spans["date"].clone()], "tomorrow");
+
+        // 終了タグに空白を含む場合も、値の終わりはタグの開始位置
+        let input = "<get_weather><location>Tokyo</location ></get_weather>";
+        let tool_call = parse_tool_call(input).unwrap();
+        assert_eq!(tool_call.param_spans()["location"], 23..28);
+
+        // 除外タグは同じバイト数の空白に置き換えるため、位置は元の入力と一致する
+        let input = "<think>x</think><get_weather><location>Tokyo</location></get_weather>";
+        let tool_call = ToolParser::new()
+            .exclude_tags(&["think"])
+            .parse(input)
+            .unwrap();
+        assert_eq!(&input[tool_call.param_spans()["location"].clone()], "Tokyo");
+    }
+
+    #[test]
+    fn test_param_spans_cleared_when_text_is_rewritten() {
+        let input = "<get_weather><location>New York</></get_weather>";
+        let tool_call = ToolParser::new()
+            .accept_generic_close(true)
+            .parse(input)
+            .unwrap();
+        assert_eq!(tool_call.parameters["location"], "New York");
+        assert!(tool_call.param_spans().is_empty());
+
+        let input = "«get_weather»«location»New York«/location»«/get_weather»";
+        let tool_call = ToolParser::new()
+            .delimiters(Delimiters {
+                open: '«',
+                close: '»',
+                close_prefix: '/',
+            })
+            .parse(input)
+            .unwrap();
+        assert_eq!(tool_call.parameters["location"], "New York");
+        assert!(tool_call.param_spans().is_empty());
+
+        let input = "<location>New York</location>";
+        let tool_call = ToolParser::new()
+            .parse_with_name(input, "get_weather")
+            .unwrap();
+        assert_eq!(tool_call.parameters["location"], "New York");
+        assert!(tool_call.param_spans().is_empty());
+    }
+
+    #[test]
+    fn test_escape_values() {
+        let value = "<&>\"'\u{1}";
+        assert_eq!(escape_param_value(value), "&lt;&amp;&gt;\"'&#x1;");
+        assert_eq!(escape_attr_value(value), "&lt;&amp;&gt;&quot;&apos;&#x1;");
+
+        // エスケープした値は元の文字列としてパースされる
+        let input = format!(
+            r#"<run note="{}"><arg>{}</arg></run>"#,
+            escape_attr_value(value),
+            escape_param_value(value)
+        );
+        let tool_call = ToolParser::new()
+            .attribute_mode(AttributeMode::Separate)
+            .parse(&input)
+            .unwrap();
+        assert_eq!(tool_call.parameters["arg"], value);
+        assert_eq!(tool_call.attributes["note"], value);
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        let original = b"\x00\x01binary\xff<xml>";
+        let parser = ToolParser::new().decode_base64("content");
+        let input = "<write_to_file><path>a.bin</path><content>AAFiaW5hcnn/\nPHhtbD4=</content></write_to_file>";
+        let tool_call = parser.parse(input).unwrap();
+        assert_eq!(tool_call.get_bytes("content").unwrap(), original);
+        assert_eq!(tool_call.get_bytes("missing"), None);
+
+        let input = "<write_to_file><content>not base64!</content></write_to_file>";
+        assert!(matches!(
+            parser.parse(input),
+            Err(ToolParseError::InvalidBase64 { key }) if key == "content"
+        ));
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        let input =
+            r"<write_to_file><path>src\main.rs</path><content>a\nb</content></write_to_file>";
+        let tool_call = ToolParser::new()
+            .normalize_path("path")
+            .parse(input)
+            .unwrap();
+        assert_eq!(tool_call.parameters["path"], "src/main.rs");
+        // 指定していないパラメータは変換しない
+        assert_eq!(tool_call.parameters["content"], r"a\nb");
+
+        let tool_call = ToolParser::new().parse(input).unwrap();
+        assert_eq!(tool_call.parameters["path"], r"src\main.rs");
+    }
+
+    #[test]
+    fn test_generic_param() {
+        let parser = ToolParser::new().generic_param("param", "name", "value");
+        let input = r#"<get_weather>
+  <param name="location" value="Tokyo"/>
+  <param name="date" value="tomorrow"/>
+  <param name="unit" value="celsius"/>
+</get_weather>"#;
+        let tool_call = parser.parse(input).unwrap();
+        assert_eq!(
+            tool_call.parameters,
+            HashMap::from([
+                ("location".to_string(), "Tokyo".to_string()),
+                ("date".to_string(), "tomorrow".to_string()),
+                ("unit".to_string(), "celsius".to_string()),
+            ])
+        );
+
+        // value 属性がない場合は要素のテキストを値とする
+        let input = r#"<search><param name="query">rust &amp; async</param></search>"#;
+        let tool_call = parser.parse(input).unwrap();
+        assert_eq!(
+            tool_call.parameters,
+            HashMap::from([("query".to_string(), "rust & async".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_exclude_tags() {
+        let llm_response = r#"<thinking>
+例えば <get_weather><location>Paris</location></get_weather> のように呼び出せる。
+</thinking>
+<get_weather>
+  <location>Tokyo</location>
+</get_weather>"#;
+        let parser = ToolParser::new().exclude_tags(&["thinking"]);
+
+        let tool_calls = parser.parse_all(llm_response).unwrap().tool_calls;
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].parameters["location"], "Tokyo");
+
+        // 除外しない場合は推論ブロック自体がツール呼び出しとして扱われる
+        assert_eq!(parse_tool_call(llm_response).unwrap().tool_name, "thinking");
+
+        // 属性や空白を含むタグ、自己終了タグも除外する。名前が前方一致するだけのタグは対象外
+        for input in [
+            r#"<thinking mode="deep"><search><query>a</query></search></thinking><search><query>b</query></search>"#,
+            "<thinking ><search><query>a</query></search></thinking ><search><query>b</query></search>",
+            "<thinking/><search><query>b</query></search>",
+        ] {
+            let tool_calls = parser.parse_all(input).unwrap().tool_calls;
+            assert_eq!(tool_calls.len(), 1, "{input}");
+            assert_eq!(tool_calls[0].parameters["query"], "b");
+        }
+        let input = "<thinking_log><query>a</query></thinking_log>";
+        assert_eq!(parser.parse(input).unwrap().tool_name, "thinking_log");
+    }
+
+    #[test]
+    fn test_reader_config_expand_empty_elements() {
+        let llm_response = "<list_files><path>src</path><recursive/></list_files>";
+        let tool_call = parse_tool_call(llm_response).unwrap();
+        assert!(!tool_call.parameters.contains_key("recursive"));
+
+        let parser = ToolParser::new().reader_config(ReaderConfig {
+            expand_empty_elements: true,
+            ..ReaderConfig::default()
+        });
+        let tool_call = parser.parse(llm_response).unwrap();
+        assert_eq!(
+            tool_call.parameters,
+            HashMap::from([
+                ("path".to_string(), "src".to_string()),
+                ("recursive".to_string(), String::new()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_tool_call_from_json() {
+        let json = r#"{"message":{"role":"assistant","content":"確認します。\n<get_weather><location>Tokyo</location></get_weather>"}}"#;
+        let tool_call = parse_tool_call_from_json(json, "/message/content").unwrap();
+        assert_eq!(tool_call.tool_name, "get_weather");
+        assert_eq!(tool_call.parameters["location"], "Tokyo");
+
+        assert!(matches!(
+            parse_tool_call_from_json(json, "/message/missing"),
+            Err(ToolParseError::JsonPointerNotFound(pointer)) if pointer == "/message/missing"
+        ));
+        assert!(matches!(
+            parse_tool_call_from_json("{", "/message/content"),
+            Err(ToolParseError::JsonError(_))
+        ));
+    }
+
+    #[test]
+    fn test_attribute_value_containing_gt() {
+        let llm_response = r#"<evaluate expr="a > b" mode='x>y'><scope>local</scope></evaluate>"#;
+        let parser = ToolParser::new().attribute_mode(AttributeMode::Separate);
+        let tool_call = parser.parse(llm_response).unwrap();
+        assert_eq!(tool_call.tool_name, "evaluate");
+        assert_eq!(
+            tool_call.parameters,
+            HashMap::from([("scope".to_string(), "local".to_string())])
+        );
+        assert_eq!(
+            tool_call.attributes,
+            HashMap::from([
+                ("expr".to_string(), "a > b".to_string()),
+                ("mode".to_string(), "x>y".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_apostrophe_in_prose_does_not_hide_tool() {
+        let llm_response =
+            "x < y, it's fine. <get_weather><location>Tokyo</location></get_weather>";
+        let tool_call = parse_tool_call(llm_response).unwrap();
+        assert_eq!(tool_call.tool_name, "get_weather");
+    }
+
+    #[test]
+    fn test_custom_delimiters() {
+        let llm_response =
+            "if a < b && c > d { «get_weather»«location»Tokyo & <Osaka>«/location»«/get_weather» }";
+        let parser = ToolParser::new().delimiters(Delimiters {
+            open: '«',
+            close: '»',
+            close_prefix: '/',
+        });
+        let tool_call = parser.parse(llm_response).unwrap();
+        assert_eq!(tool_call.tool_name, "get_weather");
+        assert_eq!(tool_call.parameters["location"], "Tokyo & <Osaka>");
+    }
+
+    #[test]
+    fn test_parse_timed() {
+        let llm_response = format!(
+            "{}<write_to_file><path>a.txt</path><content>{}</content></write_to_file>",
+            "前置きのテキスト。".repeat(20_000),
+            "x &amp; y\n".repeat(20_000)
+        );
+        let (tool_call, timing) = parse_timed(&llm_response).unwrap();
+        assert_eq!(tool_call, parse_tool_call(&llm_response).unwrap());
+        assert!(timing.scan > Duration::ZERO);
+        assert!(timing.parse > Duration::ZERO);
+        assert_eq!(timing.total(), timing.scan + timing.parse);
+    }
+
+    #[test]
+    fn test_tool_and_parameter_with_same_name() {
+        let tool_call = parse_tool_call("<param><param>v</param><other>x</other></param>").unwrap();
+        assert_eq!(tool_call.tool_name, "param");
+        assert_eq!(
+            tool_call.parameters,
+            HashMap::from([
+                ("param".to_string(), "v".to_string()),
+                ("other".to_string(), "x".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_input_is_exactly_one_tool_block() {
+        let llm_response = "<get_weather><location>Tokyo</location></get_weather>";
+        let tool_call = parse_tool_call(llm_response).unwrap();
+        assert_eq!(tool_call.tool_name, "get_weather");
+        assert_eq!(tool_call.parameters["location"], "Tokyo");
+
+        // 最短のツールブロックでも境界の計算がずれない
+        let tool_call = parse_tool_call("<a></a>").unwrap();
+        assert_eq!(tool_call.tool_name, "a");
+        assert!(tool_call.parameters.is_empty());
+    }
+
+    #[test]
+    fn test_schema_enum_param() {
+        let parser = ToolParser::new().schema(
+            schema::ToolSchema::new("get_weather").enum_param("unit", &["celsius", "fahrenheit"]),
+        );
+
+        let tool_call = parser
+            .parse("<get_weather><location>Tokyo</location><unit>celsius</unit></get_weather>")
+            .unwrap();
+        assert_eq!(tool_call.parameters["unit"], "celsius");
+
+        match parser
+            .parse("<get_weather><location>Tokyo</location><unit>kelvin</unit></get_weather>")
+        {
+            Err(ToolParseError::InvalidEnumValue {
+                key,
+                value,
+                allowed,
+            }) => {
+                assert_eq!(key, "unit");
+                assert_eq!(value, "kelvin");
+                assert_eq!(allowed, vec!["celsius", "fahrenheit"]);
+            }
+            other => panic!("Expected InvalidEnumValue, but got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_expect_param_count() {
+        let parser = ToolParser::new().expect_param_count("get_weather", 2);
+
+        let tool_call = parser
+            .parse("<get_weather><location>Tokyo</location><date>tomorrow</date></get_weather>")
+            .unwrap();
+        assert_eq!(tool_call.parameters.len(), 2);
+
+        assert!(matches!(
+            parser.parse("<get_weather><location>Tokyo</location></get_weather>"),
+            Err(ToolParseError::TooFewParams {
+                expected: 2,
+                found: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parser.parse(
+                "<get_weather><location>Tokyo</location><date>tomorrow</date><unit>celsius</unit></get_weather>"
+            ),
+            Err(ToolParseError::TooManyParams {
+                expected: 2,
+                found: 3,
+                ..
+            })
+        ));
+        // 他のツールには影響しない
+        assert!(parser.parse("<search><query>rust</query></search>").is_ok());
+    }
+
+    #[test]
+    fn test_error_context_points_at_mismatched_end_tag() {
+        let llm_response =
+            "天気を調べます。\n<get_weather>\n  <location>New York</date>\n</get_weather>";
+        let error = parse_tool_call(llm_response).unwrap_err();
+        assert!(matches!(
+            error,
+            ToolParseError::MismatchedEndTag { ref found, .. } if found == "date"
+        ));
+        assert_eq!(
+            error.context(llm_response),
+            "Expected end tag location, found date
+ --> 3:21
+  |
+3 |   <location>New York</date>
+  |                     ^^^^^^^"
+        );
+
+        // 空白を含む終了タグも、タグの開始位置を指す
+        let llm_response = "<get_weather><location>Tokyo</date ></get_weather>";
+        let error = parse_tool_call(llm_response).unwrap_err();
+        assert!(matches!(
+            error,
+            ToolParseError::MismatchedEndTag { ref found, position, .. }
+                if found == "date" && position == 28
+        ));
+        assert!(error.context(llm_response).ends_with("^^^^^^^^"));
+    }
+
+    #[test]
+    fn test_parse_all_with_max_tool_calls() {
+        let llm_response = (1..=5)
+            .map(|i| {
+                format!(
+                    "step {}\n<get_weather><location>City{}</location></get_weather>\n",
+                    i, i
+                )
+            })
+            .collect::<String>();
+
+        let parsed = ToolParser::new()
+            .max_tool_calls(2)
+            .parse_all(&llm_response)
+            .unwrap();
+        assert_eq!(parsed.stop_reason, StopReason::LimitReached);
+        assert!(parsed.is_truncated());
+        assert_eq!(parsed.tool_calls.len(), 2);
+        assert_eq!(parsed.tool_calls[0].parameters["location"], "City1");
+        assert_eq!(parsed.tool_calls[1].parameters["location"], "City2");
+
+        // 上限より少ない場合は入力の終端まで読み切る
+        let parsed = ToolParser::new()
+            .max_tool_calls(5)
+            .parse_all(&llm_response)
+            .unwrap();
+        assert_eq!(parsed.stop_reason, StopReason::EndOfInput);
+        assert_eq!(parsed.tool_calls.len(), 5);
+
+        assert_eq!(parse_all_tool_calls(&llm_response).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_yaml_fallback() {
+        let llm_response = r#"XMLは使わずに呼び出します。
+
+tool: get_weather
+params:
+  location: "New York"
+  date: tomorrow
+
+結果をお待ちください。
+"#;
+        let expected_tool_call = ToolCall {
+            tool_name: "get_weather".to_string(),
+            parameters: [
+                ("location".to_string(), "New York".to_string()),
+                ("date".to_string(), "tomorrow".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            attributes: HashMap::new(),
+            spans: HashMap::new(),
+        };
+
+        assert_eq!(
+            ToolParser::new()
+                .yaml_fallback(true)
+                .parse(llm_response)
+                .unwrap(),
+            expected_tool_call
+        );
+        // オプトインしない場合は従来どおりエラー
+        assert!(matches!(
+            ToolParser::new().parse(llm_response),
+            Err(ToolParseError::NoToolXmlFound)
+        ));
+
+        // parse_all ではすべてのブロックを解釈する
+        let llm_response =
+            "tool: get_weather\nparams:\n  location: Tokyo\ntool: get_time\nparams:\n  zone: JST\n";
+        let parser = ToolParser::new().yaml_fallback(true);
+        let parsed = parser.parse_all(llm_response).unwrap();
+        assert_eq!(parsed.stop_reason, StopReason::EndOfInput);
+        assert_eq!(
+            parsed
+                .tool_calls
+                .iter()
+                .map(|call| call.tool_name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["get_weather", "get_time"]
+        );
+        assert_eq!(parsed.tool_calls[1].parameters["zone"], "JST");
+        let parsed = parser.max_tool_calls(1).parse_all(llm_response).unwrap();
+        assert_eq!(parsed.stop_reason, StopReason::LimitReached);
+        assert_eq!(parsed.tool_calls.len(), 1);
+        assert!(
+            ToolParser::new()
+                .parse_all(llm_response)
+                .unwrap()
+                .tool_calls
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_tool_call_diff() {
+        let expected = parse_tool_call(
+            "<get_weather><location>Tokyo</location><date>tomorrow</date><unit>celsius</unit></get_weather>",
+        )
+        .unwrap();
+        let actual = parse_tool_call(
+            "<get_weather><location>Tokyo</location><unit>fahrenheit</unit></get_weather>",
+        )
+        .unwrap();
+
+        let diff = expected.diff(&actual);
+        assert_eq!(diff.tool_name, None);
+        assert_eq!(
+            diff.changed,
+            BTreeMap::from([(
+                "unit".to_string(),
+                ("celsius".to_string(), "fahrenheit".to_string())
+            )])
+        );
+        assert_eq!(
+            diff.only_in_self,
+            BTreeMap::from([("date".to_string(), "tomorrow".to_string())])
+        );
+        assert!(diff.only_in_other.is_empty());
+        assert_eq!(
+            diff.to_string(),
+            "- date: \"tomorrow\"\n~ unit: \"celsius\" -> \"fahrenheit\"\n"
+        );
+        assert!(expected.diff(&expected).is_empty());
+    }
+
+    #[test]
+    fn test_attribute_mode() {
+        let llm_response = r#"<get_weather unit="celsius" source="jma">
+  <location>Tokyo</location>
+  <unit>fahrenheit</unit>
+</get_weather>"#;
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        // デフォルトでは属性を無視する
+        let ignored = ToolParser::new()
+            .attribute_mode(AttributeMode::Ignore)
+            .parse(llm_response)
+            .unwrap();
+        assert_eq!(
+            ignored.parameters,
+            params(&[("location", "Tokyo"), ("unit", "fahrenheit")])
+        );
+        assert!(ignored.attributes.is_empty());
+        assert_eq!(parse_tool_call(llm_response).unwrap(), ignored);
+
+        let separate = ToolParser::new()
+            .attribute_mode(AttributeMode::Separate)
+            .parse(llm_response)
+            .unwrap();
+        assert_eq!(
+            separate.parameters,
+            params(&[("location", "Tokyo"), ("unit", "fahrenheit")])
+        );
+        assert_eq!(
+            separate.attributes,
+            params(&[("unit", "celsius"), ("source", "jma")])
+        );
+        // 属性もXMLに出力され、元のツール呼び出しに戻る
+        let xml = separate.to_xml();
+        assert!(xml.starts_with(r#"<get_weather source="jma" unit="celsius">"#));
+        assert_eq!(
+            ToolParser::new()
+                .attribute_mode(AttributeMode::Separate)
+                .parse(&xml)
+                .unwrap(),
+            separate
+        );
+
+        let merged = ToolParser::new()
+            .attribute_mode(AttributeMode::MergeAsParams)
+            .parse(llm_response)
+            .unwrap();
+        assert_eq!(
+            merged.parameters,
+            params(&[
+                ("location", "Tokyo"),
+                ("unit", "fahrenheit"),
+                ("source", "jma")
+            ])
+        );
+        assert!(merged.attributes.is_empty());
+    }
+
+    #[test]
+    fn test_accept_generic_close() {
+        let llm_response = "<get_weather><location>Tokyo</></get_weather>";
+        let expected_tool_call = ToolCall {
+            tool_name: "get_weather".to_string(),
+            parameters: HashMap::from([("location".to_string(), "Tokyo".to_string())]),
+            attributes: HashMap::new(),
+            spans: HashMap::new(),
+        };
+
+        let parser = ToolParser::new().accept_generic_close(true);
+        assert_eq!(parser.parse(llm_response).unwrap(), expected_tool_call);
+        // ツール自体の終了タグが `</>` の場合も受け付ける
+        assert_eq!(
+            parser.parse("<get_weather><location>Tokyo</></>").unwrap(),
+            expected_tool_call
+        );
+        // オプトインしない場合はエラー
+        assert!(ToolParser::new().parse(llm_response).is_err());
+    }
+
+    /// `xmlns` や `xml:lang` のようなXMLの予約語と同じ名前のパラメータ
+    ///
+    /// 名前空間を解決しない `quick_xml::Reader` を使用しているため、通常のパラメータとして扱われる。
+    #[test]
+    fn test_parameters_named_like_xml_reserved_words() {
+        let tool_call =
+            parse_tool_call("<tool><xmlns>v</xmlns><xml:lang>ja</xml:lang></tool>").unwrap();
+        assert_eq!(
+            tool_call.parameters,
+            HashMap::from([
+                ("xmlns".to_string(), "v".to_string()),
+                ("xml:lang".to_string(), "ja".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_only_params() {
+        let llm_response = r#"<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+  <unit>fahrenheit</unit>
+</get_weather>"#;
+
+        let tool_call = ToolParser::new()
+            .only_params(&["location", "date"])
+            .parse(llm_response)
+            .unwrap();
+        assert_eq!(
+            tool_call.parameters,
+            HashMap::from([
+                ("location".to_string(), "New York".to_string()),
+                ("date".to_string(), "tomorrow".to_string()),
+            ])
+        );
+        assert!(!tool_call.parameters.contains_key("unit"));
+    }
+
+    mod proptests {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// XMLの構文と衝突しない要素名
+        fn name() -> impl Strategy<Value = String> {
+            "[a-z][a-z0-9_]{0,8}"
+        }
+
+        /// 前後に空白を含まない値（パース時に前後の空白はトリムされるため）
+        fn value() -> impl Strategy<Value = String> {
+            "[a-zA-Z0-9<>&'\"/.,!? 日本語\n]{1,20}".prop_filter("trimmed and non-empty", |v| {
+                !v.trim().is_empty() && v.trim() == v
+            })
+        }
+
+        fn tool_call() -> impl Strategy<Value = ToolCall> {
+            (name(), prop::collection::hash_map(name(), value(), 0..5)).prop_map(
+                |(tool_name, parameters)| ToolCall {
+                    tool_name,
+                    parameters,
+                    attributes: HashMap::new(),
+                    spans: HashMap::new(),
+                },
+            )
+        }
+
+        /// 属性付きのツール呼び出し。属性値は空白だけの値も含め、そのまま復元される
+        fn tool_call_with_attributes() -> impl Strategy<Value = ToolCall> {
+            let attribute_value = "[a-zA-Z0-9<>&'\"/.,!? 日本語\t]{0,20}";
+            (
+                tool_call(),
+                prop::collection::hash_map(name(), attribute_value, 0..3),
+            )
+                .prop_map(|(tool_call, attributes)| ToolCall {
+                    attributes,
+                    ..tool_call
+                })
+        }
+
+        proptest! {
+            #[test]
+            fn to_xml_round_trips(tool_call in tool_call()) {
+                prop_assert_eq!(parse_tool_call(&tool_call.to_xml()).unwrap(), tool_call);
+            }
+
+            #[test]
+            fn to_xml_round_trips_attributes(tool_call in tool_call_with_attributes()) {
+                let parser = ToolParser::new().attribute_mode(AttributeMode::Separate);
+                prop_assert_eq!(parser.parse(&tool_call.to_xml()).unwrap(), tool_call);
+            }
+
+            #[test]
+            fn parse_never_panics(text in ".*") {
+                let _ = parse_tool_call(&text);
+                let _ = parse_all_tool_calls(&text);
+            }
+        }
+    }
+}
@@ -1,245 +1,66 @@
-use quick_xml::Reader;
-use quick_xml::events::Event;
-use serde::Deserialize;
-use std::collections::HashMap;
-
-// パースエラーを表すEnum
-#[derive(thiserror::Error, Debug)]
-pub enum ToolParseError {
-    #[error("XML parsing error: {0}")]
-    XmlError(#[from] quick_xml::Error),
-    #[error("IO error: {0}")]
-    IoError(#[from] std::io::Error),
-    #[error("Expected start tag, found {0:?}")]
-    ExpectedStartTag(String),
-    #[error("Expected end tag {expected}, found {found}")]
-    MismatchedEndTag { expected: String, found: String },
-    #[error("Unexpected end of file")]
-    UnexpectedEof,
-    #[error("Tool name not found")]
-    ToolNameNotFound,
-    #[error("Invalid XML structure")]
-    InvalidStructure,
-    #[error("No tool XML found in the input text")]
-    NoToolXmlFound,
-}
-
-// パースされたツール呼び出しを表す構造体
-#[derive(Debug, PartialEq, Deserialize, Clone)]
-pub struct ToolCall {
-    pub tool_name: String,
-    pub parameters: HashMap<String, String>,
-}
-
-/// LLMの応答テキストから最初のツール呼び出しXMLを抽出しパースする関数
-pub fn parse_tool_call(text: &str) -> Result<ToolCall, ToolParseError> {
-    // 簡易的なXMLブロック抽出（より堅牢な方法も検討可）
-    // < で始まり > で終わるタグを探し、そのタグ名で囲まれたブロックを探す
-    let mut tool_name = None;
-    let mut xml_start_index = None;
-    let mut xml_end_index = None;
-
-    if let Some(start_tag_start) = text.find('<') {
-        if let Some(start_tag_end) = text[start_tag_start..].find('>') {
-            let potential_tool_name = &text[start_tag_start + 1..start_tag_start + start_tag_end];
-            // 簡単のため、パラメータを持たないタグやコメントなどは無視
-            if !potential_tool_name.starts_with('/')
-                && !potential_tool_name.starts_with('?')
-                && !potential_tool_name.starts_with('!')
-                && potential_tool_name.contains(char::is_alphanumeric)
-            {
-                let end_tag = format!("</{}>", potential_tool_name);
-                if let Some(end_tag_start) = text.find(&end_tag) {
-                    tool_name = Some(potential_tool_name.to_string());
-                    xml_start_index = Some(start_tag_start);
-                    xml_end_index = Some(end_tag_start + end_tag.len());
-                }
+use futures::StreamExt;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tool_use_with_xml::stream_to_stream::{ToolCallEvent, stream_to_stream};
+
+/// 標準入力から読み込んだLLMの応答をストリーミングでパースし、イベントを出力する
+///
+/// `cat response.txt | tool_use_with_xml` のようにフィルターとして使用する。
+/// `--json` を指定した場合は、イベントを1行に1つのJSON（JSONL）として出力する。
+#[tokio::main]
+async fn main() {
+    let json = std::env::args().skip(1).any(|arg| arg == "--json");
+
+    // 標準入力を1行ずつ（改行を含めて）ストリームに流す
+    let stdin = BufReader::new(tokio::io::stdin());
+    let input_stream = futures::stream::unfold(stdin, |mut stdin| async move {
+        let mut line = String::new();
+        match stdin.read_line(&mut line).await {
+            Ok(0) => None,
+            Ok(_) => Some((line, stdin)),
+            Err(e) => {
+                eprintln!("標準入力の読み込みに失敗しました: {}", e);
+                None
             }
         }
-    }
-
-    let tool_name = tool_name.ok_or(ToolParseError::NoToolXmlFound)?;
-    let xml_content = &text[xml_start_index.unwrap()..xml_end_index.unwrap()];
-
-    // quick-xml でパース
-    let mut reader = Reader::from_str(xml_content);
-    reader.trim_text(true); // テキスト前後の空白をトリム
-
-    let mut params = HashMap::new();
-    let mut current_param_name: Option<String> = None;
+    })
+    .boxed();
 
-    // ルート要素の開始タグを読み飛ばす
-    loop {
-        match reader.read_event()? {
-            Event::Start(e) if e.name().as_ref() == tool_name.as_bytes() => break,
-            Event::Eof => return Err(ToolParseError::ToolNameNotFound), // 予期せぬ終了
-            _ => {} // 他のイベント（コメントなど）は無視
+    let mut stream = match stream_to_stream(input_stream) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("ストリームの作成に失敗しました: {}", e);
+            std::process::exit(1);
         }
-    }
+    };
 
-    // パラメータ要素を読み取るループ
-    loop {
-        match reader.read_event()? {
-            // パラメータの開始タグ <param_name>
-            Event::Start(e) => {
-                let tag_name = String::from_utf8(e.name().as_ref().to_vec())
-                    .map_err(|_| ToolParseError::InvalidStructure)?; // UTF-8エラーは想定しにくいが念のため
-                current_param_name = Some(tag_name);
+    while let Some(event) = stream.next().await {
+        if json {
+            match serde_json::to_string(&event) {
+                Ok(line) => println!("{}", line),
+                Err(e) => eprintln!("イベントのシリアライズに失敗しました: {}", e),
             }
-            // パラメータの値 (テキスト)
-            Event::Text(e) => {
-                if let Some(param_name) = &current_param_name {
-                    let param_value = e.unescape()?.to_string();
-                    params.insert(param_name.clone(), param_value);
-                }
-            }
-            // パラメータの終了タグ </param_name>
-            Event::End(e) => {
-                if let Some(param_name) = &current_param_name {
-                    let expected_tag_name = param_name.as_bytes();
-                    if e.name().as_ref() != expected_tag_name {
-                        return Err(ToolParseError::MismatchedEndTag {
-                            expected: param_name.clone(),
-                            found: String::from_utf8_lossy(e.name().as_ref()).to_string(),
-                        });
-                    }
-                    current_param_name = None; // 現在のパラメータ処理を終了
-                } else if e.name().as_ref() == tool_name.as_bytes() {
-                    // ルート要素の終了タグ </tool_name> ならループ終了
-                    break;
-                }
-            }
-            // ファイル終端 (予期せぬ終了)
-            Event::Eof => return Err(ToolParseError::UnexpectedEof),
-            _ => {} // 他のイベント (コメント、DTDなど) は無視
+            continue;
         }
-    }
-
-    Ok(ToolCall {
-        tool_name,
-        parameters: params,
-    })
-}
-
-fn main() {
-    let example_text = r#"
-明日のニューヨークの天気ですね。承知いたしました。
-外部の天気予報ツールを使って最新の情報を確認しますね。
-
-<get_weather>
-  <location>New York</location>
-  <date>tomorrow</date>
-  <unit>fahrenheit</unit>
-</get_weather>
-
-結果が取得でき次第、すぐにお知らせします。
-"#;
-
-    match parse_tool_call(example_text) {
-        Ok(tool_call) => {
-            println!("Tool name: {}", tool_call.tool_name);
-            println!("Parameters:");
-            for (key, value) in tool_call.parameters {
-                println!("  {}: {}", key, value);
+        match event {
+            ToolCallEvent::Text(text) => print!("{}", text),
+            ToolCallEvent::ToolStart { id, name } => {
+                println!("\n[ツール開始: {} (ID: {})]", name, id);
             }
-        }
-        Err(e) => eprintln!("Error parsing tool call: {:?}", e),
-    }
-}
-
-// --- テスト ---
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
-
-    use super::*;
-
-    #[test]
-    fn test_parse_get_weather() {
-        let llm_response = r#"
-明日のニューヨークの天気ですね。承知いたしました。
-外部の天気予報ツールを使って最新の情報を確認しますね。
-
-<get_weather>
-  <location>New York</location>
-  <date>tomorrow</date>
-  <unit>fahrenheit</unit>
-</get_weather>
-
-結果が取得でき次第、すぐにお知らせします。
-"#;
-        let expected_params: HashMap<String, String> = [
-            ("location".to_string(), "New York".to_string()),
-            ("date".to_string(), "tomorrow".to_string()),
-            ("unit".to_string(), "fahrenheit".to_string()),
-        ]
-        .iter()
-        .cloned()
-        .collect();
-
-        let expected_tool_call = ToolCall {
-            tool_name: "get_weather".to_string(),
-            parameters: expected_params,
-        };
-
-        match parse_tool_call(llm_response) {
-            Ok(tool_call) => assert_eq!(tool_call, expected_tool_call),
-            Err(e) => panic!("Parse failed: {:?}", e),
-        }
-    }
-
-    #[test]
-    fn test_parse_write_file() {
-        let llm_response = r#"
-Okay, I will write the following content to the file.
-<write_to_file>
-<path>src/main.rs</path>
-<content>
-fn main() {
-    println!("Hello, world!");
-}
-</content>
-</write_to_file>
-Let me know if that looks correct.
-"#;
-        let expected_content = r#"fn main() {
-    println!("Hello, world!");
-}"#;
-        let expected_params: HashMap<String, String> = [
-            ("path".to_string(), "src/main.rs".to_string()),
-            ("content".to_string(), expected_content.to_string()),
-        ]
-        .iter()
-        .cloned()
-        .collect();
-
-        let expected_tool_call = ToolCall {
-            tool_name: "write_to_file".to_string(),
-            parameters: expected_params,
-        };
-
-        match parse_tool_call(llm_response) {
-            Ok(tool_call) => assert_eq!(tool_call, expected_tool_call),
-            Err(e) => panic!("Parse failed: {:?}", e),
-        }
-    }
-
-    #[test]
-    fn test_no_tool_found() {
-        let llm_response = "明日の天気は晴れでしょう。";
-        match parse_tool_call(llm_response) {
-            Err(ToolParseError::NoToolXmlFound) => {} // Expected error
-            Ok(_) => panic!("Should have failed, but parsed successfully."),
-            Err(e) => panic!("Expected NoToolXmlFound, but got {:?}", e),
-        }
-    }
-
-    #[test]
-    fn test_malformed_xml() {
-        let llm_response = "<get_weather><location>New York</date></get_weather>"; // Mismatched tag
-        match parse_tool_call(llm_response) {
-            Err(_) => {} // Expected some error (likely MismatchedEndTag or XmlError)
-            Ok(_) => panic!("Should have failed due to malformed XML."),
+            ToolCallEvent::Parameter { id, arguments } => {
+                println!(
+                    "[パラメータ (ID: {}): {}]",
+                    id,
+                    serde_json::to_string_pretty(&arguments).unwrap_or_default()
+                );
+            }
+            ToolCallEvent::ToolEnd { id } => println!("[ツール終了 (ID: {})]", id),
+            ToolCallEvent::Error(err) => eprintln!("エラー: {}", err),
+            ToolCallEvent::ParameterStart { .. }
+            | ToolCallEvent::ParameterEnd { .. }
+            | ToolCallEvent::ParameterDelta { .. }
+            | ToolCallEvent::TentativeParameter { .. }
+            | ToolCallEvent::SuspiciousValue { .. }
+            | ToolCallEvent::StreamSummary { .. } => {}
         }
     }
 }
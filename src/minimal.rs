@@ -0,0 +1,247 @@
+//! quick-xml を使用しない最小構成のツール呼び出しパーサー
+//!
+//! `minimal` フィーチャーを有効にした場合のみ利用できます。既定の `quick-xml` フィーチャーを無効にしても使えるため、
+//! `--no-default-features --features minimal` で quick-xml に依存しないビルドができます。
+//! 整形式の入力に対して `crate::parse_tool_call` と同じ結果を返すことを目標とした、
+//! WASMなどの軽量ビルド向けの実装です。属性や `ToolParser` のオプションには対応していません。
+
+use crate::{ToolCall, ToolParseError, find_tag_end, find_tool_block, is_default_tool_name};
+use std::collections::HashMap;
+
+/// LLMの応答テキストから最初のツール呼び出しXMLを抽出し、quick-xml を使わずにパースする関数
+pub fn parse_tool_call(text: &str) -> Result<ToolCall, ToolParseError> {
+    let block =
+        find_tool_block(text, 0, is_default_tool_name)?.ok_or(ToolParseError::NoToolXmlFound)?;
+    let xml_content = &text[block.start..block.end];
+
+    // ツールタグの開始タグと終了タグの間を取り出す
+    let body_start = find_tag_end(xml_content).ok_or(ToolParseError::ToolNameNotFound)? + 1;
+    let body_end = xml_content
+        .rfind("</")
+        .ok_or(ToolParseError::UnexpectedEof)?;
+    let body = &xml_content[body_start..body_end];
+    let mut rest = body;
+
+    let mut parameters = HashMap::new();
+    while let Some(tag_start) = rest.find('<') {
+        let tag_len = rest[tag_start..]
+            .find('>')
+            .ok_or(ToolParseError::UnexpectedEof)?;
+        let tag = &rest[tag_start + 1..tag_start + tag_len];
+        let tag_position = block.start + body_start + (body.len() - rest.len()) + tag_start;
+        rest = &rest[tag_start + tag_len + 1..];
+
+        // コメントや処理命令は読み飛ばす
+        if tag.starts_with(['!', '?']) {
+            if let Some(comment) = tag.strip_prefix("!--")
+                && !comment.ends_with("--")
+            {
+                let comment_end = rest.find("-->").ok_or(ToolParseError::UnexpectedEof)?;
+                rest = &rest[comment_end + "-->".len()..];
+            }
+            continue;
+        }
+        if let Some(found) = tag.strip_prefix('/') {
+            return Err(ToolParseError::MismatchedEndTag {
+                expected: block.tool_name,
+                found: found.to_string(),
+                position: tag_position,
+            });
+        }
+        // 空要素 <param/> は値を持たない
+        if tag.ends_with('/') {
+            continue;
+        }
+
+        // 値の終端は対応する終了タグのみとし、途中の `</div>` などはそのまま値に含める
+        let param_name = tag.split(is_xml_whitespace).next().unwrap_or_default();
+        let close_tag = format!("</{}>", param_name);
+        let Some(value_end) = rest.find(&close_tag) else {
+            // パラメータが閉じられないままツールの終了タグに達した
+            let value_start = block.start + body_start + (body.len() - rest.len());
+            let close = rest.find("</").ok_or(ToolParseError::UnexpectedEof)?;
+            let found = rest[close + 2..]
+                .split(|c: char| c == '>' || is_xml_whitespace(c))
+                .next()
+                .unwrap_or_default();
+            return Err(ToolParseError::MismatchedEndTag {
+                expected: param_name.to_string(),
+                found: found.to_string(),
+                position: value_start + close,
+            });
+        };
+
+        let value = rest[..value_end].trim_matches(is_xml_whitespace);
+        if !value.is_empty() {
+            parameters.insert(param_name.to_string(), unescape(value)?);
+        }
+        rest = &rest[value_end + close_tag.len()..];
+    }
+
+    Ok(ToolCall {
+        tool_name: block.tool_name,
+        parameters,
+        attributes: HashMap::new(),
+        spans: HashMap::new(),
+    })
+}
+
+/// XMLの空白文字かどうか（quick-xml のトリムと同じ定義）
+fn is_xml_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\r' | '\n')
+}
+
+/// 定義済み実体参照と文字参照を展開する
+fn unescape(value: &str) -> Result<String, ToolParseError> {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(amp) = rest.find('&') {
+        unescaped.push_str(&rest[..amp]);
+        let semicolon = rest[amp..]
+            .find(';')
+            .ok_or(ToolParseError::InvalidStructure)?;
+        let entity = &rest[amp + 1..amp + semicolon];
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(dec) = entity.strip_prefix('#') {
+                    dec.parse().ok()
+                } else {
+                    None
+                };
+                code.and_then(char::from_u32)
+                    .ok_or(ToolParseError::InvalidStructure)?
+            }
+        };
+        unescaped.push(c);
+        rest = &rest[amp + semicolon + 1..];
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parity_with_quick_xml_backend() {
+        let inputs = [
+            r#"
+明日のニューヨークの天気ですね。承知いたしました。
+
+<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+  <unit>fahrenheit</unit>
+</get_weather>
+
+結果が取得でき次第、すぐにお知らせします。
+"#,
+            r#"
+Okay, I will write the following content to the file.
+<write_to_file>
+<path>src/main.rs</path>
+<content>
+fn main() {
+    println!("Hello, world!");
+}
+</content>
+</write_to_file>
+Let me know if that looks correct.
+"#,
+            "<search><query>a &lt; b &amp;&amp; c &#x3E; d &#12354;</query><!-- comment --></search>",
+            "<tool><xmlns>v</xmlns><empty></empty><self_closing/></tool>",
+            "<web-search><search-query>rust xml</search-query><max.results>5</max.results></web-search>",
+        ];
+        for input in inputs {
+            assert_eq!(
+                parse_tool_call(input).unwrap(),
+                crate::parse_tool_call(input).unwrap(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_foreign_close_tags_kept_verbatim() {
+        let tool_call = parse_tool_call(
+            "<write_to_file><content>return <div><span>x</span></div>;</content></write_to_file>",
+        )
+        .unwrap();
+        assert_eq!(
+            tool_call.parameters["content"],
+            "return <div><span>x</span></div>;"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            parse_tool_call("明日の天気は晴れでしょう。"),
+            Err(ToolParseError::NoToolXmlFound)
+        ));
+        assert!(matches!(
+            parse_tool_call("<get_weather><location>New York</date></get_weather>"),
+            Err(ToolParseError::MismatchedEndTag { .. })
+        ));
+    }
+
+    #[test]
+    fn test_tags_in_values_match_other_parsers() {
+        use crate::stream_to_stream::{ToolCallEvent, parse_str};
+
+        let inputs = [
+            "<write_to_file><content><div>x</div></content></write_to_file>",
+            "<write_to_file><content>a</div>b</content></write_to_file>",
+            "<write_to_file><content>a <div>x</div> b</content></write_to_file>",
+            "<write_to_file><content>const re = /<\\/span>/;</content></write_to_file>",
+        ];
+        for input in inputs {
+            let tool_call = parse_tool_call(input).unwrap();
+            assert_eq!(crate::parse_tool_call(input).unwrap(), tool_call, "{input}");
+            let arguments = parse_str(input).find_map(|event| match event {
+                ToolCallEvent::Parameter { arguments, .. } => Some(arguments),
+                _ => None,
+            });
+            assert_eq!(
+                arguments,
+                Some(serde_json::json!({ "content": tool_call.parameters["content"] })),
+                "{input}"
+            );
+        }
+
+        // パラメータが閉じられないままツールが閉じられた場合は、最初の対応しない終了タグを報告する
+        let input = "<get_weather><location>New York</date></get_weather>";
+        for result in [parse_tool_call(input), crate::parse_tool_call(input)] {
+            assert!(matches!(
+                result,
+                Err(ToolParseError::MismatchedEndTag { expected, found, position: 31 })
+                    if expected == "location" && found == "date"
+            ));
+        }
+        assert_eq!(
+            parse_str(input).collect::<Vec<_>>(),
+            vec![
+                ToolCallEvent::ToolStart {
+                    id: "tool_1".to_string(),
+                    name: "get_weather".to_string(),
+                },
+                ToolCallEvent::Error(
+                    "mismatched end tag: expected </location>, found </date>".to_string()
+                ),
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string(),
+                },
+            ]
+        );
+    }
+}
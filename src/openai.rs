@@ -0,0 +1,133 @@
+//! `async-openai` のストリーミングレスポンスを `stream_to_stream` の入力に変換するアダプタ
+//!
+//! `async-openai` フィーチャーを有効にした場合のみ利用できます。
+//!
+//! # 使用例
+//!
+//! ```rust,ignore
+//! let response_stream = client.chat().create_stream(request).await?;
+//! // 上流のエラーは記録したうえで、そこまでの入力でパースを終える
+//! let contents = openai_content_stream(response_stream)
+//!     .map_while(|content| content.inspect_err(|e| eprintln!("stream error: {e}")).ok())
+//!     .boxed();
+//! let mut events = stream_to_stream(contents)?;
+//! ```
+
+use async_openai::types::chat::CreateChatCompletionStreamResponse;
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use tokio_stream::Stream;
+
+/// OpenAIのストリーミングレスポンスから `choices[].delta.content` の文字列を取り出す
+///
+/// - `n > 1` で複数の候補が返る場合でも、XMLが混ざらないよう `index` が0の候補のみを使用する
+/// - `content` を持たないチャンク（ロールのみ、`tool_calls` のみ等）は読み飛ばす
+/// - エラーを受け取った場合は、そのエラーを `Err` として返してからストリームを終了する
+///
+/// 途中で切れた応答を正常な終了と区別できるよう、エラーは読み捨てずに呼び出し側へ渡す。
+pub fn openai_content_stream<S, E>(responses: S) -> BoxStream<'static, Result<String, E>>
+where
+    S: Stream<Item = Result<CreateChatCompletionStreamResponse, E>> + Send + 'static,
+    E: Send + 'static,
+{
+    responses
+        .scan(false, |failed, response| {
+            // エラーのあとのチャンクは使わない
+            if *failed {
+                return futures::future::ready(None);
+            }
+            *failed = response.is_err();
+            futures::future::ready(Some(response))
+        })
+        .filter_map(|response| {
+            let content = match response {
+                Ok(response) => response
+                    .choices
+                    .into_iter()
+                    .find(|choice| choice.index == 0)
+                    .and_then(|choice| choice.delta.content)
+                    .filter(|content| !content.is_empty())
+                    .map(Ok),
+                Err(e) => Some(Err(e)),
+            };
+            futures::future::ready(content)
+        })
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream_to_stream::{ToolCallEvent, stream_to_stream};
+    use pretty_assertions::assert_eq;
+
+    fn chunk(content: Option<&str>) -> CreateChatCompletionStreamResponse {
+        serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "delta": { "content": content },
+                "finish_reason": null
+            }]
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_openai_content_stream() {
+        let chunks: Vec<Result<_, std::io::Error>> = vec![
+            Ok(chunk(None)),
+            Ok(chunk(Some("天気を調べます。<get_"))),
+            Ok(chunk(Some("weather><location>Tok"))),
+            Ok(chunk(Some("yo</location></get_weather>"))),
+            Ok(chunk(None)),
+        ];
+        let content_stream = openai_content_stream(futures::stream::iter(chunks))
+            .map(Result::unwrap)
+            .boxed();
+
+        let events = stream_to_stream(content_stream)
+            .unwrap()
+            .filter(|event| futures::future::ready(!matches!(event, ToolCallEvent::Text(_))))
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(
+            events,
+            vec![
+                ToolCallEvent::ToolStart {
+                    id: "tool_1".to_string(),
+                    name: "get_weather".to_string(),
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({ "location": "Tokyo" }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_openai_content_stream_surfaces_error() {
+        let chunks = vec![
+            Ok(chunk(Some("abc"))),
+            Err(std::io::Error::other("connection reset")),
+            Ok(chunk(Some("def"))),
+        ];
+        let contents = openai_content_stream(futures::stream::iter(chunks))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents[0].as_ref().unwrap(), "abc");
+        assert_eq!(
+            contents[1].as_ref().unwrap_err().to_string(),
+            "connection reset"
+        );
+    }
+}
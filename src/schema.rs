@@ -0,0 +1,92 @@
+//! ツールごとのパラメータの制約（スキーマ）
+
+use crate::{ToolCall, ToolParseError};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// 1つのツールのパラメータに対する制約
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolSchema {
+    tool_name: String,
+    /// パラメータ名ごとの許可する値の一覧
+    enums: BTreeMap<String, Vec<String>>,
+    /// パラメータの個数
+    param_count: Option<usize>,
+}
+
+impl ToolSchema {
+    pub fn new(tool_name: &str) -> Self {
+        Self {
+            tool_name: tool_name.to_string(),
+            enums: BTreeMap::new(),
+            param_count: None,
+        }
+    }
+
+    /// 対象のツール名
+    pub fn tool_name(&self) -> &str {
+        &self.tool_name
+    }
+
+    /// パラメータが取りうる値を列挙して制限する
+    ///
+    /// パラメータが存在しない場合は検査しない。
+    pub fn enum_param(mut self, key: &str, allowed: &[&str]) -> Self {
+        self.enums.insert(
+            key.to_string(),
+            allowed.iter().map(|value| value.to_string()).collect(),
+        );
+        self
+    }
+
+    /// パラメータの個数を固定する
+    pub fn param_count(mut self, count: usize) -> Self {
+        self.param_count = Some(count);
+        self
+    }
+
+    /// ツール呼び出しが制約を満たすか検査する
+    pub fn validate(&self, tool_call: &ToolCall) -> Result<(), ToolParseError> {
+        self.validate_params(&tool_call.parameters)
+    }
+
+    /// パラメータが制約を満たすか検査する
+    pub(crate) fn validate_params<K>(
+        &self,
+        params: &HashMap<K, String>,
+    ) -> Result<(), ToolParseError>
+    where
+        K: Borrow<str> + Eq + Hash,
+    {
+        if let Some(expected) = self.param_count {
+            let found = params.len();
+            if found < expected {
+                return Err(ToolParseError::TooFewParams {
+                    tool_name: self.tool_name.clone(),
+                    expected,
+                    found,
+                });
+            }
+            if found > expected {
+                return Err(ToolParseError::TooManyParams {
+                    tool_name: self.tool_name.clone(),
+                    expected,
+                    found,
+                });
+            }
+        }
+        for (key, allowed) in &self.enums {
+            if let Some(value) = params.get(key.as_str())
+                && !allowed.contains(value)
+            {
+                return Err(ToolParseError::InvalidEnumValue {
+                    key: key.clone(),
+                    value: value.clone(),
+                    allowed: allowed.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
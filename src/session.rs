@@ -0,0 +1,242 @@
+//! エージェントの1ターン分の応答を、表示用テキストと完成したツール呼び出しに振り分ける
+
+use crate::ToolCall;
+use crate::stream_to_stream::{ToolCallAssembler, ToolCallEvent, XmlParserCore};
+
+/// ストリーミングパーサーを包み、テキストと完成したツール呼び出しを蓄積するセッション
+///
+/// 受信したチャンクを `push_chunk` で渡し、表示用のテキストは `text_so_far`、
+/// 実行すべきツール呼び出しは `take_tool_calls` で取り出す。
+#[derive(Debug, Default)]
+pub struct ToolSession {
+    core: XmlParserCore,
+    assembler: ToolCallAssembler,
+    /// これまでに受信したツール呼び出し以外のテキスト
+    text: String,
+    /// 完成したが、まだ取り出されていないツール呼び出し
+    tool_calls: Vec<ToolCall>,
+}
+
+impl ToolSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 設定済みのパーサー本体でセッションを作成する
+    pub fn with_core(core: XmlParserCore) -> Self {
+        Self {
+            core,
+            ..Self::default()
+        }
+    }
+
+    /// 受信したチャンクを処理する
+    pub fn push_chunk(&mut self, chunk: &str) {
+        for c in chunk.chars() {
+            let event = self.core.process_char(c.encode_utf8(&mut [0; 4]));
+            self.handle(event);
+            let pending = self.core.take_pending_event();
+            self.handle(pending);
+        }
+    }
+
+    /// 完成したツール呼び出しを取り出す。取り出したものは次回以降は返さない
+    pub fn take_tool_calls(&mut self) -> Vec<ToolCall> {
+        std::mem::take(&mut self.tool_calls)
+    }
+
+    /// これまでに受信した、ツール呼び出し以外のテキスト
+    pub fn text_so_far(&self) -> &str {
+        &self.text
+    }
+
+    fn handle(&mut self, event: Option<ToolCallEvent>) {
+        let Some(event) = event else {
+            return;
+        };
+        if let ToolCallEvent::Text(text) = &event {
+            self.text.push_str(text);
+        }
+        if let Some(streamed) = self.assembler.push(&event) {
+            self.tool_calls.push(streamed.call);
+        }
+    }
+}
+
+/// 追記されていく文字列から、完成したツール呼び出しを逐次取り出すパーサー
+///
+/// バッファ全体を毎回 `parse_tool_call` で解析し直す代わりに、追記分だけを `feed` で渡す。
+/// テキストは保持しないため、表示用のテキストも必要な場合は `ToolSession` を使用する。
+#[derive(Debug, Default)]
+pub struct IncrementalParser {
+    core: XmlParserCore,
+    assembler: ToolCallAssembler,
+}
+
+impl IncrementalParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 設定済みのパーサー本体でパーサーを作成する
+    pub fn with_core(core: XmlParserCore) -> Self {
+        Self {
+            core,
+            ..Self::default()
+        }
+    }
+
+    /// 追記されたテキストを処理し、このテキストで終了タグまで揃ったツール呼び出しを返す
+    pub fn feed(&mut self, text: &str) -> Vec<ToolCall> {
+        let mut tool_calls = Vec::new();
+        for c in text.chars() {
+            let event = self.core.process_char(c.encode_utf8(&mut [0; 4]));
+            let pending = self.core.take_pending_event();
+            for event in event.iter().chain(pending.iter()) {
+                tool_calls.extend(self.assembler.push(event).map(|streamed| streamed.call));
+            }
+        }
+        tool_calls
+    }
+}
+
+/// 会話中の連続したアシスタントのメッセージから、ツール呼び出しを取り出すパーサー
+///
+/// 出力の打ち切りと続きの生成により、1つのツール呼び出しが2つのメッセージにまたがる場合でも、
+/// 未完成のツール呼び出しの状態を次のメッセージに引き継いでつなぎ合わせる。
+#[derive(Debug, Default)]
+pub struct ConversationParser {
+    parser: IncrementalParser,
+}
+
+impl ConversationParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 設定済みのパーサー本体でパーサーを作成する
+    pub fn with_core(core: XmlParserCore) -> Self {
+        Self {
+            parser: IncrementalParser::with_core(core),
+        }
+    }
+
+    /// 次のメッセージを処理し、このメッセージで終了タグまで揃ったツール呼び出しを返す
+    pub fn push_message(&mut self, message: &str) -> Vec<ToolCall> {
+        self.parser.feed(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_full_turn() {
+        let chunks = [
+            "明日の天気を確認して",
+            "から保存します。\n<get_weather><loca",
+            "tion>Tokyo</location></get_wea",
+            "ther>\n保存します。\n<write_to_file><path>a.txt</path>",
+            "<content>晴れ</content></write_to_file>\n完了しました。",
+        ];
+        let mut session = ToolSession::new();
+
+        session.push_chunk(chunks[0]);
+        session.push_chunk(chunks[1]);
+        assert!(session.take_tool_calls().is_empty());
+
+        session.push_chunk(chunks[2]);
+        session.push_chunk(chunks[3]);
+        assert_eq!(
+            session.take_tool_calls(),
+            vec![ToolCall {
+                tool_name: "get_weather".to_string(),
+                parameters: HashMap::from([("location".to_string(), "Tokyo".to_string())]),
+                attributes: HashMap::new(),
+                spans: HashMap::new(),
+            }]
+        );
+
+        session.push_chunk(chunks[4]);
+        assert_eq!(
+            session.take_tool_calls(),
+            vec![ToolCall {
+                tool_name: "write_to_file".to_string(),
+                parameters: HashMap::from([
+                    ("path".to_string(), "a.txt".to_string()),
+                    ("content".to_string(), "晴れ".to_string()),
+                ]),
+                attributes: HashMap::new(),
+                spans: HashMap::new(),
+            }]
+        );
+        assert!(session.take_tool_calls().is_empty());
+        assert_eq!(
+            session.text_so_far(),
+            "明日の天気を確認してから保存します。\n\n保存します。\n\n完了しました。"
+        );
+    }
+
+    #[test]
+    fn test_incremental_parser_feed_tokens() {
+        let tokens = [
+            "天気を",
+            "確認します。\n<",
+            "get_",
+            "weather",
+            ">\n  <loc",
+            "ation>New",
+            " York",
+            "</location>\n</get_wea",
+            "ther",
+            ">",
+            "\n完了",
+        ];
+        let mut parser = IncrementalParser::new();
+        let mut tool_calls = Vec::new();
+        for (i, token) in tokens.iter().enumerate() {
+            let completed = parser.feed(token);
+            // 終了タグの最後の `>` を受け取るまではツール呼び出しを返さない
+            assert_eq!(completed.is_empty(), i != 9, "token {i}");
+            tool_calls.extend(completed);
+        }
+        assert_eq!(
+            tool_calls,
+            vec![ToolCall {
+                tool_name: "get_weather".to_string(),
+                parameters: HashMap::from([("location".to_string(), "New York".to_string())]),
+                attributes: HashMap::new(),
+                spans: HashMap::new(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_conversation_parser_stitches_messages() {
+        let mut parser = ConversationParser::new();
+
+        // 1つ目のメッセージはパラメータの値の途中で打ち切られている
+        let completed = parser.push_message(
+            "ファイルを保存します。\n<write_to_file><path>a.txt</path><content>1行目\n2行",
+        );
+        assert!(completed.is_empty());
+
+        let completed = parser.push_message("目</content></write_to_file>\n保存しました。");
+        assert_eq!(
+            completed,
+            vec![ToolCall {
+                tool_name: "write_to_file".to_string(),
+                parameters: HashMap::from([
+                    ("path".to_string(), "a.txt".to_string()),
+                    ("content".to_string(), "1行目\n2行目".to_string()),
+                ]),
+                attributes: HashMap::new(),
+                spans: HashMap::new(),
+            }]
+        );
+        assert!(parser.push_message("次のメッセージ").is_empty());
+    }
+}
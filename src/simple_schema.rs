@@ -0,0 +1,245 @@
+//! 簡易スキーマによるツール呼び出しのパラメータの検証
+//!
+//! 簡易スキーマは、ツールの定義に使われるJSON Schemaの書式のうち、次のキーワードだけを使うスキーマです。
+//! JSON Schemaの検証器ではなく、任意のJSON Schemaを正しく検証できるわけではありません。
+//!
+//! - `required`、`properties`、`additionalProperties: false`
+//! - 各プロパティの `type`、`enum`、`minLength`、`maxLength`
+//!
+//! パラメータの値はすべて文字列のため、`type` が文字列以外の場合は値をその型として解釈できるかで判定します。
+//!
+//! `pattern`、`minimum`、`items`、`oneOf`、`$ref`、`format` など上記以外の検証キーワードを含むスキーマは、
+//! 検証を黙って省略せずに違反として報告します。`title` や `description` などの注釈は無視します。
+
+use crate::ToolCall;
+use serde_json::Value;
+
+// 検証に影響しない注釈のキーワード
+const ANNOTATIONS: &[&str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "title",
+    "description",
+    "default",
+    "examples",
+];
+
+// スキーマ全体で対応しているキーワード
+const SCHEMA_KEYWORDS: &[&str] = &["type", "required", "properties", "additionalProperties"];
+
+// 各プロパティで対応しているキーワード
+const PROPERTY_KEYWORDS: &[&str] = &["type", "enum", "minLength", "maxLength"];
+
+/// ツール呼び出しのパラメータが簡易スキーマを満たすか検証し、違反をすべて返す
+pub fn validate_simple_schema(tool_call: &ToolCall, schema: &Value) -> Result<(), Vec<String>> {
+    let mut violations = unsupported_keywords(schema);
+    if !violations.is_empty() {
+        return Err(violations);
+    }
+
+    if let Some(required) = schema.get("required").and_then(Value::as_array) {
+        for key in required.iter().filter_map(Value::as_str) {
+            if !tool_call.parameters.contains_key(key) {
+                violations.push(format!("missing required parameter {key:?}"));
+            }
+        }
+    }
+
+    let properties = schema.get("properties").and_then(Value::as_object);
+    let deny_additional = schema.get("additionalProperties") == Some(&Value::Bool(false));
+    let mut parameters: Vec<_> = tool_call.parameters.iter().collect();
+    parameters.sort();
+    for (key, value) in parameters {
+        match properties.and_then(|properties| properties.get(key)) {
+            Some(property) => check_property(key, value, property, &mut violations),
+            None if deny_additional => violations.push(format!("unexpected parameter {key:?}")),
+            None => {}
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+/// この実装が検証できないキーワードを列挙する
+fn unsupported_keywords(schema: &Value) -> Vec<String> {
+    let mut unsupported = Vec::new();
+    let Some(schema) = schema.as_object() else {
+        return vec!["schema must be an object".to_string()];
+    };
+    for (keyword, value) in schema {
+        if ANNOTATIONS.contains(&keyword.as_str()) {
+            continue;
+        }
+        let supported = match keyword.as_str() {
+            "type" => value == "object",
+            "additionalProperties" => value.is_boolean(),
+            keyword => SCHEMA_KEYWORDS.contains(&keyword),
+        };
+        if !supported {
+            unsupported.push(format!("unsupported schema keyword {keyword:?}"));
+        }
+    }
+    if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+        for (key, property) in properties {
+            let Some(property) = property.as_object() else {
+                unsupported.push(format!("property {key:?}: schema must be an object"));
+                continue;
+            };
+            for keyword in property.keys() {
+                if !ANNOTATIONS.contains(&keyword.as_str())
+                    && !PROPERTY_KEYWORDS.contains(&keyword.as_str())
+                {
+                    unsupported.push(format!(
+                        "property {key:?}: unsupported schema keyword {keyword:?}"
+                    ));
+                }
+            }
+        }
+    }
+    unsupported
+}
+
+/// 1つのパラメータの値をプロパティのスキーマで検証する
+fn check_property(key: &str, value: &str, property: &Value, violations: &mut Vec<String>) {
+    let types: Vec<&str> = match property.get("type") {
+        Some(Value::String(ty)) => vec![ty.as_str()],
+        Some(Value::Array(types)) => types.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if !types.is_empty() && !types.iter().any(|ty| matches_type(value, ty)) {
+        violations.push(format!(
+            "parameter {key:?}: {value:?} is not of type {}",
+            types.join(" or ")
+        ));
+    }
+
+    // 文字列以外の列挙値は、値をJSONとして解釈して比較する
+    let parsed = serde_json::from_str::<Value>(value.trim()).ok();
+    if let Some(allowed) = property.get("enum").and_then(Value::as_array)
+        && !allowed
+            .iter()
+            .any(|allowed| allowed.as_str() == Some(value) || parsed.as_ref() == Some(allowed))
+    {
+        violations.push(format!(
+            "parameter {key:?}: {value:?} is not one of {}",
+            Value::Array(allowed.clone())
+        ));
+    }
+
+    let len = value.chars().count() as u64;
+    if let Some(min) = property.get("minLength").and_then(Value::as_u64)
+        && len < min
+    {
+        violations.push(format!("parameter {key:?}: shorter than {min} characters"));
+    }
+    if let Some(max) = property.get("maxLength").and_then(Value::as_u64)
+        && len > max
+    {
+        violations.push(format!("parameter {key:?}: longer than {max} characters"));
+    }
+}
+
+/// 文字列の値を、JSON Schema の型として解釈できるかどうか
+fn matches_type(value: &str, ty: &str) -> bool {
+    if ty == "string" {
+        return true;
+    }
+    let Ok(parsed) = serde_json::from_str::<Value>(value.trim()) else {
+        return false;
+    };
+    match ty {
+        "integer" => parsed.is_i64() || parsed.is_u64(),
+        "number" => parsed.is_number(),
+        "boolean" => parsed.is_boolean(),
+        "null" => parsed.is_null(),
+        "object" => parsed.is_object(),
+        "array" => parsed.is_array(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_tool_call;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_simple_schema() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "location": { "type": "string" },
+                "days": { "type": "integer" },
+                "unit": { "type": "string", "enum": ["celsius", "fahrenheit"] }
+            },
+            "required": ["location"]
+        });
+
+        let tool_call = parse_tool_call(
+            "<get_weather><location>Tokyo</location><days>3</days><unit>celsius</unit></get_weather>",
+        )
+        .unwrap();
+        assert_eq!(validate_simple_schema(&tool_call, &schema), Ok(()));
+
+        // 違反はまとめて返す
+        let tool_call =
+            parse_tool_call("<get_weather><days>three</days><unit>kelvin</unit></get_weather>")
+                .unwrap();
+        let violations = validate_simple_schema(&tool_call, &schema).unwrap_err();
+        assert_eq!(
+            violations,
+            vec![
+                r#"missing required parameter "location""#.to_string(),
+                r#"parameter "days": "three" is not of type integer"#.to_string(),
+                r#"parameter "unit": "kelvin" is not one of ["celsius","fahrenheit"]"#.to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_keywords_are_reported() {
+        let tool_call =
+            parse_tool_call("<get_weather><location>Tokyo</location></get_weather>").unwrap();
+
+        // 注釈は検証に影響しない
+        let schema = json!({
+            "title": "get_weather",
+            "type": "object",
+            "properties": {
+                "location": { "type": "string", "description": "city name" }
+            },
+            "additionalProperties": false
+        });
+        assert_eq!(validate_simple_schema(&tool_call, &schema), Ok(()));
+
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "location": { "type": "string", "pattern": "^[A-Z]" },
+                "days": { "type": "integer", "minimum": 1 },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "unit": { "$ref": "#/definitions/unit" }
+            },
+            "oneOf": [{ "required": ["location"] }],
+            "additionalProperties": { "type": "string" }
+        });
+        assert_eq!(
+            validate_simple_schema(&tool_call, &schema),
+            Err(vec![
+                r#"unsupported schema keyword "oneOf""#.to_string(),
+                r#"unsupported schema keyword "additionalProperties""#.to_string(),
+                r#"property "location": unsupported schema keyword "pattern""#.to_string(),
+                r#"property "days": unsupported schema keyword "minimum""#.to_string(),
+                r#"property "tags": unsupported schema keyword "items""#.to_string(),
+                r#"property "unit": unsupported schema keyword "$ref""#.to_string(),
+            ])
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! ツール呼び出しイベントを Server-Sent Events (SSE) の形式に変換する
+
+use crate::stream_to_stream::ToolCallEvent;
+use futures::{Stream, StreamExt};
+use serde_json::json;
+
+/// イベントストリームを、SSEのフレーム文字列のストリームに変換する
+///
+/// 各フレームは `event: <種類>\ndata: <データ>\n\n` の形式。`Text` はテキストをそのまま、
+/// それ以外は整形したJSONをデータとし、複数行のデータは行ごとに `data:` 行へ分割する。
+pub fn events_to_sse<S>(events: S) -> impl Stream<Item = String>
+where
+    S: Stream<Item = ToolCallEvent>,
+{
+    events.map(|event| to_sse_frame(&event))
+}
+
+/// イベントを1つのSSEフレームに変換する
+pub fn to_sse_frame(event: &ToolCallEvent) -> String {
+    let (event_type, data) = match event {
+        ToolCallEvent::Text(text) => ("text", text.clone()),
+        ToolCallEvent::ToolStart { id, name } => {
+            ("tool_start", pretty(json!({ "id": id, "name": name })))
+        }
+        ToolCallEvent::Parameter { id, arguments } => (
+            "parameter",
+            pretty(json!({ "id": id, "arguments": arguments })),
+        ),
+        ToolCallEvent::ParameterStart { id, name } => {
+            ("parameter_start", pretty(json!({ "id": id, "name": name })))
+        }
+        ToolCallEvent::ParameterEnd { id, name } => {
+            ("parameter_end", pretty(json!({ "id": id, "name": name })))
+        }
+        ToolCallEvent::ParameterDelta { id, name, delta } => (
+            "parameter_delta",
+            pretty(json!({ "id": id, "name": name, "delta": delta })),
+        ),
+        ToolCallEvent::TentativeParameter { id, arguments } => (
+            "tentative_parameter",
+            pretty(json!({ "id": id, "arguments": arguments })),
+        ),
+        ToolCallEvent::ToolEnd { id } => ("tool_end", pretty(json!({ "id": id }))),
+        ToolCallEvent::Error(message) => ("error", pretty(json!({ "message": message }))),
+        ToolCallEvent::SuspiciousValue { id, param, snippet } => (
+            "suspicious_value",
+            pretty(json!({ "id": id, "param": param, "snippet": snippet })),
+        ),
+        ToolCallEvent::StreamSummary {
+            tools,
+            params,
+            text_chars,
+        } => (
+            "stream_summary",
+            pretty(json!({ "tools": tools, "params": params, "text_chars": text_chars })),
+        ),
+    };
+
+    let mut frame = format!("event: {}\n", event_type);
+    // SSEの仕様では CR、LF、CRLF のいずれも改行として扱われる
+    for line in data.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+        frame.push_str("data: ");
+        frame.push_str(line);
+        frame.push('\n');
+    }
+    frame.push('\n');
+    frame
+}
+
+fn pretty(value: serde_json::Value) -> String {
+    serde_json::to_string_pretty(&value).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_events_to_sse() {
+        let events = futures::stream::iter(vec![
+            ToolCallEvent::Text("了解です。\n確認します。".to_string()),
+            ToolCallEvent::ToolStart {
+                id: "tool_1".to_string(),
+                name: "write_to_file".to_string(),
+            },
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: json!({ "content": "line1\nline2" }),
+            },
+        ]);
+        let frames: Vec<_> = events_to_sse(events).collect().await;
+
+        assert_eq!(
+            frames,
+            vec![
+                "event: text\ndata: 了解です。\ndata: 確認します。\n\n",
+                "event: tool_start\ndata: {\ndata:   \"id\": \"tool_1\",\ndata:   \"name\": \"write_to_file\"\ndata: }\n\n",
+                "event: parameter\ndata: {\ndata:   \"id\": \"tool_1\",\ndata:   \"arguments\": {\ndata:     \"content\": \"line1\\nline2\"\ndata:   }\ndata: }\n\n",
+            ]
+        );
+    }
+}
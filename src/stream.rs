@@ -1,9 +1,11 @@
+//! quick-xml を用いた、イベントごとにバッファを読み進めるストリーミングパーサー
+
+use crate::ReaderConfig;
 use quick_xml::Reader;
 use quick_xml::events::Event;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio_stream::Stream;
-use tokio_stream::StreamExt;
 
 // ストリーミングイベントを表すenum
 #[derive(Debug, Clone)]
@@ -40,6 +42,9 @@ pub struct ToolCallStream {
     position: usize,
     state: ParserState,
     current_tool: Option<String>,
+    reader_config: ReaderConfig,
+    // 末尾で途切れたUTF-8の文字のバイト列。次の push_data で続きを受け取るまでパーサーに渡さない
+    incomplete_utf8: Vec<u8>,
 }
 
 #[derive(Debug, Clone)]
@@ -72,21 +77,37 @@ impl ToolCallStream {
             position: 0,
             state: ParserState::Initial,
             current_tool: None,
+            reader_config: ReaderConfig::default(),
+            incomplete_utf8: Vec::new(),
         }
     }
 
+    /// quick-xml の `Reader` の設定を変更する
+    pub fn reader_config(mut self, config: ReaderConfig) -> Self {
+        self.reader_config = config;
+        self
+    }
+
     pub fn push_data(&mut self, data: &[u8]) {
         if self.position > 0 && self.position == self.buffer.len() {
             self.buffer.clear();
             self.position = 0;
         }
-        self.buffer.extend_from_slice(data);
+        let mut bytes = std::mem::take(&mut self.incomplete_utf8);
+        bytes.extend_from_slice(data);
+        // 末尾の文字が途切れている場合のみ保留する。不正なバイト列はそのまま渡してパーサーに任せる
+        let complete_len = match std::str::from_utf8(&bytes) {
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            _ => bytes.len(),
+        };
+        self.incomplete_utf8 = bytes.split_off(complete_len);
+        self.buffer.extend_from_slice(&bytes);
     }
 
     fn get_reader(&self) -> Reader<&[u8]> {
-        let mut reader = Reader::from_reader(&self.buffer[self.position..]);
-        reader.trim_text(true);
-        reader.check_end_names(false);
+        let remaining = self.buffer.get(self.position..).unwrap_or_default();
+        let mut reader = Reader::from_reader(remaining);
+        self.reader_config.apply(&mut reader);
         reader
     }
 
@@ -151,6 +172,40 @@ impl ToolCallStream {
         }
     }
 
+    // 読み取り位置を進める。バッファ末尾を超える場合は末尾に丸めてエラーを返す
+    fn advance(&mut self, size: usize) -> Result<(), XmlError> {
+        match self.position.checked_add(size) {
+            Some(position) if position <= self.buffer.len() => {
+                self.position = position;
+                Ok(())
+            }
+            _ => {
+                let position = self.position;
+                self.position = self.buffer.len();
+                Err(XmlError::Other(format!(
+                    "read position overflow: {} + {} exceeds buffer length {}",
+                    position,
+                    size,
+                    self.buffer.len()
+                )))
+            }
+        }
+    }
+
+    // Eof 以外のイベントで読み取り位置が進まないまま次のイベントを読むと、同じイベントを無限に処理し続ける
+    fn consume(&mut self, consumed: usize, kind: &str) -> Result<(), XmlError> {
+        if consumed == 0 {
+            let position = self.position;
+            let remaining = self.buffer.len() - position;
+            self.position = self.buffer.len();
+            return Err(XmlError::Other(format!(
+                "read position did not advance at {} ({} event, {} bytes remaining)",
+                position, kind, remaining
+            )));
+        }
+        self.advance(consumed)
+    }
+
     fn apply_update(&mut self, update: StateUpdate) {
         self.state = update.new_state;
         if let Some(tool) = update.new_tool {
@@ -159,6 +214,22 @@ impl ToolCallStream {
     }
 }
 
+// エラーメッセージ用のイベントの種類名
+fn event_kind(event: &Event) -> &'static str {
+    match event {
+        Event::Start(_) => "Start",
+        Event::End(_) => "End",
+        Event::Empty(_) => "Empty",
+        Event::Text(_) => "Text",
+        Event::CData(_) => "CData",
+        Event::Comment(_) => "Comment",
+        Event::Decl(_) => "Decl",
+        Event::PI(_) => "PI",
+        Event::DocType(_) => "DocType",
+        Event::Eof => "Eof",
+    }
+}
+
 impl Stream for ToolCallStream {
     type Item = Result<ToolCallEvent, XmlError>;
 
@@ -170,28 +241,35 @@ impl Stream for ToolCallStream {
         let mut reader = self.get_reader();
         let mut buf = Vec::new();
 
-        match reader.read_event_into(&mut buf) {
+        let read = reader.read_event_into(&mut buf);
+        let consumed = reader.buffer_position();
+        match read {
             Ok(event) => {
                 let current_state = self.state.clone();
                 let update = self.process_event(&event, &current_state);
                 let result = update.event.clone();
 
-                let event_size = match &event {
-                    Event::Text(e) => e.as_ref().len(),
-                    Event::Start(e) => {
-                        let name_ref = e.name();
-                        let name_bytes = name_ref.as_ref();
-                        name_bytes.len() + 2 // < + name + >
-                    }
-                    Event::End(e) => {
-                        let name_ref = e.name();
-                        let name_bytes = name_ref.as_ref();
-                        name_bytes.len() + 3 // </ + name + >
-                    }
-                    Event::Eof => 0,
-                    _ => 1,
+                if matches!(event, Event::Eof) {
+                    // 末尾の空白だけが残っている場合も正常な終端として扱う
+                    self.position = self.buffer.len();
+                    return Poll::Ready(None);
+                }
+
+                // quick-xml は入力の末尾で途切れたタグやテキストも完結したイベントとして返すため、
+                // タグは `>` まで、テキストは次の `<` までを受信するまでは読み進めない
+                let remaining = &self.buffer[self.position..];
+                let complete = match event {
+                    Event::Text(_) => remaining.get(consumed) == Some(&b'<'),
+                    _ => remaining.get(consumed.wrapping_sub(1)) == Some(&b'>'),
                 };
-                self.position += event_size;
+                if consumed > 0 && !complete {
+                    return Poll::Ready(None);
+                }
+
+                let kind = event_kind(&event);
+                if let Err(e) = self.consume(consumed, kind) {
+                    return Poll::Ready(Some(Err(e)));
+                }
 
                 self.apply_update(update);
 
@@ -206,30 +284,6 @@ impl Stream for ToolCallStream {
     }
 }
 
-#[tokio::main]
-async fn main() {
-    let xml = r#"<get_weather><location>Tokyo</location><date>2024-03-21</date></get_weather>"#;
-
-    let stream = ToolCallStream::new(xml.as_bytes());
-    let mut stream = Box::pin(stream);
-
-    while let Some(result) = stream.next().await {
-        match result {
-            Ok(event) => match event {
-                ToolCallEvent::ToolStart(name) => println!("ツール開始: {}", name),
-                ToolCallEvent::Parameter { name, value } => {
-                    println!("パラメータ: {} = {}", name, value)
-                }
-                ToolCallEvent::ToolEnd => println!("ツール終了"),
-                ToolCallEvent::Error(err) => println!("ツールエラー: {}", err),
-            },
-            Err(e) => println!("エラー: {}", e),
-        }
-    }
-
-    println!("---イベントストリーム終了---");
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -260,4 +314,128 @@ mod tests {
         );
         assert!(matches!(events[2], Ok(ToolCallEvent::ToolEnd)));
     }
+
+    #[tokio::test]
+    async fn test_push_data_split_inside_multibyte_char() {
+        let xml = "<get_weather><location>東京都</location></get_weather>".as_bytes();
+        // 「東」の2バイト目で分割する
+        let split = "<get_weather><location>".len() + 1;
+        let mut stream = ToolCallStream::new(b"");
+
+        stream.push_data(&xml[..split]);
+        let mut events = Vec::new();
+        while let Some(event) = stream.next().await {
+            events.push(event);
+        }
+        stream.push_data(&xml[split..]);
+        while let Some(event) = stream.next().await {
+            events.push(event);
+        }
+
+        assert!(
+            matches!(events[1], Ok(ToolCallEvent::Parameter { ref name, ref value })
+            if name == "location" && value == "東京都")
+        );
+        assert!(matches!(events.last(), Some(Ok(ToolCallEvent::ToolEnd))));
+    }
+
+    #[tokio::test]
+    async fn test_only_opening_tool_tag() {
+        // 開始タグだけで入力が終わった場合は、ToolStart のあとに何も発行せずに終了する
+        let stream = ToolCallStream::new(b"<get_weather>");
+        let events: Vec<_> = stream.collect().await;
+        assert_eq!(events.len(), 1);
+        assert!(
+            matches!(events[0], Ok(ToolCallEvent::ToolStart(ref name)) if name == "get_weather")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_advance_past_buffer_end_is_recoverable() {
+        let mut stream = ToolCallStream::new(b"<get_weather>");
+
+        // イベントサイズの計算ミスを想定し、バッファ長を超えて進めようとする
+        let result = stream.advance(stream.buffer.len() + 1);
+        assert!(matches!(result, Err(XmlError::Other(_))));
+        assert_eq!(stream.position, stream.buffer.len());
+        assert!(stream.advance(usize::MAX).is_err());
+
+        // パニックせず、後続のデータを受け付けて処理を続けられる
+        stream.push_data(b"<get_weather><location>Tokyo</location></get_weather>");
+        let events: Vec<_> = stream.collect().await;
+        assert!(
+            matches!(events[0], Ok(ToolCallEvent::ToolStart(ref name)) if name == "get_weather")
+        );
+        assert!(matches!(events.last(), Some(Ok(ToolCallEvent::ToolEnd))));
+    }
+
+    #[tokio::test]
+    async fn test_hyphen_and_dot_names() {
+        let stream = ToolCallStream::new(
+            b"<web-search><search-query>rust</search-query><max.results>5</max.results></web-search>",
+        );
+        let events: Vec<_> = stream.collect().await;
+        assert!(
+            matches!(events[0], Ok(ToolCallEvent::ToolStart(ref name)) if name == "web-search")
+        );
+        assert!(
+            matches!(events[1], Ok(ToolCallEvent::Parameter { ref name, ref value })
+            if name == "search-query" && value == "rust")
+        );
+        assert!(
+            matches!(events[2], Ok(ToolCallEvent::Parameter { ref name, ref value })
+            if name == "max.results" && value == "5")
+        );
+        assert!(matches!(events[3], Ok(ToolCallEvent::ToolEnd)));
+    }
+
+    #[tokio::test]
+    async fn test_trailing_whitespace_ends_cleanly() {
+        let stream =
+            ToolCallStream::new(b"<get_weather><location>Tokyo</location></get_weather>\n  \n");
+        let events: Vec<_> = stream.collect().await;
+        assert_eq!(events.len(), 3);
+        assert!(events.iter().all(|event| event.is_ok()));
+        assert!(matches!(events[2], Ok(ToolCallEvent::ToolEnd)));
+    }
+
+    #[tokio::test]
+    async fn test_non_advancing_event_is_reported() {
+        // quick-xml は末尾の `>` だけのテキストを、読み取り位置を進めずに返す
+        let mut stream = ToolCallStream::new(b"<get_weather>>");
+        assert!(matches!(
+            stream.next().await,
+            Some(Ok(ToolCallEvent::ToolStart(_)))
+        ));
+        assert!(
+            matches!(stream.next().await, Some(Err(XmlError::Other(message)))
+            if message.contains("did not advance at 13 (Text event, 1 bytes remaining)"))
+        );
+        assert_eq!(stream.position, 14);
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_incomplete_tail_waits_for_more_data() {
+        // 途切れた開始タグ `<loc` をパラメータ名と値として扱わない
+        let mut stream = ToolCallStream::new(b"<get_weather><loc");
+        assert!(matches!(
+            stream.next().await,
+            Some(Ok(ToolCallEvent::ToolStart(_)))
+        ));
+        assert!(stream.next().await.is_none());
+
+        // 途切れたテキストも、続きを受信してから1つの値として発行する
+        stream.push_data(b"ation>Tok");
+        assert!(stream.next().await.is_none());
+        stream.push_data(b"yo</location></get_weather>");
+        let events: Vec<_> = stream.collect().await;
+        assert!(
+            matches!(&events[..], [
+            Ok(ToolCallEvent::Parameter { name, value }),
+            Ok(ToolCallEvent::ToolEnd),
+        ] if name == "location" && value == "Tokyo"),
+            "{events:?}"
+        );
+    }
 }
@@ -9,11 +9,13 @@
 //! - ツール呼び出しの開始・終了の検出
 //! - パラメータの収集と構造化
 //! - イベントの生成と配信
+//! - 入力全体が手元にある場合の同期パース（`parse_str`）
 //!
 //! # イベントの種類
 //!
 //! - `ToolStart`: ツール呼び出しの開始
 //! - `Parameter`: ツールのパラメータ
+//! - `ParameterStart` / `ParameterEnd`: パラメータタグの開始・終了（オプトイン）
 //! - `ToolEnd`: ツール呼び出しの終了
 //! - `Text`: XMLタグ以外のテキスト
 //! - `Error`: エラー発生時のイベント
@@ -22,7 +24,10 @@
 //!
 //! ```rust
 //! use futures::StreamExt;
+//! use tool_use_with_xml::stream_to_stream::{ToolCallEvent, stream_to_stream};
 //!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
 //! let input = r#"<get_weather>
 //!   <location>Tokyo</location>
 //!   <date>tomorrow</date>
@@ -38,14 +43,31 @@
 //!         ToolCallEvent::ToolEnd { id } => println!("ツール終了 (ID: {})", id),
 //!         ToolCallEvent::Text(text) => print!("{}", text),
 //!         ToolCallEvent::Error(err) => eprintln!("エラー: {}", err),
+//!         _ => {}
 //!     }
 //! }
+//! # Ok(())
+//! # }
 //! ```
 
+use crate::{Delimiters, ToolCall, WhitespaceMode, is_default_tool_name, strip_code_fence};
 use futures::StreamExt;
+use futures::future::BoxFuture;
 use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, RandomState};
+use std::io::Write;
+use std::ops::Range;
+use std::path::PathBuf;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::time::{Interval, MissedTickBehavior};
 use tokio_stream::Stream;
 
 type Result<T> = std::result::Result<T, ToolCallStreamError>;
@@ -57,11 +79,16 @@ pub enum ToolCallStreamError {
     IoError(#[from] std::io::Error),
     #[error("Unexpected end of file")]
     UnexpectedEof,
+    #[error("Event buffer overflow: capacity {0}")]
+    BufferOverflow(usize),
 }
 
 /// ストリーミングイベントを表すenum
 /// XMLの解析結果を表現するために使用される
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// JSONには `{"type": "tool_start", "data": {"id": ..., "name": ...}}` の形式でシリアライズされる。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum ToolCallEvent {
     /// テキストイベント：XMLタグ以外のテキストを表す
     Text(String),
@@ -72,17 +99,50 @@ pub enum ToolCallEvent {
         id: String,
         arguments: serde_json::Value,
     },
+    /// パラメータの開始：<param_name>タグの検出（`parameter_events(true)` の場合のみ）
+    ParameterStart { id: String, name: String },
+    /// パラメータの終了：</param_name>タグの検出（`parameter_events(true)` の場合のみ）
+    ParameterEnd { id: String, name: String },
+    /// パラメータの値の差分：前回の差分以降に確定した値（`parameter_deltas(true)` の場合のみ）
+    ///
+    /// 同じパラメータの差分をつなげると、`Parameter` イベントの値と同じになる。
+    ParameterDelta {
+        id: String,
+        name: String,
+        delta: String,
+    },
     /// ツール呼び出しの終了：</tool_name>タグの検出
     ToolEnd { id: String },
     /// エラーイベント：処理中に発生したエラー
     Error(String),
+    /// 受信途中のパラメータの暫定値：確定済みのパラメータと受信途中の値のスナップショット
+    /// （`debounce_params` で一定時間入力が途絶えた場合のみ）。確定値は後続の `Parameter` イベントで届く
+    TentativeParameter {
+        id: String,
+        arguments: serde_json::Value,
+    },
+    /// 不審なパラメータの値：実体参照の展開後の値に `<` か `>` を含む（`warn_suspicious_values(true)` の場合のみ）
+    ///
+    /// エスケープ漏れやタグの入れ子の疑いがあることを示す警告で、値自体は `Parameter` イベントで届く。
+    /// `snippet` は最初の `<` または `>` からの最大40文字。
+    SuspiciousValue {
+        id: String,
+        param: String,
+        snippet: String,
+    },
+    /// 入力終了時の集計：ツール数、パラメータ数、テキストの文字数（`summary_event(true)` の場合のみ）
+    StreamSummary {
+        tools: usize,
+        params: usize,
+        text_chars: usize,
+    },
 }
 
-type ToolCallStream = BoxStream<'static, ToolCallEvent>;
-type ToolCallStreamResult = Result<ToolCallStream>;
+pub type ToolCallStream = BoxStream<'static, ToolCallEvent>;
+pub type ToolCallStreamResult = Result<ToolCallStream>;
 
 /// パーサーの状態を表すenum
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 enum ParserState {
     /// 通常状態：XMLタグ外
     Normal,
@@ -92,24 +152,209 @@ enum ParserState {
     InToolTag,
     /// パラメータタグ内：<param_name> と </param_name> の間
     InParameterTag,
+    /// `validate_tool` で拒否したツールの内側：対応する終了タグまで読み飛ばす
+    SkippingTool,
 }
 
-/// XMLストリームをイベントストリームに変換するための構造体
-struct XmlStreamParser {
-    /// 入力ストリーム
-    input: BoxStream<'static, String>,
+/// パラメータの値。`spill_params` で一時ファイルに書き出した値は `Spilled` になる
+///
+/// `Parameter` イベントの `arguments` の各値から `serde_json::from_value` で変換できる。
+/// 書き出した値は `{"spilled": "<パス>"}` として `arguments` に格納される。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParamValue {
+    /// 一時ファイルに書き出した値のパス。前後の空白はトリムせずにそのまま書き出す
+    #[serde(rename = "spilled")]
+    Spilled(PathBuf),
+    /// メモリ上に保持した値
+    #[serde(untagged)]
+    Inline(String),
+}
+
+/// パラメータの値を一時ファイルに書き出す設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SpillConfig {
+    /// この長さ（バイト）を超えた値を書き出す
+    threshold: usize,
+    /// 一時ファイルを作成するディレクトリ
+    dir: PathBuf,
+}
+
+/// パラメータの値に含まれる実体参照（`&lt;` `&gt;` `&amp;` `&quot;` `&apos;` と文字参照）を展開する
+///
+/// 一括パーサーと同じ結果になるようにする。未知の実体参照はそのまま残す。
+/// `decode_entities` が解釈する実体参照の最大の長さ（`&#x10FFFF;` など）
+const MAX_ENTITY_LEN: usize = 16;
+
+fn decode_entities(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let c = rest.find(';').and_then(|semicolon| {
+            let c = match &rest[1..semicolon] {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                entity => entity
+                    .strip_prefix("#x")
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| entity.strip_prefix('#').map(str::parse))
+                    .and_then(|code| code.ok())
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, semicolon))
+        });
+        match c {
+            Some((c, semicolon)) => {
+                decoded.push(c);
+                rest = &rest[semicolon + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// 開始タグの内容（`header key="A"`）から、引用符で囲まれた属性の値を取り出す
+fn tag_attribute(tag: &str, attr: &str) -> Option<String> {
+    let mut rest = tag.split_once(char::is_whitespace)?.1;
+    loop {
+        let (name, after) = rest.split_once('=')?;
+        let after = after.trim_start();
+        let quote = after.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+        let (value, remaining) = after[1..].split_once(quote)?;
+        if name.trim() == attr {
+            return Some(decode_entities(value));
+        }
+        rest = remaining;
+    }
+}
+
+/// タグの内容（区切り文字の内側）が、要素名と `属性名="値"` の組だけからなる開始タグかどうか
+///
+/// 値の中の `a<b && c>d` のような比較演算子を、タグと誤認しないために使用する。
+fn is_start_tag(tag: &str) -> bool {
+    let is_name = |name: &str| {
+        is_default_tool_name(name)
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'))
+    };
+    let tag = tag.strip_suffix('/').unwrap_or(tag);
+    let (name, mut rest) = tag.split_at(tag.find(char::is_whitespace).unwrap_or(tag.len()));
+    if !is_name(name) {
+        return false;
+    }
+    loop {
+        let attrs = rest.trim_start();
+        if attrs.is_empty() {
+            return true;
+        }
+        // 属性の前には空白が必要
+        if attrs.len() == rest.len() {
+            return false;
+        }
+        let Some((attr, value)) = attrs.split_once('=') else {
+            return false;
+        };
+        let value = value.trim_start();
+        let Some(quote) = value.chars().next().filter(|c| matches!(c, '"' | '\'')) else {
+            return false;
+        };
+        let Some((_, remaining)) = value[1..].split_once(quote) else {
+            return false;
+        };
+        if !is_name(attr.trim_end()) {
+            return false;
+        }
+        rest = remaining;
+    }
+}
+
+/// 入れ子のパラメータ要素1つ分の解析状態
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ParamFrame {
+    /// 要素名
+    name: String,
+    /// 要素直下のテキスト
+    text: String,
+    /// 子要素の値
+    children: serde_json::Map<String, serde_json::Value>,
+    /// `keyed_list` の要素の場合、親のオブジェクトでのキー（属性の値）
+    key: Option<String>,
+}
+
+impl ParamFrame {
+    /// 子要素があればオブジェクト、なければ空白の扱いを適用したテキストを値とする
+    ///
+    /// 子要素と並ぶテキスト（`本文<b>強調</b>` の `本文`）は、オブジェクトの `#text` に格納する。
+    fn into_value(mut self, whitespace: WhitespaceMode) -> Option<serde_json::Value> {
+        let text = whitespace.apply(&self.text);
+        if self.children.is_empty() {
+            return (!text.is_empty()).then(|| serde_json::Value::String(decode_entities(&text)));
+        }
+        if !text.trim().is_empty() {
+            self.children.insert(
+                "#text".to_string(),
+                serde_json::Value::String(decode_entities(&text)),
+            );
+        }
+        Some(serde_json::Value::Object(self.children))
+    }
+}
+
+/// パラメータの値の中に現れた、終了タグ以外のタグ（`<content>` 内の `<b>` など）の扱い
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InnerTagPolicy {
+    /// タグを値の一部としてそのまま保持する。コードを含む値でも壊れないように既定とする
+    #[default]
+    Literal,
+    /// 入れ子の要素として解析する（`nested_params(true)` と同じ）。タグと並ぶテキストは `#text` に格納する
+    Parse,
+    /// 値はそのまま保持し、開始タグごとに `Error` イベントを発行する
+    Error,
+}
+
+/// ツール呼び出しのIDの形式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IdScheme {
+    /// `tool_1`、`tool_2` のような連番
+    #[default]
+    Sequential,
+    /// `get_weather_1` のように、ツール名に連番を付ける。連番はツール名によらず共通
+    NamePrefixed,
+}
+
+/// 入出力を持たない、1文字ずつ状態遷移するパーサー本体
+///
+/// `XmlStreamParser` や `StrEvents` はこの状態機械に文字を供給する。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct XmlParserCore {
     /// タグ名を一時的に保存するバッファ
     tag_buffer: String,
     /// 現在のパーサー状態
     state: ParserState,
     /// 現在のツールのパラメータを保持
+    ///
+    /// `serde_json` の `preserve_order` フィーチャーにより、入力に現れた順序が保たれる
     current_params: serde_json::Map<String, serde_json::Value>,
     /// パラメータの値を一時的に保存するバッファ
     param_value_buffer: String,
+    /// 処理中のパラメータの終了タグ（`</param_name>`）
+    param_close_tag: String,
+    /// 処理中のツールの終了タグ（`</tool_name>`）
+    tool_close_tag: String,
+    /// パラメータが閉じられないままツールの終了タグに達したため、ツールを閉じる必要があるかどうか
+    close_tool_pending: bool,
     /// 現在処理中のツール名
     current_tool: Option<String>,
-    /// 直前の文字が改行だったかどうか
-    last_char_was_newline: bool,
     /// ToolEndイベントを発行する必要があるかどうか
     need_to_emit_tool_end: bool,
     /// XMLタグ内にいるかどうか
@@ -118,38 +363,485 @@ struct XmlStreamParser {
     current_id: Option<String>,
     /// IDカウンター
     id_counter: u64,
-    /// 未処理の文字を保持するバッファ
-    char_buffer: String,
+    /// IDの形式
+    id_scheme: IdScheme,
+    /// ParameterStart/ParameterEndイベントを発行するかどうか
+    emit_parameter_events: bool,
+    /// 子要素を持たないツールのテキストを格納するパラメータ名
+    single_text_param: Option<String>,
+    /// ツールタグ直下のテキストを一時的に保存するバッファ
+    tool_text_buffer: String,
+    /// 現在のツールが子要素（パラメータ）を持つかどうか
+    tool_has_children: bool,
+    /// タグの区切り文字
+    delimiters: Delimiters,
+    /// パラメータ内の子要素を入れ子のオブジェクトとして解析するかどうか
+    nested_params: bool,
+    /// 開いている入れ子のパラメータ要素のスタック
+    param_stack: Vec<ParamFrame>,
+    /// パラメータの値の中に現れたタグの扱い
+    inner_tag_policy: InnerTagPolicy,
+    /// ツールを受け付けるかどうかの判定（`None` の場合はすべて受け付ける）
+    #[serde(skip)]
+    validate_tool: Option<fn(&str) -> bool>,
+    /// 属性の値をキーとして親のオブジェクトに格納する要素名と、その属性名
+    keyed_lists: HashMap<String, String>,
+    /// パラメータの値の空白の扱い
+    whitespace: WhitespaceMode,
+    /// タグ（`<` と `>` の間）としてバッファする最大のバイト数
+    max_tag_len: Option<usize>,
+    /// 値全体を囲むコードフェンスを取り除くかどうか
+    strip_content_fence: bool,
+    /// タグを含まないテキストを1文字ずつではなくまとめて発行するかどうか
+    coalesce_text: bool,
+    /// `<` や `>` を含む値に対して SuspiciousValue イベントを発行するかどうか
+    warn_suspicious_values: bool,
+    /// 発行を保留している SuspiciousValue イベントのパラメータ名と抜粋
+    suspicious_value: Option<(String, String)>,
+    /// 値をバッファせずに差分として取り出すパラメータ（ツール名、パラメータ名）
+    streamed_param: Option<(String, String)>,
+    /// 処理中のパラメータが `streamed_param` かどうか
+    streaming_value: bool,
+    /// `take_param_delta` で取り出していない、`streamed_param` の値の差分
+    param_delta: String,
+    /// パラメータの値の差分を ParameterDelta イベントとして発行するかどうか
+    emit_parameter_deltas: bool,
+    /// 処理中のパラメータの値のうち、差分として処理済みのバイト数
+    delta_offset: usize,
+    /// 処理中のパラメータの差分を発行済みかどうか
+    delta_emitted: bool,
+    /// 最後の差分のあとに発行を保留している ParameterEnd イベントのパラメータ名
+    pending_param_end: Option<String>,
+    /// パラメータの値を一時ファイルに書き出す設定
+    spill: Option<SpillConfig>,
+    /// 処理中のパラメータの値を書き出している一時ファイル
+    spill_path: Option<PathBuf>,
+    /// 一時ファイルの作成を試みた回数（ファイル名の生成に使う）
+    spill_count: u64,
+    /// 入力終了時にStreamSummaryイベントを発行するかどうか
+    emit_summary: bool,
+    /// StreamSummaryイベントを発行済みかどうか
+    summary_emitted: bool,
+    /// これまでに発行したツールの数
+    tools_count: usize,
+    /// これまでに発行したパラメータの数
+    params_count: usize,
+    /// これまでに発行したテキストの文字数
+    text_chars: usize,
 }
 
-impl XmlStreamParser {
-    /// 新しいStreamToStreamインスタンスを作成
-    fn new(input: BoxStream<'static, String>) -> Self {
+impl Default for XmlParserCore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl XmlParserCore {
+    /// 新しいパーサー本体を作成
+    pub fn new() -> Self {
         Self {
-            input,
             tag_buffer: String::new(),
             state: ParserState::Normal,
             current_params: serde_json::Map::new(),
             param_value_buffer: String::new(),
+            param_close_tag: String::new(),
+            tool_close_tag: String::new(),
+            close_tool_pending: false,
             current_tool: None,
-            last_char_was_newline: false,
             need_to_emit_tool_end: false,
             in_xml: false,
             current_id: None,
             id_counter: 0,
-            char_buffer: String::new(),
+            id_scheme: IdScheme::Sequential,
+            emit_parameter_events: false,
+            single_text_param: None,
+            tool_text_buffer: String::new(),
+            tool_has_children: false,
+            delimiters: Delimiters::default(),
+            nested_params: false,
+            inner_tag_policy: InnerTagPolicy::Literal,
+            validate_tool: None,
+            param_stack: Vec::new(),
+            keyed_lists: HashMap::new(),
+            whitespace: WhitespaceMode::default(),
+            max_tag_len: None,
+            strip_content_fence: false,
+            coalesce_text: false,
+            warn_suspicious_values: false,
+            suspicious_value: None,
+            streamed_param: None,
+            streaming_value: false,
+            param_delta: String::new(),
+            emit_parameter_deltas: false,
+            delta_offset: 0,
+            delta_emitted: false,
+            pending_param_end: None,
+            spill: None,
+            spill_path: None,
+            spill_count: 0,
+            emit_summary: false,
+            summary_emitted: false,
+            tools_count: 0,
+            params_count: 0,
+            text_chars: 0,
+        }
+    }
+
+    /// パラメータタグの開始・終了を `ParameterStart` / `ParameterEnd` イベントとして発行する
+    ///
+    /// フォームUIなどで、パラメータごとに入力欄を作成・確定したい場合に使用する。
+    pub fn parameter_events(mut self, enabled: bool) -> Self {
+        self.emit_parameter_events = enabled;
+        self
+    }
+
+    /// パラメータの値を、確定した分ごとに `ParameterDelta` イベントとしても発行する
+    ///
+    /// 値の途中の差分は `take_parameter_delta` を呼び出した時点で発行し、残りは終了タグの検出時に発行する。
+    /// 入れ子のパラメータ、`stream_param` や `spill_params` の対象の値は対象外。
+    /// `strip_content_fence` を有効にした場合は、終了タグの検出時に値全体を1つの差分として発行する。
+    pub fn parameter_deltas(mut self, enabled: bool) -> Self {
+        self.emit_parameter_deltas = enabled;
+        self
+    }
+
+    /// IDカウンターの初期値を設定する。最初のツールのIDは `tool_<start + 1>` になる
+    pub fn id_start(mut self, start: u64) -> Self {
+        self.id_counter = start;
+        self
+    }
+
+    /// ツール呼び出しのIDの形式を設定する（デフォルトは `IdScheme::Sequential`）
+    pub fn id_scheme(mut self, scheme: IdScheme) -> Self {
+        self.id_scheme = scheme;
+        self
+    }
+
+    /// 子要素を持たないツールの直下のテキストを、指定した名前のパラメータとして扱う
+    ///
+    /// `<search>rust async</search>` のように、名前のない引数を1つだけ取るツール向け。
+    pub fn single_text_param(mut self, name: Option<&str>) -> Self {
+        self.single_text_param = name.map(str::to_string);
+        self
+    }
+
+    /// 入力の終了時に、集計結果を `StreamSummary` イベントとして1回だけ発行する
+    pub fn summary_event(mut self, enabled: bool) -> Self {
+        self.emit_summary = enabled;
+        self
+    }
+
+    /// タグの区切り文字を変更する
+    pub fn delimiters(mut self, delimiters: Delimiters) -> Self {
+        self.delimiters = delimiters;
+        self
+    }
+
+    /// パラメータ内の子要素を、値をそのまま保持する代わりに入れ子のオブジェクトとして解析する
+    ///
+    /// `<a><b><c>v</c></b></a>` はパラメータ `b` の値 `{"c": "v"}` になる。
+    /// 終了タグが開いている要素と一致しない場合は `Error` イベントを発行し、開いている要素を破棄する。
+    pub fn nested_params(mut self, enabled: bool) -> Self {
+        self.nested_params = enabled;
+        self
+    }
+
+    /// ツールの開始タグを検出した時点で、ツール名を受け付けるかどうかを判定する
+    ///
+    /// `false` を返したツールは `ToolStart` の代わりに `Error` イベントを発行し、
+    /// パラメータをバッファせずに対応する終了タグまで読み飛ばす。
+    /// スナップショットには含まれないため、復元後に再度設定すること。
+    pub fn validate_tool(mut self, validate: fn(&str) -> bool) -> Self {
+        self.validate_tool = Some(validate);
+        self
+    }
+
+    /// パラメータの値の中に現れたタグの扱いを設定する（デフォルトは `InnerTagPolicy::Literal`）
+    ///
+    /// `nested_params` や `keyed_list` で入れ子のパラメータの解析を有効にした場合は、この設定によらず解析する。
+    pub fn inner_tag_policy(mut self, policy: InnerTagPolicy) -> Self {
+        self.inner_tag_policy = policy;
+        self
+    }
+
+    /// パラメータの値の空白の扱いを設定する（デフォルトは `WhitespaceMode::Trim`）
+    pub fn whitespace_mode(mut self, mode: WhitespaceMode) -> Self {
+        self.whitespace = mode;
+        self
+    }
+
+    /// 繰り返される `element` 要素を、`key_attr` 属性の値をキー、要素の値を値として親のオブジェクトに格納する
+    ///
+    /// `<headers><header key="A">1</header><header key="B">2</header></headers>` は
+    /// `{"headers": {"A": "1", "B": "2"}}` になる。入れ子のパラメータの解析（`nested_params`）も有効にする。
+    /// 属性がない要素は要素名をキーとする。
+    pub fn keyed_list(mut self, element: &str, key_attr: &str) -> Self {
+        self.keyed_lists
+            .insert(element.to_string(), key_attr.to_string());
+        self.nested_params = true;
+        self
+    }
+
+    /// パラメータの値全体が ```` ``` ```` や `"""` のフェンスで囲まれている場合に、フェンスを取り除く
+    pub fn strip_content_fence(mut self, enabled: bool) -> Self {
+        self.strip_content_fence = enabled;
+        self
+    }
+
+    /// 実体参照の展開後の値に `<` か `>` を含むパラメータについて、`SuspiciousValue` イベントを発行する
+    ///
+    /// ストリーミングのパーサーは値中のタグもそのまま値として扱うため、エスケープ漏れの検出に使用する。
+    pub fn warn_suspicious_values(mut self, enabled: bool) -> Self {
+        self.warn_suspicious_values = enabled;
+        self
+    }
+
+    /// ツールの外側の、タグを含まないテキストを1文字ずつではなく1つの `Text` イベントにまとめて発行する
+    ///
+    /// ほとんどがテキストの応答で、1文字ごとの状態遷移とイベントの発行を省くために使用する。
+    pub fn coalesce_text(mut self, enabled: bool) -> Self {
+        self.coalesce_text = enabled;
+        self
+    }
+
+    /// `coalesce_text(true)` の場合に、タグの開始を含まない `text` を状態遷移なしで1つの `Text` イベントにする
+    ///
+    /// ツールの外側にいない場合や保留中のイベントがある場合は何もせず `None` を返すため、
+    /// 呼び出し側は `process_char` で1文字ずつ処理する。
+    pub fn try_plain_text(&mut self, text: &str) -> Option<ToolCallEvent> {
+        if !self.coalesce_text
+            || !self.is_in_text()
+            || self.need_to_emit_tool_end
+            || text.is_empty()
+            || text.contains(self.delimiters.open)
+        {
+            return None;
+        }
+        self.text_chars += text.chars().count();
+        Some(ToolCallEvent::Text(text.to_string()))
+    }
+
+    /// `try_plain_text` でまとめられる場合に、`text` の先頭からタグの開始の直前までの長さを返す
+    ///
+    /// まとめられない状態では `text` を走査せずに `None` を返す。テキストの連続ごとに1回だけ走査するため、
+    /// 既定の1文字ずつの処理の計算量は変わらない。
+    fn plain_text_len(&self, text: &str) -> Option<usize> {
+        if !self.coalesce_text || !self.is_in_text() || self.need_to_emit_tool_end {
+            return None;
+        }
+        Some(text.find(self.delimiters.open).unwrap_or(text.len()))
+    }
+
+    /// タグとしてバッファする長さの上限を設定する
+    ///
+    /// `<` のあとに `>` が現れないまま、属性も含めたタグの長さが `len` バイトを超えた場合はタグの解析を打ち切る。
+    /// ツールの外側では受信した文字をテキストとして発行し、ツールの内側では読み飛ばす。
+    pub fn max_tag_name_len(mut self, len: usize) -> Self {
+        self.max_tag_len = Some(len);
+        self
+    }
+
+    /// 指定したツールのパラメータの値をバッファせず、受信した分を `take_param_delta` で取り出せるようにする
+    ///
+    /// 値はトリムや実体参照の展開をせずにそのまま渡し、`Parameter` イベントには含めない。
+    pub fn stream_param(mut self, tool_name: &str, param_name: &str) -> Self {
+        self.streamed_param = Some((tool_name.to_string(), param_name.to_string()));
+        self
+    }
+
+    /// `stream_param` で指定したパラメータの、前回の呼び出し以降に確定した値の差分を取り出す
+    pub fn take_param_delta(&mut self) -> String {
+        std::mem::take(&mut self.param_delta)
+    }
+
+    /// `threshold` バイトを超えたパラメータの値を、`dir` に作成する一時ファイルに書き出す
+    ///
+    /// 数MBのファイル内容などを受け取る際のメモリ使用量を抑える。書き出した値は
+    /// `Parameter` イベントで `ParamValue::Spilled` として渡され、ファイルの削除は呼び出し側が行う。
+    pub fn spill_params(mut self, threshold: usize, dir: impl Into<PathBuf>) -> Self {
+        self.spill = Some(SpillConfig {
+            threshold,
+            dir: dir.into(),
+        });
+        self
+    }
+
+    /// 値のバッファがしきい値を超えていれば、終了タグの検出に必要な末尾を残して一時ファイルに書き出す
+    fn spill_param_value(&mut self) -> Option<ToolCallEvent> {
+        let threshold = self.spill.as_ref()?.threshold;
+        if self.param_value_buffer.len() <= threshold {
+            return None;
+        }
+        let mut head = self.take_value_head();
+        let keep_from = self.complete_len(&head);
+        self.param_value_buffer
+            .insert_str(0, &head.split_off(keep_from));
+
+        let value = self.normalize_value_part(&head, self.spill_path.is_none(), false);
+        if value.is_empty() {
+            return None;
+        }
+        self.write_spilled(&value).err()
+    }
+
+    /// 値の一部のうち、続きを受信しなくても正規化の結果が変わらない先頭部分のバイト数
+    ///
+    /// 途中で切れた実体参照と、値の終わりでトリムされうる末尾の空白は含めない。
+    fn complete_len(&self, part: &str) -> usize {
+        let mut len = part.len();
+        if let Some(amp) = part.rfind('&')
+            && !part[amp..].contains(';')
+            && part.len() - amp <= MAX_ENTITY_LEN
+        {
+            len = amp;
+        }
+        if self.whitespace != WhitespaceMode::Preserve {
+            len = part[..len].trim_end().len();
         }
+        len
+    }
+
+    /// 値の一部に、インラインの値と同じ空白の扱いと実体参照のデコードを適用する
+    ///
+    /// `at_start` は値の先頭からの部分かどうか（前の部分がすべて空白で、何も出力していない場合を含む）。
+    /// 末尾の空白は値の終わり（`at_end`）まで残すため、途中の空白の連続が分割されることはない。
+    fn normalize_value_part(&self, chunk: &str, at_start: bool, at_end: bool) -> String {
+        let chunk = match self.whitespace {
+            WhitespaceMode::Preserve => Cow::Borrowed(chunk),
+            WhitespaceMode::Trim => {
+                let chunk = if at_start { chunk.trim_start() } else { chunk };
+                Cow::Borrowed(if at_end { chunk.trim_end() } else { chunk })
+            }
+            WhitespaceMode::Collapse => {
+                let mut collapsed = String::with_capacity(chunk.len());
+                let mut in_whitespace = false;
+                for c in chunk.chars() {
+                    if c.is_whitespace() {
+                        in_whitespace = true;
+                        continue;
+                    }
+                    if in_whitespace && !(at_start && collapsed.is_empty()) {
+                        collapsed.push(' ');
+                    }
+                    in_whitespace = false;
+                    collapsed.push(c);
+                }
+                Cow::Owned(collapsed)
+            }
+        };
+        decode_entities(&chunk)
+    }
+
+    /// 値のバッファから、終了タグの検出に必要な末尾を除いた先頭部分を取り出す
+    fn take_value_head(&mut self) -> String {
+        let mut keep_from = self
+            .param_value_buffer
+            .len()
+            .saturating_sub(self.param_close_tag.len().max(self.tool_close_tag.len()));
+        while !self.param_value_buffer.is_char_boundary(keep_from) {
+            keep_from -= 1;
+        }
+        let tail = self.param_value_buffer.split_off(keep_from);
+        std::mem::replace(&mut self.param_value_buffer, tail)
+    }
+
+    /// 処理中のパラメータの一時ファイルに追記する。初回はファイルを作成する
+    fn write_spilled(&mut self, value: &str) -> std::result::Result<(), ToolCallEvent> {
+        let file = match &self.spill_path {
+            Some(path) => std::fs::OpenOptions::new()
+                .append(true)
+                .open(path)
+                .map_err(|e| (path.clone(), e)),
+            None => self.create_spill_file().map(|(path, file)| {
+                self.spill_path = Some(path);
+                file
+            }),
+        };
+        file.and_then(|mut file| {
+            file.write_all(value.as_bytes())
+                .map_err(|e| (self.spill_path.clone().unwrap_or_default(), e))
+        })
+        .map_err(|(path, e)| {
+            ToolCallEvent::Error(format!(
+                "failed to spill parameter to {}: {}",
+                path.display(),
+                e
+            ))
+        })
+    }
+
+    /// 推測できない名前の一時ファイルを新規に作成する
+    ///
+    /// 同じプロセス内の別のパーサーや、事前に置かれたシンボリックリンクと衝突しないよう、
+    /// 既存のファイルは開かずに名前を変えて作成し直す。
+    fn create_spill_file(
+        &mut self,
+    ) -> std::result::Result<(PathBuf, std::fs::File), (PathBuf, std::io::Error)> {
+        let dir = self
+            .spill
+            .as_ref()
+            .map_or_else(PathBuf::new, |spill| spill.dir.clone());
+        let mut last_path = PathBuf::new();
+        for _ in 0..16 {
+            self.spill_count += 1;
+            let nanos = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_nanos());
+            let random = RandomState::new().hash_one((std::process::id(), self.spill_count, nanos));
+            let path = dir.join(format!(
+                "tool_use_with_xml_{}_{:016x}.txt",
+                std::process::id(),
+                random
+            ));
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => return Ok((path, file)),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => last_path = path,
+                Err(e) => return Err((path, e)),
+            }
+        }
+        Err((
+            last_path,
+            std::io::Error::from(std::io::ErrorKind::AlreadyExists),
+        ))
+    }
+
+    /// 文字が指定した区切り文字かどうか
+    fn is_delimiter(c: &str, delimiter: char) -> bool {
+        let mut chars = c.chars();
+        chars.next() == Some(delimiter) && chars.next().is_none()
+    }
+
+    /// パラメータ境界イベントが有効な場合のみイベントを生成
+    fn parameter_event(
+        &self,
+        build: impl FnOnce(String) -> ToolCallEvent,
+    ) -> Option<ToolCallEvent> {
+        if !self.emit_parameter_events {
+            return None;
+        }
+        let id = self
+            .current_id
+            .clone()
+            .unwrap_or_else(|| "unknown".to_string());
+        Some(build(id))
     }
 
     /// 新しいIDを生成
-    fn generate_id(&mut self) -> String {
+    fn generate_id(&mut self, tool_name: &str) -> String {
         self.id_counter += 1;
-        format!("tool_{}", self.id_counter)
+        match self.id_scheme {
+            IdScheme::Sequential => format!("tool_{}", self.id_counter),
+            IdScheme::NamePrefixed => format!("{}_{}", tool_name, self.id_counter),
+        }
     }
 
     /// 通常状態（XMLタグ外）での文字処理
     fn process_normal_state(&mut self, c: &str) -> Option<ToolCallEvent> {
-        if c == "<" {
+        if Self::is_delimiter(c, self.delimiters.open) {
             self.state = ParserState::InTag;
             self.tag_buffer.clear();
             self.in_xml = true;
@@ -161,22 +853,112 @@ impl XmlStreamParser {
 
     /// タグ内での文字処理
     fn process_in_tag_state(&mut self, c: &str) -> Option<ToolCallEvent> {
-        if c == ">" {
+        // コメント中の区切り文字ではタグを終了せず、`-->` まで読み進める
+        let in_comment = self
+            .tag_buffer
+            .strip_prefix("!--")
+            .is_some_and(|body| !body.ends_with("--"));
+        // 入れ子のパラメータ内では、閉じないまま次の区切り文字が現れたものをテキストとして扱う
+        if Self::is_delimiter(c, self.delimiters.open)
+            && !in_comment
+            && self.parses_nested()
+            && !self.param_stack.is_empty()
+        {
             let tag = std::mem::take(&mut self.tag_buffer);
-            if let Some(tag_name) = tag.strip_prefix('/') {
+            self.push_literal_tag(&tag, false);
+            self.state = ParserState::InTag;
+            return None;
+        }
+        if Self::is_delimiter(c, self.delimiters.close) && !in_comment {
+            let tag = std::mem::take(&mut self.tag_buffer);
+            let close_prefix = self.delimiters.close_prefix;
+            if tag.starts_with("!--") {
+                self.process_comment()
+            } else if tag.is_empty() || tag.strip_prefix(close_prefix) == Some("") {
+                self.process_empty_tag(&tag)
+            } else if let Some(tag_name) = tag.strip_prefix(close_prefix) {
                 self.process_closing_tag(tag_name)
             } else {
                 self.process_opening_tag(tag)
             }
         } else {
             self.tag_buffer.push_str(c);
+            if self
+                .max_tag_len
+                .is_some_and(|max| self.tag_buffer.len() > max)
+            {
+                return self.abandon_tag();
+            }
             None
         }
     }
 
+    /// タグとして解析しなかった `<tag` または `<tag>` を、入れ子のパラメータのテキストに加える
+    fn push_literal_tag(&mut self, tag: &str, closed: bool) {
+        if let Some(frame) = self.param_stack.last_mut() {
+            frame.text.push(self.delimiters.open);
+            frame.text.push_str(tag);
+            if closed {
+                frame.text.push(self.delimiters.close);
+            }
+        }
+        self.state = ParserState::InParameterTag;
+    }
+
+    /// 長すぎるタグの解析を打ち切り、タグの前の状態に戻る
+    fn abandon_tag(&mut self) -> Option<ToolCallEvent> {
+        let tag = std::mem::take(&mut self.tag_buffer);
+        if self.current_tool.is_some() {
+            return self.process_comment();
+        }
+        self.state = ParserState::Normal;
+        self.in_xml = false;
+        Some(ToolCallEvent::Text(format!(
+            "{}{}",
+            self.delimiters.open, tag
+        )))
+    }
+
+    /// コメント `<!-- ... -->` の処理。内容は読み飛ばし、コメントの前の状態に戻る
+    fn process_comment(&mut self) -> Option<ToolCallEvent> {
+        self.state = if self.current_tool.is_none() {
+            self.in_xml = false;
+            ParserState::Normal
+        } else if !self.param_stack.is_empty() {
+            ParserState::InParameterTag
+        } else {
+            ParserState::InToolTag
+        };
+        None
+    }
+
+    /// タグ名のない `<>` や `</>` の処理
+    ///
+    /// ツールの外側ではテキストとして扱い、ツールタグ内では読み飛ばす。
+    fn process_empty_tag(&mut self, tag: &str) -> Option<ToolCallEvent> {
+        if self.current_tool.is_some() {
+            self.state = ParserState::InToolTag;
+            return None;
+        }
+        self.state = ParserState::Normal;
+        self.in_xml = false;
+        Some(ToolCallEvent::Text(format!(
+            "{}{}{}",
+            self.delimiters.open, tag, self.delimiters.close
+        )))
+    }
+
+    /// パラメータ内の子要素を入れ子のオブジェクトとして解析するかどうか
+    fn parses_nested(&self) -> bool {
+        self.nested_params || self.inner_tag_policy == InnerTagPolicy::Parse
+    }
+
     /// 終了タグの処理
     fn process_closing_tag(&mut self, tag_name: &str) -> Option<ToolCallEvent> {
         let tag_name = tag_name.to_string();
+        if self.parses_nested() && !self.param_stack.is_empty() {
+            return self.close_nested_param(tag_name);
+        }
         if let Some(current_tool) = &self.current_tool {
             if current_tool == &tag_name {
                 // ツール終了の処理
@@ -187,7 +969,18 @@ impl XmlStreamParser {
                     .unwrap_or_else(|| "unknown".to_string());
                 self.current_tool = None;
                 self.in_xml = false;
-                self.last_char_was_newline = false;
+
+                let text = std::mem::take(&mut self.tool_text_buffer);
+                let text = self.whitespace.apply(&text);
+                if let Some(name) = &self.single_text_param
+                    && !self.tool_has_children
+                    && !text.is_empty()
+                {
+                    self.current_params.insert(
+                        name.clone(),
+                        serde_json::Value::String(decode_entities(&text)),
+                    );
+                }
 
                 // パラメータがある場合は、まずParameterイベントを返す
                 if !self.current_params.is_empty() {
@@ -202,16 +995,7 @@ impl XmlStreamParser {
                     Some(ToolCallEvent::ToolEnd { id })
                 }
             } else {
-                // パラメータタグの終了処理
-                let value = std::mem::take(&mut self.param_value_buffer);
-                if !value.trim().is_empty() {
-                    self.current_params.insert(
-                        tag_name,
-                        serde_json::Value::String(value.trim().to_string()),
-                    );
-                }
-                self.state = ParserState::InToolTag;
-                None
+                self.process_parameter_end(tag_name)
             }
         } else {
             self.state = ParserState::Normal;
@@ -220,6 +1004,35 @@ impl XmlStreamParser {
         }
     }
 
+    /// 入れ子のパラメータ要素の終了タグの処理
+    fn close_nested_param(&mut self, tag_name: String) -> Option<ToolCallEvent> {
+        let frame = self.param_stack.pop().unwrap_or_default();
+        if frame.name != tag_name {
+            self.param_stack.clear();
+            self.state = ParserState::InToolTag;
+            return Some(ToolCallEvent::Error(format!(
+                "mismatched end tag: expected {}, found {}",
+                self.delimiters.close_tag(&frame.name),
+                self.delimiters.close_tag(&tag_name)
+            )));
+        }
+
+        let key = frame.key.clone().unwrap_or_else(|| tag_name.clone());
+        let value = frame.into_value(self.whitespace);
+        if let Some(parent) = self.param_stack.last_mut() {
+            if let Some(value) = value {
+                parent.children.insert(key, value);
+            }
+            self.state = ParserState::InParameterTag;
+            return None;
+        }
+        if let Some(value) = value {
+            self.current_params.insert(key, value);
+        }
+        self.state = ParserState::InToolTag;
+        self.parameter_event(|id| ToolCallEvent::ParameterEnd { id, name: tag_name })
+    }
+
     /// ツール終了の処理
     #[allow(dead_code)]
     fn process_tool_end(&mut self) -> Option<ToolCallEvent> {
@@ -230,7 +1043,6 @@ impl XmlStreamParser {
             .unwrap_or_else(|| "unknown".to_string());
         self.current_tool = None;
         self.in_xml = false;
-        self.last_char_was_newline = false;
 
         if !self.current_params.is_empty() {
             let params = std::mem::take(&mut self.current_params);
@@ -246,304 +1058,1521 @@ impl XmlStreamParser {
     }
 
     /// パラメータ終了の処理
-    #[allow(dead_code)]
+    ///
+    /// パラメータ内で対応する終了タグを検出した場合は、ツールと同名であってもツールを終了させずにここで処理する。
     fn process_parameter_end(&mut self, tag_name: String) -> Option<ToolCallEvent> {
         let value = std::mem::take(&mut self.param_value_buffer);
-        if !value.trim().is_empty() {
+        let delta_offset = std::mem::take(&mut self.delta_offset);
+        let delta_emitted = std::mem::take(&mut self.delta_emitted);
+        let mut delta = None;
+        self.state = ParserState::InToolTag;
+        if self.spill_path.is_some() {
+            let value = self.normalize_value_part(&value, self.spill_path.is_none(), true);
+            if let Err(error) = self.write_spilled(&value) {
+                self.spill_path = None;
+                return Some(error);
+            }
+            let path = self.spill_path.take().unwrap_or_default();
             self.current_params.insert(
-                tag_name,
-                serde_json::Value::String(value.trim().to_string()),
+                tag_name.clone(),
+                serde_json::to_value(ParamValue::Spilled(path)).unwrap_or_default(),
+            );
+        } else {
+            let mut value = self.whitespace.apply(&value);
+            if self.strip_content_fence
+                && let Some(inner) = strip_code_fence(&value)
+            {
+                value = Cow::Owned(inner.to_string());
+            }
+            if !value.is_empty() {
+                let value = decode_entities(&value);
+                if self.warn_suspicious_values
+                    && let Some(position) = value.find(['<', '>'])
+                {
+                    let snippet = value[position..].chars().take(40).collect();
+                    self.suspicious_value = Some((tag_name.clone(), snippet));
+                }
+                if self.emit_parameter_deltas && delta_offset == 0 {
+                    delta = Some(value.clone());
+                }
+                self.current_params
+                    .insert(tag_name.clone(), serde_json::Value::String(value));
+            }
+        }
+        if delta_offset > 0 {
+            let rest = self.normalize_value_part(
+                value.get(delta_offset..).unwrap_or_default(),
+                !delta_emitted,
+                true,
             );
+            delta = Some(rest).filter(|rest| !rest.is_empty());
         }
-        self.state = ParserState::InToolTag;
-        None
+
+        let Some(delta) = delta else {
+            return self.parameter_event(|id| ToolCallEvent::ParameterEnd { id, name: tag_name });
+        };
+        if self.emit_parameter_events {
+            self.pending_param_end = Some(tag_name.clone());
+        }
+        Some(ToolCallEvent::ParameterDelta {
+            id: self
+                .current_id
+                .clone()
+                .unwrap_or_else(|| "unknown".to_string()),
+            name: tag_name,
+            delta,
+        })
+    }
+
+    /// 処理中のパラメータの値のうち、前回の差分以降に確定した分を `ParameterDelta` イベントとして返す
+    ///
+    /// `parameter_deltas(true)` の場合のみ発行する。終了タグの一部かもしれない末尾や、途中で切れた実体参照、
+    /// 値の終わりでトリムされうる末尾の空白は、次の呼び出しか終了タグの検出時まで発行しない。
+    pub fn flush_parameter_delta(&mut self) -> Option<ToolCallEvent> {
+        if !self.emit_parameter_deltas
+            || !matches!(self.state, ParserState::InParameterTag)
+            || self.parses_nested()
+            || self.streaming_value
+            || self.spill.is_some()
+            || self.strip_content_fence
+        {
+            return None;
+        }
+        let mut end = self
+            .param_value_buffer
+            .len()
+            .saturating_sub(self.param_close_tag.len().max(self.tool_close_tag.len()));
+        while !self.param_value_buffer.is_char_boundary(end) {
+            end -= 1;
+        }
+        let part = self.param_value_buffer.get(self.delta_offset..end)?;
+        let len = self.complete_len(part);
+        let delta = self.normalize_value_part(&part[..len], !self.delta_emitted, false);
+        self.delta_offset += len;
+        if delta.is_empty() {
+            return None;
+        }
+        self.delta_emitted = true;
+
+        let name_start = self.delimiters.open.len_utf8() + self.delimiters.close_prefix.len_utf8();
+        let name_end = self.param_close_tag.len() - self.delimiters.close.len_utf8();
+        Some(ToolCallEvent::ParameterDelta {
+            id: self
+                .current_id
+                .clone()
+                .unwrap_or_else(|| "unknown".to_string()),
+            name: self.param_close_tag[name_start..name_end].to_string(),
+            delta,
+        })
     }
 
     /// 開始タグの処理
     fn process_opening_tag(&mut self, tag: String) -> Option<ToolCallEvent> {
+        if self.current_tool.is_none()
+            && let Some(validate) = self.validate_tool
+        {
+            let name = tag.split_whitespace().next().unwrap_or_default();
+            if !validate(name) {
+                self.state = ParserState::SkippingTool;
+                self.param_value_buffer.clear();
+                self.param_close_tag = self.delimiters.close_tag(name);
+                return Some(ToolCallEvent::Error(format!("rejected tool: {}", name)));
+            }
+        }
         if self.current_tool.is_none() {
-            let id = self.generate_id();
+            let id = self.generate_id(tag.split_whitespace().next().unwrap_or_default());
             self.current_id = Some(id.clone());
             self.current_tool = Some(tag.clone());
+            self.tool_close_tag = self
+                .delimiters
+                .close_tag(tag.split_whitespace().next().unwrap_or_default());
             self.state = ParserState::InToolTag;
+            self.tool_text_buffer.clear();
+            self.tool_has_children = false;
             Some(ToolCallEvent::ToolStart { id, name: tag })
+        } else if self.parses_nested() {
+            let is_top_level = self.param_stack.is_empty();
+            // 比較演算子などタグとして整形式でないものは、値のテキストとして扱う
+            if !is_top_level && !is_start_tag(&tag) {
+                self.push_literal_tag(&tag, true);
+                return None;
+            }
+            self.state = ParserState::InParameterTag;
+            self.tool_has_children = true;
+            let name = tag.split_whitespace().next().unwrap_or_default();
+            let key = self
+                .keyed_lists
+                .get(name)
+                .and_then(|key_attr| tag_attribute(&tag, key_attr));
+            self.param_stack.push(ParamFrame {
+                name: name.to_string(),
+                key,
+                ..ParamFrame::default()
+            });
+            if !is_top_level {
+                return None;
+            }
+            self.parameter_event(|id| ToolCallEvent::ParameterStart { id, name: tag })
         } else {
             self.state = ParserState::InParameterTag;
             self.param_value_buffer.clear();
-            None
+            self.delta_offset = 0;
+            self.delta_emitted = false;
+            self.tool_has_children = true;
+            let name = tag.split_whitespace().next().unwrap_or_default();
+            self.param_close_tag = self.delimiters.close_tag(name);
+            self.streaming_value = self.streamed_param.as_ref().is_some_and(|(tool, param)| {
+                self.current_tool.as_ref() == Some(tool) && param == name
+            });
+            self.parameter_event(|id| ToolCallEvent::ParameterStart { id, name: tag })
         }
     }
 
     /// ツールタグ内での文字処理
     fn process_in_tool_tag_state(&mut self, c: &str) -> Option<ToolCallEvent> {
-        if c == "<" {
+        if Self::is_delimiter(c, self.delimiters.open) {
             self.state = ParserState::InTag;
             self.tag_buffer.clear();
             None
         } else {
-            // ツールタグ内のテキストは無視する
+            // ツールタグ内のテキストは、single_text_param が設定されている場合のみ保持する
+            if self.single_text_param.is_some() && !self.tool_has_children {
+                self.tool_text_buffer.push_str(c);
+            }
             None
         }
     }
 
     /// パラメータタグ内での文字処理
+    ///
+    /// 値にはコードなどが含まれうるため、対応する `</param_name>` が現れるまで
+    /// `</div>` のような他のタグも値の一部としてそのまま保持する。
+    /// 対応する終了タグより先にツールの終了タグが現れた場合は、`Error` イベントを発行してツールを閉じる。
     fn process_in_parameter_tag_state(&mut self, c: &str) -> Option<ToolCallEvent> {
-        if c == "<" {
-            self.state = ParserState::InTag;
-            self.tag_buffer.clear();
-            None
-        } else {
-            self.param_value_buffer.push_str(c);
-            None
+        if self.parses_nested() {
+            if Self::is_delimiter(c, self.delimiters.open) {
+                self.state = ParserState::InTag;
+                self.tag_buffer.clear();
+            } else if let Some(frame) = self.param_stack.last_mut() {
+                frame.text.push_str(c);
+            }
+            return None;
+        }
+        self.param_value_buffer.push_str(c);
+        if self.tool_close_tag != self.param_close_tag
+            && self.param_value_buffer.ends_with(&self.tool_close_tag)
+        {
+            return Some(self.close_unclosed_param());
+        }
+        if !self.param_value_buffer.ends_with(&self.param_close_tag) {
+            if self.inner_tag_policy == InnerTagPolicy::Error
+                && let Some(tag) = self.inner_start_tag()
+            {
+                let name_start =
+                    self.delimiters.open.len_utf8() + self.delimiters.close_prefix.len_utf8();
+                let name_end = self.param_close_tag.len() - self.delimiters.close.len_utf8();
+                return Some(ToolCallEvent::Error(format!(
+                    "unexpected tag {} inside parameter {}",
+                    tag,
+                    &self.param_close_tag[name_start..name_end]
+                )));
+            }
+            if self.streaming_value {
+                let head = self.take_value_head();
+                self.param_delta.push_str(&head);
+                return None;
+            }
+            return self.spill_param_value();
         }
+        let value_len = self.param_value_buffer.len() - self.param_close_tag.len();
+        self.param_value_buffer.truncate(value_len);
+        if self.streaming_value {
+            self.streaming_value = false;
+            let rest = std::mem::take(&mut self.param_value_buffer);
+            self.param_delta.push_str(&rest);
+        }
+        let close_tag = std::mem::take(&mut self.param_close_tag);
+        let name_start = self.delimiters.open.len_utf8() + self.delimiters.close_prefix.len_utf8();
+        let name_end = close_tag.len() - self.delimiters.close.len_utf8();
+        self.process_parameter_end(close_tag[name_start..name_end].to_string())
     }
 
-    /// 1文字を処理し、必要に応じてイベントを生成
-    fn process_char(&mut self, c: &str) -> Option<ToolCallEvent> {
-        match &self.state {
-            ParserState::Normal => self.process_normal_state(c),
-            ParserState::InTag => self.process_in_tag_state(c),
-            ParserState::InToolTag => self.process_in_tool_tag_state(c),
-            ParserState::InParameterTag => self.process_in_parameter_tag_state(c),
-        }
+    /// パラメータが閉じられないままツールの終了タグに達した場合に、値を破棄してエラーを返す
+    ///
+    /// ツールは次の `take_pending_event` で閉じるため、後続のテキストは通常どおり発行される。
+    fn close_unclosed_param(&mut self) -> ToolCallEvent {
+        let mut value = std::mem::take(&mut self.param_value_buffer);
+        value.truncate(value.len() - self.tool_close_tag.len());
+        let close_start = format!("{}{}", self.delimiters.open, self.delimiters.close_prefix);
+        let found = value
+            .find(&close_start)
+            .and_then(|start| {
+                let end = value[start..].find(self.delimiters.close)?;
+                Some(&value[start..start + end + self.delimiters.close.len_utf8()])
+            })
+            .unwrap_or(&self.tool_close_tag);
+        let error = ToolCallEvent::Error(format!(
+            "mismatched end tag: expected {}, found {}",
+            self.param_close_tag, found
+        ));
+        self.param_close_tag.clear();
+        self.streaming_value = false;
+        self.spill_path = None;
+        self.state = ParserState::InToolTag;
+        self.close_tool_pending = true;
+        error
     }
-}
 
-/// Stream traitの実装
-impl Stream for XmlStreamParser {
-    type Item = ToolCallEvent;
+    /// パラメータの値のバッファが開始タグ（`<b>` など）で終わっている場合、そのタグを返す
+    ///
+    /// `a<b && c>` のように要素名と属性だけからならないものはタグとみなさない。
+    fn inner_start_tag(&self) -> Option<&str> {
+        let value = self
+            .param_value_buffer
+            .strip_suffix(self.delimiters.close)?;
+        let start = value.rfind(self.delimiters.open)?;
+        is_start_tag(&value[start + self.delimiters.open.len_utf8()..])
+            .then(|| &self.param_value_buffer[start..])
+    }
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let this = self.as_mut().get_mut();
+    /// 拒否したツールの内側での文字処理
+    ///
+    /// 終了タグの検出に必要な末尾の文字だけを保持し、対応する終了タグのあとで通常の状態に戻る。
+    fn process_skipping_tool_state(&mut self, c: &str) -> Option<ToolCallEvent> {
+        self.param_value_buffer.push_str(c);
+        if self.param_value_buffer.ends_with(&self.param_close_tag) {
+            self.param_value_buffer.clear();
+            self.param_close_tag.clear();
+            self.state = ParserState::Normal;
+            self.in_xml = false;
+        } else if self.param_value_buffer.len() > self.param_close_tag.len() {
+            let excess = self.param_value_buffer.len() - self.param_close_tag.len();
+            let head = (excess..=self.param_value_buffer.len())
+                .find(|&i| self.param_value_buffer.is_char_boundary(i))
+                .unwrap_or_default();
+            self.param_value_buffer.drain(..head);
+        }
+        None
+    }
 
-        // ToolEndイベントの遅延発行
-        if this.need_to_emit_tool_end {
-            this.need_to_emit_tool_end = false;
-            if let Some(id) = this.current_id.take() {
-                return Poll::Ready(Some(ToolCallEvent::ToolEnd { id }));
+    /// 1文字を処理し、必要に応じてイベントを生成
+    pub fn process_char(&mut self, c: &str) -> Option<ToolCallEvent> {
+        let event = match &self.state {
+            ParserState::Normal => self.process_normal_state(c),
+            ParserState::InTag => self.process_in_tag_state(c),
+            ParserState::InToolTag => self.process_in_tool_tag_state(c),
+            ParserState::InParameterTag => self.process_in_parameter_tag_state(c),
+            ParserState::SkippingTool => self.process_skipping_tool_state(c),
+        };
+        match &event {
+            Some(ToolCallEvent::Text(text)) => self.text_chars += text.chars().count(),
+            Some(ToolCallEvent::ToolStart { .. }) => self.tools_count += 1,
+            Some(ToolCallEvent::Parameter { arguments, .. }) => {
+                self.params_count += arguments.as_object().map_or(0, |params| params.len());
             }
+            _ => {}
         }
+        event
+    }
 
-        // バッファに残っている文字がある場合は、それを処理
-        if !this.char_buffer.is_empty() {
-            let c = this.char_buffer.remove(0).to_string();
-            if let Some(event) = this.process_char(&c) {
-                return Poll::Ready(Some(event));
-            }
-            return self.poll_next(cx);
+    /// 入力の終了を通知する。`summary_event(true)` の場合、初回のみ `StreamSummary` イベントを返す
+    pub fn finish(&mut self) -> Option<ToolCallEvent> {
+        if !self.emit_summary || self.summary_emitted {
+            return None;
         }
+        self.summary_emitted = true;
+        Some(ToolCallEvent::StreamSummary {
+            tools: self.tools_count,
+            params: self.params_count,
+            text_chars: self.text_chars,
+        })
+    }
 
-        // 入力ストリームからの次の文字列を処理
-        match this.input.poll_next_unpin(cx) {
-            Poll::Ready(Some(s)) => {
-                // 受け取った文字列をバッファに追加
-                this.char_buffer.push_str(&s);
-                // 再帰的に次の文字を処理
-                self.poll_next(cx)
+    /// 直前の文字処理で発行を保留したイベント（ParameterDeltaイベントのあとのParameterEnd、
+    /// ParameterイベントのあとのToolEnd、SuspiciousValue、
+    /// 閉じられていないパラメータのエラーのあとのツールの終了）を取り出す
+    ///
+    /// `process_char` を呼び出す前に、毎回このメソッドで保留中のイベントを確認すること。
+    pub fn take_pending_event(&mut self) -> Option<ToolCallEvent> {
+        if let Some(name) = self.pending_param_end.take() {
+            return self.parameter_event(|id| ToolCallEvent::ParameterEnd { id, name });
+        }
+        if let Some((param, snippet)) = self.suspicious_value.take() {
+            return Some(ToolCallEvent::SuspiciousValue {
+                id: self.current_id.clone().unwrap_or_default(),
+                param,
+                snippet,
+            });
+        }
+        if self.close_tool_pending {
+            self.close_tool_pending = false;
+            if let Some(tool) = self.current_tool.clone() {
+                return self.process_closing_tag(&tool);
             }
-            Poll::Ready(None) => Poll::Ready(None),
-            Poll::Pending => Poll::Pending,
         }
+        if !self.need_to_emit_tool_end {
+            return None;
+        }
+        self.need_to_emit_tool_end = false;
+        self.current_id
+            .take()
+            .map(|id| ToolCallEvent::ToolEnd { id })
+    }
+
+    /// 受信途中のパラメータがある場合、確定済みのパラメータに受信途中の値を加えた暫定の引数を返す
+    ///
+    /// 一時ファイルに書き出し中の値や、入れ子のパラメータは対象外。
+    pub fn tentative_arguments(&self) -> Option<(String, serde_json::Value)> {
+        if !matches!(self.state, ParserState::InParameterTag)
+            || self.parses_nested()
+            || self.spill_path.is_some()
+        {
+            return None;
+        }
+        let id = self.current_id.clone()?;
+        let name_start = self.delimiters.open.len_utf8() + self.delimiters.close_prefix.len_utf8();
+        let name_end = self.param_close_tag.len() - self.delimiters.close.len_utf8();
+        let name = self.param_close_tag.get(name_start..name_end)?;
+
+        // 受信途中の終了タグは値に含めない
+        let mut value = self.param_value_buffer.as_str();
+        if let Some(partial) = (1..self.param_close_tag.len())
+            .rev()
+            .filter_map(|len| self.param_close_tag.get(..len))
+            .find(|prefix| value.ends_with(prefix))
+        {
+            value = &value[..value.len() - partial.len()];
+        }
+        let value = self.whitespace.apply(value);
+        if value.is_empty() {
+            return None;
+        }
+
+        let mut arguments = self.current_params.clone();
+        arguments.insert(
+            name.to_string(),
+            serde_json::Value::String(decode_entities(&value)),
+        );
+        Some((id, serde_json::Value::Object(arguments)))
+    }
+
+    /// 受信したがイベントとして発行していない、途中のタグやパラメータの値を返す
+    ///
+    /// 入力が途中で打ち切られた場合の原因調査用。
+    pub fn leftover(&self) -> String {
+        let mut leftover = self.tool_text_buffer.clone();
+        leftover.push_str(&self.param_value_buffer);
+        if matches!(self.state, ParserState::InTag) {
+            leftover.push(self.delimiters.open);
+            leftover.push_str(&self.tag_buffer);
+        }
+        leftover
+    }
+
+    /// XMLタグの外側（テキストとして扱われる位置）にいるかどうか
+    fn is_in_text(&self) -> bool {
+        matches!(self.state, ParserState::Normal)
+    }
+
+    /// パーサーの内部状態を人が読める形式で返す
+    ///
+    /// ツールが閉じられないままストリームが止まった場合などのトラブルシューティング用。
+    /// 処理のホットパスからは呼び出さないこと。
+    pub fn debug_state(&self) -> String {
+        format!(
+            "state: {:?}\ncurrent_tool: {:?}\ncurrent_id: {:?}\ntag_buffer: {:?}\nparam_value_buffer: {:?}\ncurrent_params: {}",
+            self.state,
+            self.current_tool,
+            self.current_id,
+            self.tag_buffer,
+            self.param_value_buffer,
+            serde_json::Value::Object(self.current_params.clone()),
+        )
     }
 }
 
-/// 入力ストリームをツール呼び出しイベントのストリームに変換
-fn stream_to_stream(input: BoxStream<'static, String>) -> ToolCallStreamResult {
-    let stream = XmlStreamParser::new(input);
-    Ok(Box::pin(stream))
+/// 永続化して再接続後に解析を再開するための、`XmlStreamParser` の状態のスナップショット
+///
+/// `XmlStreamParser::snapshot` で取得し、`XmlStreamParser::from_snapshot` で復元する。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParserSnapshot {
+    /// パーサー本体の状態
+    core: XmlParserCore,
+    /// 受信済みで未処理の文字
+    char_buffer: String,
 }
 
-#[tokio::main]
-async fn main() {
-    // サンプルの入力テキスト
-    let input = r#"明日のニューヨークの天気を確認します。
+/// XMLストリームをイベントストリームに変換するための構造体
+pub struct XmlStreamParser {
+    /// 入力ストリーム
+    input: BoxStream<'static, String>,
+    /// 未処理の文字を保持するバッファ
+    char_buffer: String,
+    /// `char_buffer` の中で次に処理するバイト位置
+    char_offset: usize,
+    /// パーサー本体
+    core: XmlParserCore,
+}
 
-<get_weather>
-  <location>New York</location>
-  <date>tomorrow</date>
-  <unit>fahrenheit</unit>
-</get_weather>
+impl XmlStreamParser {
+    /// 新しいStreamToStreamインスタンスを作成
+    pub fn new(input: BoxStream<'static, String>) -> Self {
+        Self {
+            input,
+            char_buffer: String::new(),
+            char_offset: 0,
+            core: XmlParserCore::new(),
+        }
+    }
 
-天気予報を取得しました。次に、ファイルに書き込みます。
+    /// 設定済みのパーサー本体でインスタンスを作成
+    pub fn with_core(input: BoxStream<'static, String>, core: XmlParserCore) -> Self {
+        Self {
+            input,
+            char_buffer: String::new(),
+            char_offset: 0,
+            core,
+        }
+    }
 
-<write_to_file>
-<path>weather_report.txt</path>
-<content>
-明日のニューヨークの天気予報：
-- 最高気温: 75°F
-- 最低気温: 60°F
-- 天候: 晴れ時々曇り
-</content>
-</write_to_file>
+    /// IDカウンターの初期値を指定してインスタンスを作成
+    ///
+    /// 再接続などでセッションを再開する際に、前回の続きからIDを採番して衝突を避けるために使用する。
+    /// 最初のツールのIDは `tool_<start + 1>` になる。
+    pub fn with_id_start(input: BoxStream<'static, String>, start: u64) -> Self {
+        let mut parser = Self::new(input);
+        parser.core = parser.core.id_start(start);
+        parser
+    }
 
-処理が完了しました。"#;
+    /// ツール呼び出しのIDの形式を設定する
+    pub fn id_scheme(mut self, scheme: IdScheme) -> Self {
+        self.core = self.core.id_scheme(scheme);
+        self
+    }
 
-    // 入力テキストを1文字ずつのストリームに変換
-    let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
-
-    // ストリームを処理
-    match stream_to_stream(input_stream) {
-        Ok(mut stream) => {
-            // イベントを順番に処理
-            while let Some(event) = stream.next().await {
-                match event {
-                    ToolCallEvent::Text(text) => {
-                        // テキストイベントの処理
-                        print!("{}", text);
+    /// スナップショットから、途中まで処理したパーサーを復元する
+    ///
+    /// `input` には、スナップショットを取得した時点より後の入力を渡す。
+    pub fn from_snapshot(snapshot: ParserSnapshot, input: BoxStream<'static, String>) -> Self {
+        Self {
+            input,
+            char_buffer: snapshot.char_buffer,
+            char_offset: 0,
+            core: snapshot.core,
+        }
+    }
+
+    /// パラメータの受信中に `window` の間入力が途絶えた場合、暫定値を `TentativeParameter` として発行する
+    ///
+    /// 閉じタグを待たずに値を表示したいUI向け。同じ暫定値は繰り返し発行しない。
+    pub fn debounce_params(self, window: Duration) -> BoxStream<'static, ToolCallEvent> {
+        futures::stream::unfold(
+            (self, None::<serde_json::Value>),
+            move |(mut parser, mut last_tentative)| async move {
+                let event = loop {
+                    tokio::select! {
+                        event = parser.next() => break event?,
+                        _ = tokio::time::sleep(window) => {
+                            // 入力が途絶えている間に、受信途中のパラメータの暫定値が変化していれば発行する
+                            if let Some((id, arguments)) = parser.core.tentative_arguments()
+                                && last_tentative.as_ref() != Some(&arguments)
+                            {
+                                last_tentative = Some(arguments.clone());
+                                break ToolCallEvent::TentativeParameter { id, arguments };
+                            }
+                        }
                     }
-                    ToolCallEvent::ToolStart { id, name } => {
-                        // ツール開始イベントの処理
-                        println!("\n[ツール開始: {} (ID: {})]", name, id);
+                };
+                if matches!(
+                    event,
+                    ToolCallEvent::Parameter { .. } | ToolCallEvent::ToolEnd { .. }
+                ) {
+                    last_tentative = None;
+                }
+                Some((event, (parser, last_tentative)))
+            },
+        )
+        .boxed()
+    }
+
+    /// 現在の解析状態（状態遷移、処理中のツールとID、IDカウンター、各バッファ、設定）のスナップショットを取得する
+    pub fn snapshot(&self) -> ParserSnapshot {
+        ParserSnapshot {
+            core: self.core.clone(),
+            char_buffer: self.pending_chars().to_string(),
+        }
+    }
+
+    /// パラメータタグの開始・終了を `ParameterStart` / `ParameterEnd` イベントとして発行する
+    ///
+    /// フォームUIなどで、パラメータごとに入力欄を作成・確定したい場合に使用する。
+    pub fn parameter_events(mut self, enabled: bool) -> Self {
+        self.core = self.core.parameter_events(enabled);
+        self
+    }
+
+    /// パラメータの値を、受信したチャンクごとに `ParameterDelta` イベントとしても発行する
+    ///
+    /// チャンクを処理し終えるたびに、その時点で確定した値の差分を発行する。
+    pub fn parameter_deltas(mut self, enabled: bool) -> Self {
+        self.core = self.core.parameter_deltas(enabled);
+        self
+    }
+
+    /// 入力の終了時に、集計結果を `StreamSummary` イベントとして1回だけ発行する
+    pub fn summary_event(mut self, enabled: bool) -> Self {
+        self.core = self.core.summary_event(enabled);
+        self
+    }
+
+    /// タグの区切り文字を変更する
+    pub fn delimiters(mut self, delimiters: Delimiters) -> Self {
+        self.core = self.core.delimiters(delimiters);
+        self
+    }
+
+    /// パラメータ内の子要素を入れ子のオブジェクトとして解析する
+    pub fn nested_params(mut self, enabled: bool) -> Self {
+        self.core = self.core.nested_params(enabled);
+        self
+    }
+
+    /// パラメータの値の中に現れたタグの扱いを設定する
+    pub fn inner_tag_policy(mut self, policy: InnerTagPolicy) -> Self {
+        self.core = self.core.inner_tag_policy(policy);
+        self
+    }
+
+    /// ツール名を受け付けるかどうかを判定し、拒否したツールの内側を読み飛ばす
+    pub fn validate_tool(mut self, validate: fn(&str) -> bool) -> Self {
+        self.core = self.core.validate_tool(validate);
+        self
+    }
+
+    /// 繰り返される `element` 要素を、`key_attr` 属性の値をキーとして親のオブジェクトに格納する
+    pub fn keyed_list(mut self, element: &str, key_attr: &str) -> Self {
+        self.core = self.core.keyed_list(element, key_attr);
+        self
+    }
+
+    /// パラメータの値の空白の扱いを設定する
+    pub fn whitespace_mode(mut self, mode: WhitespaceMode) -> Self {
+        self.core = self.core.whitespace_mode(mode);
+        self
+    }
+
+    /// タグとしてバッファする長さの上限を設定する
+    pub fn max_tag_name_len(mut self, len: usize) -> Self {
+        self.core = self.core.max_tag_name_len(len);
+        self
+    }
+
+    /// パラメータの値全体を囲むコードフェンスを取り除く
+    pub fn strip_content_fence(mut self, enabled: bool) -> Self {
+        self.core = self.core.strip_content_fence(enabled);
+        self
+    }
+
+    /// 実体参照の展開後の値に `<` か `>` を含むパラメータについて、`SuspiciousValue` イベントを発行する
+    pub fn warn_suspicious_values(mut self, enabled: bool) -> Self {
+        self.core = self.core.warn_suspicious_values(enabled);
+        self
+    }
+
+    /// ツールの外側の、タグを含まないテキストをまとめて1つの `Text` イベントとして発行する
+    pub fn coalesce_text(mut self, enabled: bool) -> Self {
+        self.core = self.core.coalesce_text(enabled);
+        self
+    }
+
+    /// `threshold` バイトを超えたパラメータの値を、`dir` に作成する一時ファイルに書き出す
+    pub fn spill_params(mut self, threshold: usize, dir: impl Into<PathBuf>) -> Self {
+        self.core = self.core.spill_params(threshold, dir);
+        self
+    }
+
+    /// 指定したツールのパラメータの値を、受信したチャンクごとに `sink` へ書き込むストリームに変換する
+    ///
+    /// 値はメモリに保持せず、`Parameter` イベントにも含めないため、巨大なファイル内容を一定のメモリで書き込める。
+    /// 値はトリムや実体参照の展開をせずにそのまま書き込む。書き込みに失敗した場合は `Error` イベントを発行する。
+    pub fn on_content_stream<W>(
+        mut self,
+        tool_name: &str,
+        param_name: &str,
+        sink: W,
+    ) -> BoxStream<'static, ToolCallEvent>
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        self.core = self.core.stream_param(tool_name, param_name);
+        let state = (self, sink, VecDeque::new(), false);
+        futures::stream::unfold(
+            state,
+            |(mut parser, mut sink, mut queued, mut done)| async move {
+                loop {
+                    if let Some(event) = queued.pop_front() {
+                        return Some((event, (parser, sink, queued, done)));
                     }
-                    ToolCallEvent::Parameter { id, arguments } => {
-                        // パラメータイベントの処理
-                        println!(
-                            "[パラメータ (ID: {}): {}]",
-                            id,
-                            serde_json::to_string_pretty(&arguments).unwrap()
-                        );
+                    if done {
+                        return None;
                     }
-                    ToolCallEvent::ToolEnd { id } => {
-                        // ツール終了イベントの処理
-                        println!("[ツール終了 (ID: {})]\n", id);
+
+                    let chunk = if parser.pending_chars().is_empty() {
+                        parser.input.next().await
+                    } else {
+                        Some(parser.take_pending_chars())
+                    };
+                    match chunk {
+                        Some(chunk) => {
+                            for c in chunk.chars() {
+                                queued.extend(parser.core.take_pending_event());
+                                queued.extend(parser.process_char(c.encode_utf8(&mut [0; 4])));
+                            }
+                        }
+                        None => {
+                            queued.extend(parser.core.take_pending_event());
+                            queued.extend(parser.core.finish());
+                            done = true;
+                        }
                     }
-                    ToolCallEvent::Error(err) => {
-                        eprintln!("エラー: {}", err);
+
+                    // イベントを発行する前に、このチャンクで確定した値を書き込む
+                    let delta = parser.core.take_param_delta();
+                    if !delta.is_empty() {
+                        let written = async {
+                            sink.write_all(delta.as_bytes()).await?;
+                            sink.flush().await
+                        };
+                        if let Err(e) = written.await {
+                            queued.push_front(ToolCallEvent::Error(format!(
+                                "failed to write streamed parameter: {}",
+                                e
+                            )));
+                        }
                     }
                 }
-            }
-        }
-        Err(e) => {
-            eprintln!("ストリームの作成に失敗しました: {}", e);
-        }
+            },
+        )
+        .boxed()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use pretty_assertions::assert_eq;
-    use tokio_stream::StreamExt;
+    /// 受信済みで未処理の文字
+    fn pending_chars(&self) -> &str {
+        &self.char_buffer[self.char_offset..]
+    }
 
-    #[tokio::test]
-    async fn test_stream_to_stream_only_text() -> Result<()> {
-        let input = r#"明日のニューヨークの天気ですね。承知いたしました。
+    /// 未処理の文字を取り出し、バッファを空にする
+    fn take_pending_chars(&mut self) -> String {
+        let mut chars = std::mem::take(&mut self.char_buffer);
+        chars.drain(..std::mem::take(&mut self.char_offset));
+        chars
+    }
 
-結果が取得でき次第、すぐにお知らせします。"#;
-        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+    /// 1文字を処理し、必要に応じてイベントを生成
+    fn process_char(&mut self, c: &str) -> Option<ToolCallEvent> {
+        self.core.process_char(c)
+    }
 
-        let expected_events = vec![
-            // 一文字ずつ返す
-            ToolCallEvent::Text("明".into()),
-            ToolCallEvent::Text("日".into()),
-            ToolCallEvent::Text("の".into()),
-            ToolCallEvent::Text("ニ".into()),
-            ToolCallEvent::Text("ュ".into()),
-            ToolCallEvent::Text("ー".into()),
-            ToolCallEvent::Text("ヨ".into()),
-            ToolCallEvent::Text("ー".into()),
-            ToolCallEvent::Text("ク".into()),
-            ToolCallEvent::Text("の".into()),
-            ToolCallEvent::Text("天".into()),
-            ToolCallEvent::Text("気".into()),
-            ToolCallEvent::Text("で".into()),
-            ToolCallEvent::Text("す".into()),
-            ToolCallEvent::Text("ね".into()),
-            ToolCallEvent::Text("。".into()),
-            ToolCallEvent::Text("承".into()),
-            ToolCallEvent::Text("知".into()),
-            ToolCallEvent::Text("い".into()),
-            ToolCallEvent::Text("た".into()),
-            ToolCallEvent::Text("し".into()),
-            ToolCallEvent::Text("ま".into()),
-            ToolCallEvent::Text("し".into()),
-            ToolCallEvent::Text("た".into()),
-            ToolCallEvent::Text("。".into()),
-            ToolCallEvent::Text("\n".into()),
-            ToolCallEvent::Text("\n".into()),
-            ToolCallEvent::Text("結".into()),
-            ToolCallEvent::Text("果".into()),
-            ToolCallEvent::Text("が".into()),
-            ToolCallEvent::Text("取".into()),
-            ToolCallEvent::Text("得".into()),
-            ToolCallEvent::Text("で".into()),
-            ToolCallEvent::Text("き".into()),
-            ToolCallEvent::Text("次".into()),
-            ToolCallEvent::Text("第".into()),
-            ToolCallEvent::Text("、".into()),
-            ToolCallEvent::Text("す".into()),
-            ToolCallEvent::Text("ぐ".into()),
-            ToolCallEvent::Text("に".into()),
-            ToolCallEvent::Text("お".into()),
-            ToolCallEvent::Text("知".into()),
-            ToolCallEvent::Text("ら".into()),
-            ToolCallEvent::Text("せ".into()),
-            ToolCallEvent::Text("し".into()),
-            ToolCallEvent::Text("ま".into()),
-            ToolCallEvent::Text("す".into()),
-            ToolCallEvent::Text("。".into()),
-        ];
-        let mut stream = stream_to_stream(input_stream)?;
-        let mut events = Vec::new();
-        while let Some(event) = stream.next().await {
-            events.push(event);
+    /// ストリームを最後まで読み、完了したツール呼び出しごとに `f` を呼び出す
+    ///
+    /// テキストや、`ToolEnd` を受信する前に途切れたツール呼び出しは無視する。
+    pub async fn for_each_tool<F: FnMut(ToolCall)>(mut self, mut f: F) {
+        let mut assembler = ToolCallAssembler::new();
+        while let Some(event) = self.next().await {
+            if let Some(tool_call) = assembler.push(&event) {
+                f(tool_call.call);
+            }
         }
-        assert_eq!(events, expected_events);
-        Ok(())
     }
 
-    #[tokio::test]
-    async fn test_stream_to_stream_only_tool_call() -> Result<()> {
-        let input = r#"<get_weather>
-  <location>New York</location>
-  <date>tomorrow</date>
-  <unit>fahrenheit</unit>
-</get_weather>"#;
-        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+    /// すべてのイベントに `context` を付けたストリームに変換する
+    ///
+    /// リクエストIDとの相関などのために、消費側で各イベントを包み直す必要がなくなる。
+    /// コンテキストは `Arc` で共有するため、イベントごとに文字列を複製しない。
+    pub fn with_context(self, context: impl Into<Arc<str>>) -> BoxStream<'static, ContextEvent> {
+        let context = context.into();
+        self.map(move |event| ContextEvent {
+            context: context.clone(),
+            event,
+        })
+        .boxed()
+    }
 
-        let expected_events = vec![
-            // 一文字ずつ返す
-            ToolCallEvent::ToolStart {
-                id: "tool_1".to_string(),
-                name: "get_weather".to_string(),
-            },
-            ToolCallEvent::Parameter {
-                id: "tool_1".to_string(),
-                arguments: serde_json::json!({
-                    "location": "New York",
-                    "date": "tomorrow",
-                    "unit": "fahrenheit"
-                }),
-            },
-            ToolCallEvent::ToolEnd {
-                id: "tool_1".to_string(),
-            },
-        ];
-        let mut stream = stream_to_stream(input_stream)?;
-        let mut events = Vec::new();
-        while let Some(event) = stream.next().await {
-            events.push(event);
-        }
-        assert_eq!(events, expected_events);
-        Ok(())
+    /// パーサーの内部状態を人が読める形式で返す
+    ///
+    /// ツールが閉じられないままストリームが止まった場合などのトラブルシューティング用。
+    /// 処理のホットパスからは呼び出さないこと。
+    pub fn debug_state(&self) -> String {
+        format!(
+            "{}\nchar_buffer_len: {}",
+            self.core.debug_state(),
+            self.pending_chars().len()
+        )
     }
 
-    /// メインのストリーム変換テスト
+    /// 受信したがイベントとして発行していない、途中のタグやパラメータの値などのデータを返す
     ///
-    /// このテストでは以下の点を確認します：
-    /// - テキストの1文字ずつの処理
-    /// - XMLタグの適切な解析
-    /// - パラメータの収集と出力
-    /// - 改行の適切な処理
-    #[tokio::test]
-    async fn test_stream_to_stream() -> Result<()> {
-        let input = r#"明日のニューヨークの天気ですね。承知いたしました。
+    /// ストリームの終了後に呼び出し、途中で打ち切られた応答の原因調査に使用する。
+    pub fn leftover(&self) -> String {
+        let mut leftover = self.core.leftover();
+        leftover.push_str(self.pending_chars());
+        leftover
+    }
+}
 
-<get_weather>
-  <location>New York</location>
-  <date>tomorrow</date>
-  <unit>fahrenheit</unit>
-</get_weather>
+/// Stream traitの実装
+impl Stream for XmlStreamParser {
+    type Item = ToolCallEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.as_mut().get_mut();
+
+        // 入力の大きさによらずスタックを消費しないよう、再帰せずにループで処理する
+        loop {
+            // ToolEndイベントの遅延発行
+            if let Some(event) = this.core.take_pending_event() {
+                return Poll::Ready(Some(event));
+            }
+
+            // バッファに残っている文字がある場合は、それを処理
+            let rest = &this.char_buffer[this.char_offset..];
+            if let Some(c) = rest.chars().next() {
+                // タグの開始より前のテキストは、可能であれば1文字ずつ処理せずにまとめて発行する
+                if let Some(plain_len) = this.core.plain_text_len(rest)
+                    && let Some(event) = this.core.try_plain_text(&rest[..plain_len])
+                {
+                    this.char_offset += plain_len;
+                    return Poll::Ready(Some(event));
+                }
+                this.char_offset += c.len_utf8();
+                if let Some(event) = this.core.process_char(c.encode_utf8(&mut [0; 4])) {
+                    return Poll::Ready(Some(event));
+                }
+                continue;
+            }
+
+            // チャンクを処理し終えた時点で確定した値の差分を発行
+            if let Some(event) = this.core.flush_parameter_delta() {
+                return Poll::Ready(Some(event));
+            }
+
+            // 入力ストリームからの次の文字列を処理
+            match this.input.poll_next_unpin(cx) {
+                Poll::Ready(Some(s)) => {
+                    this.char_buffer = s;
+                    this.char_offset = 0;
+                }
+                Poll::Ready(None) => return Poll::Ready(this.core.finish()),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// 文字列全体を同期的にパースするイテレーター
+///
+/// 入力全体が手元にある場合に、非同期ランタイムを使わずに `stream_to_stream` と同じイベントを取り出せる。
+pub struct StrEvents<'a> {
+    /// 入力文字列
+    input: &'a str,
+    /// 次に処理するバイト位置
+    position: usize,
+    /// パーサー本体
+    core: XmlParserCore,
+}
+
+impl<'a> StrEvents<'a> {
+    /// 設定済みのパーサー本体で入力文字列をパースするイテレーターを作成
+    pub fn new(input: &'a str, core: XmlParserCore) -> Self {
+        Self {
+            input,
+            position: 0,
+            core,
+        }
+    }
+
+    /// テキストを所有する `String` ではなく、元の入力を参照するバイト範囲として返すイテレーターに変換する
+    pub fn text_spans(self) -> SpannedEvents<'a> {
+        SpannedEvents { events: self }
+    }
+
+    /// 各イベントと、そのイベントを生成した入力のバイト範囲の組を返すイテレーターに変換する
+    pub fn with_ranges(self) -> RangedEvents<'a> {
+        RangedEvents {
+            events: self,
+            consumed: 0,
+        }
+    }
+
+    /// 1文字を処理する。入力の終端に達した場合は、集計イベントを返した後に `None` を返す
+    fn step(&mut self) -> Option<Option<ToolCallEvent>> {
+        let rest = &self.input[self.position..];
+        if let Some(plain_len) = self.core.plain_text_len(rest)
+            && let Some(event) = self.core.try_plain_text(&rest[..plain_len])
+        {
+            self.position += plain_len;
+            return Some(Some(event));
+        }
+        let Some(c) = self.input[self.position..].chars().next() else {
+            return self.core.finish().map(Some);
+        };
+        self.position += c.len_utf8();
+        Some(self.core.process_char(c.encode_utf8(&mut [0; 4])))
+    }
+}
+
+impl Iterator for StrEvents<'_> {
+    type Item = ToolCallEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.core.take_pending_event() {
+                return Some(event);
+            }
+            if let Some(event) = self.step()? {
+                return Some(event);
+            }
+        }
+    }
+}
+
+/// `SpannedEvents` が返すイベント
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpannedEvent {
+    /// テキスト：元の入力の `start..end` のバイト範囲。連続するテキストは1つの範囲にまとめられる
+    Text { start: usize, end: usize },
+    /// テキスト以外のイベント
+    Event(ToolCallEvent),
+}
+
+/// テキストを元の入力への範囲として返す同期イテレーター
+///
+/// `StrEvents::text_spans` で作成する。テキストごとの `String` の確保を避けられる。
+pub struct SpannedEvents<'a> {
+    events: StrEvents<'a>,
+}
+
+impl Iterator for SpannedEvents<'_> {
+    type Item = SpannedEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let events = &mut self.events;
+        loop {
+            if let Some(event) = events.core.take_pending_event() {
+                return Some(SpannedEvent::Event(event));
+            }
+            // タグの外側では、次の `<` までをまとめてテキスト範囲として返す
+            if events.core.is_in_text() {
+                let rest = &events.input[events.position..];
+                let len = rest.find(events.core.delimiters.open).unwrap_or(rest.len());
+                if len > 0 {
+                    let start = events.position;
+                    events.position += len;
+                    return Some(SpannedEvent::Text {
+                        start,
+                        end: events.position,
+                    });
+                }
+            }
+            if let Some(event) = events.step()? {
+                return Some(SpannedEvent::Event(event));
+            }
+        }
+    }
+}
+
+/// イベントと入力のバイト範囲の組を返す同期イテレーター
+///
+/// `StrEvents::with_ranges` で作成する。各範囲は直前のイベント以降に消費した入力を表し、
+/// 範囲同士は隙間なく連続する。テキストはその文字のバイト、ツールのイベントはタグ（とその間の空白や
+/// パラメータ）のバイトに対応し、保留されていた `ToolEnd` は空の範囲になる。
+/// 入力がタグの途中で終わった場合、末尾の未完了部分はどの範囲にも含まれない。
+pub struct RangedEvents<'a> {
+    events: StrEvents<'a>,
+    /// 既にイベントに割り当てた入力のバイト数
+    consumed: usize,
+}
+
+impl Iterator for RangedEvents<'_> {
+    type Item = (ToolCallEvent, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.events.core.take_pending_event() {
+                return Some((event, self.consumed..self.consumed));
+            }
+            if let Some(event) = self.events.step()? {
+                let range = self.consumed..self.events.position;
+                self.consumed = self.events.position;
+                return Some((event, range));
+            }
+        }
+    }
+}
+
+/// 文字列をパースし、イベントを返す同期イテレーターを作成
+pub fn parse_str(input: &str) -> StrEvents<'_> {
+    StrEvents::new(input, XmlParserCore::new())
+}
+
+/// 受信済みのチャンクの列を同期的にパースし、すべてのイベントを返す
+///
+/// SSEのパーサーなどからチャンクを `Vec<String>` で受け取っており、非同期ランタイムを使わない場合に使用する。
+/// チャンクの境界に関わらず、同じチャンクを `stream_to_stream` に渡した場合と同じイベントを返す。
+pub fn parse_chunks(chunks: &[&str]) -> Vec<ToolCallEvent> {
+    let mut core = XmlParserCore::new();
+    let mut events = Vec::new();
+    for c in chunks.iter().flat_map(|chunk| chunk.chars()) {
+        events.extend(core.take_pending_event());
+        events.extend(core.process_char(c.encode_utf8(&mut [0; 4])));
+    }
+    events.extend(core.take_pending_event());
+    events.extend(core.finish());
+    events
+}
+
+/// 入力ストリームをツール呼び出しイベントのストリームに変換
+pub fn stream_to_stream(input: BoxStream<'static, String>) -> ToolCallStreamResult {
+    let stream = XmlStreamParser::new(input);
+    Ok(Box::pin(stream))
+}
+
+/// 入力ストリームをイベントストリームに変換するパーサーの共通インターフェース
+///
+/// 厳密なXML、寛容なXML、JSON埋め込みなどの実装を `Box<dyn ToolCallParser>` として差し替えるために使用する。
+/// トレイトオブジェクトとして扱えるよう、`async fn` の代わりに `BoxFuture` を返す。
+pub trait ToolCallParser: Send + Sync {
+    /// 入力ストリームを解析し、イベントのストリームを返す
+    fn parse_stream(
+        &self,
+        input: BoxStream<'static, String>,
+    ) -> BoxFuture<'_, BoxStream<'static, ToolCallEvent>>;
+}
+
+/// 設定済みのパーサー本体を複製し、入力ごとに `XmlStreamParser` を作成する
+impl ToolCallParser for XmlParserCore {
+    fn parse_stream(
+        &self,
+        input: BoxStream<'static, String>,
+    ) -> BoxFuture<'_, BoxStream<'static, ToolCallEvent>> {
+        let core = self.clone();
+        Box::pin(async move { XmlStreamParser::with_core(input, core).boxed() })
+    }
+}
+
+/// `spawn_parser` が使用するチャネルの容量
+const SPAWN_PARSER_CHANNEL_CAPACITY: usize = 64;
+
+/// パーサーを tokio のタスクとして起動し、イベントを有界チャネル経由で受け取る
+///
+/// チャネルが満杯の間はパーサー側が待機するため、消費側の速度に合わせて入力の読み取りが抑えられる。
+/// 受信側を破棄するとタスクは終了する。tokio ランタイム内で呼び出す必要がある。
+pub fn spawn_parser(input: BoxStream<'static, String>) -> mpsc::Receiver<ToolCallEvent> {
+    let (tx, rx) = mpsc::channel(SPAWN_PARSER_CHANNEL_CAPACITY);
+    tokio::spawn(async move {
+        let mut events = XmlStreamParser::new(input);
+        while let Some(event) = events.next().await {
+            if tx.send(event).await.is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// 1つの入力から解析した、テキストとツール呼び出しの2つのストリーム
+///
+/// `split_text_and_tools` で作成する。
+pub struct SplitStreams {
+    /// ツール呼び出し以外のテキスト
+    pub text_stream: BoxStream<'static, String>,
+    /// 完成したツール呼び出し
+    pub tool_stream: BoxStream<'static, ToolCall>,
+}
+
+/// パーサーを tokio のタスクとして起動し、テキストと完成したツール呼び出しを別々のストリームで受け取る
+///
+/// 文章とツールのカードを別の領域に表示するUI向け。解析は1回だけ行い、2つのチャネルに振り分ける。
+/// 一方のストリームだけを読み進めても解析が止まらないよう、チャネルは有界ではない。
+/// tokio ランタイム内で呼び出す必要がある。
+pub fn split_text_and_tools(input: BoxStream<'static, String>) -> SplitStreams {
+    let (text_tx, text_rx) = mpsc::unbounded_channel();
+    let (tool_tx, tool_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut events = XmlStreamParser::new(input);
+        let mut assembler = ToolCallAssembler::new();
+        while let Some(event) = events.next().await {
+            if let ToolCallEvent::Text(text) = &event {
+                let _ = text_tx.send(text.clone());
+            }
+            if let Some(streamed) = assembler.push(&event) {
+                let _ = tool_tx.send(streamed.call);
+            }
+            if text_tx.is_closed() && tool_tx.is_closed() {
+                break;
+            }
+        }
+    });
+    SplitStreams {
+        text_stream: receiver_stream(text_rx),
+        tool_stream: receiver_stream(tool_rx),
+    }
+}
+
+/// チャネルの受信側をストリームに変換する
+fn receiver_stream<T: Send + 'static>(rx: mpsc::UnboundedReceiver<T>) -> BoxStream<'static, T> {
+    futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    })
+    .boxed()
+}
+
+/// 複数の入力ストリームを1つの入力として連結する
+///
+/// 各ストリームは渡された順に最後まで読み取られ、終了してから次のストリームに進む（交互には読み取らない）。
+/// 推論とコンテンツのように、1つのツール呼び出しが複数のチャネルにまたがって届く場合に使用する。
+pub fn chain_inputs(inputs: Vec<BoxStream<'static, String>>) -> BoxStream<'static, String> {
+    futures::stream::iter(inputs).flatten().boxed()
+}
+
+/// `strip_boundaries` の内部状態
+struct BoundaryStrip {
+    input: BoxStream<'static, String>,
+    /// 区切り行（`--boundary`）
+    delimiter: String,
+    /// 最後の区切り行（`--boundary--`）
+    close_delimiter: String,
+    /// まだ出力していない入力
+    buffer: String,
+    /// `buffer` が行の先頭から始まっているかどうか
+    at_line_start: bool,
+    /// 次の行が区切り行でないと分かるまで出力を保留している改行
+    held_break: String,
+    /// 入力ストリームが終了したかどうか
+    done: bool,
+}
+
+impl BoundaryStrip {
+    fn is_delimiter(&self, line: &str) -> bool {
+        let line = line.trim_end();
+        line == self.delimiter || line == self.close_delimiter
+    }
+
+    /// 区切り行でないと確定した部分を取り出す。`finished` の場合は残りをすべて確定させる
+    fn drain(&mut self, finished: bool) -> String {
+        let mut output = String::new();
+        loop {
+            if !self.at_line_start {
+                let Some(newline) = self.buffer.find('\n') else {
+                    output.push_str(&self.buffer);
+                    self.buffer.clear();
+                    break;
+                };
+                let line_end = if self.buffer[..newline].ends_with('\r') {
+                    newline - 1
+                } else {
+                    newline
+                };
+                output.push_str(&self.buffer[..line_end]);
+                self.held_break = self.buffer[line_end..=newline].to_string();
+                self.buffer.drain(..=newline);
+                self.at_line_start = true;
+                continue;
+            }
+
+            let newline = self.buffer.find('\n');
+            let line = &self.buffer[..newline.unwrap_or(self.buffer.len())];
+            if newline.is_none() && !finished {
+                // 受信途中の行が区切り行になりうる間は出力を保留する
+                if self.close_delimiter.starts_with(line.trim_end()) {
+                    break;
+                }
+            } else if self.is_delimiter(line) {
+                // 区切り行は、直前の改行とあわせて取り除く
+                self.held_break.clear();
+                match newline {
+                    Some(newline) => drop(self.buffer.drain(..=newline)),
+                    None => self.buffer.clear(),
+                }
+                if self.buffer.is_empty() && finished {
+                    break;
+                }
+                continue;
+            }
+            output.push_str(&std::mem::take(&mut self.held_break));
+            self.at_line_start = false;
+            if self.buffer.is_empty() {
+                break;
+            }
+        }
+        output
+    }
+}
+
+/// 入力ストリームから、MIMEのマルチパート形式の区切り行（`--boundary` と `--boundary--`）を取り除く
+///
+/// 区切り行の直前の改行も区切りの一部として取り除くため、パートをまたいで分割されたツール呼び出しのXMLも
+/// 1つにつながる。区切り行になりうる受信途中の行は、行が確定するまで出力を保留する。
+pub fn strip_boundaries(
+    input: BoxStream<'static, String>,
+    boundary: &str,
+) -> BoxStream<'static, String> {
+    let state = BoundaryStrip {
+        input,
+        delimiter: format!("--{boundary}"),
+        close_delimiter: format!("--{boundary}--"),
+        buffer: String::new(),
+        at_line_start: true,
+        held_break: String::new(),
+        done: false,
+    };
+
+    futures::stream::unfold(state, |mut state| async move {
+        loop {
+            if state.done {
+                return None;
+            }
+            let output = state.drain(false);
+            if !output.is_empty() {
+                return Some((output, state));
+            }
+            match state.input.next().await {
+                Some(chunk) => state.buffer.push_str(&chunk),
+                None => {
+                    state.done = true;
+                    let output = state.drain(true);
+                    return (!output.is_empty()).then_some((output, state));
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+/// `throttle_text` の内部状態
+struct TextThrottle<S> {
+    events: S,
+    interval: Interval,
+    /// まだ発行していないテキスト
+    text: String,
+    /// テキストを先に発行するために待たせているイベント
+    queued: VecDeque<ToolCallEvent>,
+    /// 入力ストリームが終了したかどうか
+    done: bool,
+}
+
+/// `Text` イベントを一定間隔でまとめて発行するストリームに変換する
+///
+/// テキストは `period` ごとに1つの `Text` イベントにまとめて発行し、それ以外のイベントは即座に発行する。
+/// 順序を保つため、テキスト以外のイベントの直前にはそれまでのテキストを発行する。
+pub fn throttle_text<S>(events: S, period: Duration) -> BoxStream<'static, ToolCallEvent>
+where
+    S: Stream<Item = ToolCallEvent> + Unpin + Send + 'static,
+{
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let state = TextThrottle {
+        events,
+        interval,
+        text: String::new(),
+        queued: VecDeque::new(),
+        done: false,
+    };
+
+    futures::stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(event) = state.queued.pop_front() {
+                return Some((event, state));
+            }
+            if state.done {
+                if state.text.is_empty() {
+                    return None;
+                }
+                let text = std::mem::take(&mut state.text);
+                return Some((ToolCallEvent::Text(text), state));
+            }
+            tokio::select! {
+                event = state.events.next() => match event {
+                    Some(ToolCallEvent::Text(text)) => state.text.push_str(&text),
+                    Some(event) if state.text.is_empty() => return Some((event, state)),
+                    Some(event) => {
+                        state.queued.push_back(event);
+                        let text = std::mem::take(&mut state.text);
+                        return Some((ToolCallEvent::Text(text), state));
+                    }
+                    None => state.done = true,
+                },
+                _ = state.interval.tick(), if !state.text.is_empty() => {
+                    let text = std::mem::take(&mut state.text);
+                    return Some((ToolCallEvent::Text(text), state));
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+/// 相関用のコンテキストを付けたイベント
+///
+/// `XmlStreamParser::with_context` で作成する。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextEvent {
+    /// リクエストIDなど、このストリームのすべてのイベントに共通するコンテキスト
+    pub context: Arc<str>,
+    /// パーサーが発行したイベント
+    pub event: ToolCallEvent,
+}
+
+/// イベントから組み立てたツール呼び出し
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamedToolCall {
+    /// 組み立てたツール呼び出し
+    pub call: ToolCall,
+    /// `ToolEnd` まで受信したかどうか。`false` の場合は途中でストリームが終了している
+    pub complete: bool,
+}
+
+/// `ToolStart` / `Parameter` / `ToolEnd` イベントから `ToolCall` を組み立てる
+#[derive(Debug, Default)]
+pub struct ToolCallAssembler {
+    /// 組み立て中のツール呼び出し
+    current: Option<ToolCall>,
+}
+
+impl ToolCallAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// イベントを取り込み、`ToolEnd` で完成したツール呼び出しを返す
+    pub fn push(&mut self, event: &ToolCallEvent) -> Option<StreamedToolCall> {
+        match event {
+            ToolCallEvent::ToolStart { name, .. } => {
+                self.current = Some(ToolCall {
+                    tool_name: name.clone(),
+                    parameters: HashMap::new(),
+                    attributes: HashMap::new(),
+                    spans: HashMap::new(),
+                });
+                None
+            }
+            ToolCallEvent::Parameter { arguments, .. } => {
+                if let (Some(call), serde_json::Value::Object(arguments)) =
+                    (self.current.as_mut(), arguments)
+                {
+                    for (key, value) in arguments {
+                        let value = match value {
+                            serde_json::Value::String(value) => value.clone(),
+                            value => value.to_string(),
+                        };
+                        call.parameters.insert(key.clone(), value);
+                    }
+                }
+                None
+            }
+            ToolCallEvent::ToolEnd { .. } => self.current.take().map(|call| StreamedToolCall {
+                call,
+                complete: true,
+            }),
+            _ => None,
+        }
+    }
+
+    /// 入力の終了時に、`ToolEnd` を受信していない組み立て途中のツール呼び出しを返す
+    pub fn finish(self) -> Option<StreamedToolCall> {
+        self.current.map(|call| StreamedToolCall {
+            call,
+            complete: false,
+        })
+    }
+}
+
+/// イベントストリームを最後まで読み、ツール呼び出しを組み立てて返す
+///
+/// ストリームが `ToolEnd` の前に終了した場合、最後の要素は `complete: false` になる。
+pub async fn collect_tool_calls<S>(mut events: S) -> Vec<StreamedToolCall>
+where
+    S: Stream<Item = ToolCallEvent> + Unpin,
+{
+    let mut assembler = ToolCallAssembler::new();
+    let mut tool_calls = Vec::new();
+    while let Some(event) = events.next().await {
+        tool_calls.extend(assembler.push(&event));
+    }
+    tool_calls.extend(assembler.finish());
+    tool_calls
+}
+
+/// イベントストリームを、完成したツール呼び出しのストリームに変換する
+///
+/// `ToolEnd` まで受信したツール呼び出しのみを返し、テキストや途中で終了したツール呼び出しは捨てる。
+pub fn stream_by_tool<S>(events: S) -> BoxStream<'static, ToolCall>
+where
+    S: Stream<Item = ToolCallEvent> + Send + 'static,
+{
+    events
+        .scan(ToolCallAssembler::new(), |assembler, event| {
+            futures::future::ready(Some(assembler.push(&event).map(|streamed| streamed.call)))
+        })
+        .filter_map(futures::future::ready)
+        .boxed()
+}
+
+/// 直前と同じツール呼び出しを取り除く
+///
+/// ツール名とパラメータが一致する場合に同一とみなす。連続していない重複は取り除かない。
+pub fn dedup_tools<S>(tool_calls: S) -> BoxStream<'static, ToolCall>
+where
+    S: Stream<Item = ToolCall> + Send + 'static,
+{
+    tool_calls
+        .scan(None::<ToolCall>, |previous, tool_call| {
+            let is_duplicate = previous.as_ref().is_some_and(|previous| {
+                previous.tool_name == tool_call.tool_name
+                    && previous.parameters == tool_call.parameters
+            });
+            *previous = Some(tool_call.clone());
+            futures::future::ready(Some((!is_duplicate).then_some(tool_call)))
+        })
+        .filter_map(futures::future::ready)
+        .boxed()
+}
+
+/// `collect_bounded` で上限を超えた場合の動作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// 最も古いイベントを捨てて、最新の `cap` 件を保持する
+    DropOldest,
+    /// `ToolCallStreamError::BufferOverflow` を返す
+    Error,
+}
+
+/// イベントストリームを最大 `cap` 件まで集める
+///
+/// 上限を超えた場合の動作は `policy` で指定する。
+pub async fn collect_bounded<S>(
+    mut events: S,
+    cap: usize,
+    policy: OverflowPolicy,
+) -> Result<Vec<ToolCallEvent>>
+where
+    S: Stream<Item = ToolCallEvent> + Unpin,
+{
+    let mut buffer = VecDeque::with_capacity(cap);
+    while let Some(event) = events.next().await {
+        if buffer.len() == cap {
+            match policy {
+                OverflowPolicy::DropOldest => {
+                    // cap が 0 の場合は何も保持しない
+                    if buffer.pop_front().is_none() {
+                        continue;
+                    }
+                }
+                OverflowPolicy::Error => return Err(ToolCallStreamError::BufferOverflow(cap)),
+            }
+        }
+        buffer.push_back(event);
+    }
+    Ok(buffer.into())
+}
+
+/// 各イベントに、この関数を呼び出してからの経過時間を付加する
+///
+/// 実際のモデルのストリームで、イベント間の間隔を計測するために使用する。
+pub fn timestamped<S>(events: S) -> BoxStream<'static, (Duration, ToolCallEvent)>
+where
+    S: Stream<Item = ToolCallEvent> + Send + 'static,
+{
+    let start = tokio::time::Instant::now();
+    events.map(move |event| (start.elapsed(), event)).boxed()
+}
+
+/// 各イベントに対して `f` を呼び出し、イベントはそのまま下流へ渡す
+///
+/// ストリームを二重に消費せずに、ログ出力などの観測処理を挟むために使用する。
+pub fn inspect_events<S, F>(events: S, mut f: F) -> BoxStream<'static, ToolCallEvent>
+where
+    S: Stream<Item = ToolCallEvent> + Send + 'static,
+    F: FnMut(&ToolCallEvent) + Send + 'static,
+{
+    events
+        .map(move |event| {
+            f(&event);
+            event
+        })
+        .boxed()
+}
+
+/// パラメータの値の差分をまとめ、パラメータごとに値全体の `Parameter` イベントに変換する
+///
+/// `parameter_events(true)` と `parameter_deltas(true)` を有効にした同じ入力から、パラメータ全体の粒度で
+/// 表示したい消費側のために使用する。`ParameterStart` から `ParameterEnd` までの `ParameterDelta` を
+/// `(id, パラメータ名)` ごとにバッファし、`ParameterEnd` の位置で `{パラメータ名: 値}` の `Parameter` を発行する。
+/// ツール全体の `Parameter` からは発行済みのパラメータを除き、残りがない場合は発行しない。
+/// `TentativeParameter` は取り除き、その他のイベントはそのまま渡す。
+/// 終了タグを受信しないまま `ToolEnd` に達したパラメータの差分は捨てる。
+pub fn coalesce_param_deltas<S>(events: S) -> BoxStream<'static, ToolCallEvent>
+where
+    S: Stream<Item = ToolCallEvent> + Send + 'static,
+{
+    let mut values: HashMap<(String, String), String> = HashMap::new();
+    let mut emitted: HashSet<(String, String)> = HashSet::new();
+    events
+        .filter_map(move |event| {
+            let event = match event {
+                ToolCallEvent::ParameterStart { id, name } => {
+                    values.insert((id, name), String::new());
+                    None
+                }
+                ToolCallEvent::ParameterDelta { id, name, delta } => {
+                    values.entry((id, name)).or_default().push_str(&delta);
+                    None
+                }
+                ToolCallEvent::ParameterEnd { id, name } => {
+                    let key = (id, name);
+                    let value = values.remove(&key).filter(|value| !value.is_empty());
+                    value.map(|value| {
+                        emitted.insert(key.clone());
+                        let (id, name) = key;
+                        ToolCallEvent::Parameter {
+                            id,
+                            arguments: serde_json::json!({ name: value }),
+                        }
+                    })
+                }
+                ToolCallEvent::Parameter {
+                    id,
+                    arguments: serde_json::Value::Object(mut arguments),
+                } => {
+                    arguments.retain(|name, _| !emitted.remove(&(id.clone(), name.clone())));
+                    (!arguments.is_empty()).then(|| ToolCallEvent::Parameter {
+                        id,
+                        arguments: serde_json::Value::Object(arguments),
+                    })
+                }
+                ToolCallEvent::TentativeParameter { .. } => None,
+                ToolCallEvent::ToolEnd { id } => {
+                    values.retain(|(value_id, _), _| *value_id != id);
+                    emitted.retain(|(value_id, _)| *value_id != id);
+                    Some(ToolCallEvent::ToolEnd { id })
+                }
+                event => Some(event),
+            };
+            futures::future::ready(event)
+        })
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_stream_to_stream_only_text() -> Result<()> {
+        let input = r#"明日のニューヨークの天気ですね。承知いたしました。
 
 結果が取得でき次第、すぐにお知らせします。"#;
         let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
@@ -577,23 +2606,6 @@ mod tests {
             ToolCallEvent::Text("。".into()),
             ToolCallEvent::Text("\n".into()),
             ToolCallEvent::Text("\n".into()),
-            ToolCallEvent::ToolStart {
-                id: "tool_1".to_string(),
-                name: "get_weather".to_string(),
-            },
-            ToolCallEvent::Parameter {
-                id: "tool_1".to_string(),
-                arguments: serde_json::json!({
-                    "location": "New York",
-                    "date": "tomorrow",
-                    "unit": "fahrenheit"
-                }),
-            },
-            ToolCallEvent::ToolEnd {
-                id: "tool_1".to_string(),
-            },
-            ToolCallEvent::Text("\n".into()),
-            ToolCallEvent::Text("\n".into()),
             ToolCallEvent::Text("結".into()),
             ToolCallEvent::Text("果".into()),
             ToolCallEvent::Text("が".into()),
@@ -625,42 +2637,175 @@ mod tests {
         Ok(())
     }
 
-    /// メインのストリーム変換テスト
-    ///
-    /// このテストでは以下の点を確認します：
-    /// - テキストの3文字ずつの処理
-    /// - XMLタグの適切な解析
-    /// - パラメータの収集と出力
-    /// - 改行の適切な処理
     #[tokio::test]
-    async fn test_stream_to_stream_2() -> Result<()> {
-        let input = r#"明日のニューヨークの天気ですね。承知いたしました。
-
-<get_weather>
+    async fn test_stream_to_stream_only_tool_call() -> Result<()> {
+        let input = r#"<get_weather>
   <location>New York</location>
   <date>tomorrow</date>
   <unit>fahrenheit</unit>
-</get_weather>
-
-結果が取得でき次第、すぐにお知らせします。"#;
-        let input_stream = Box::pin(futures::stream::iter(
-            input
-                .chars()
-                .map(|c| c.to_string())
-                .collect::<Vec<_>>()
-                .chunks(3)
-                .map(|chunk| chunk.join(""))
-                .collect::<Vec<_>>()
-                .into_iter(),
-        ));
+</get_weather>"#;
+        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
 
         let expected_events = vec![
             // 一文字ずつ返す
-            ToolCallEvent::Text("明".into()),
-            ToolCallEvent::Text("日".into()),
-            ToolCallEvent::Text("の".into()),
-            ToolCallEvent::Text("ニ".into()),
-            ToolCallEvent::Text("ュ".into()),
+            ToolCallEvent::ToolStart {
+                id: "tool_1".to_string(),
+                name: "get_weather".to_string(),
+            },
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({
+                    "location": "New York",
+                    "date": "tomorrow",
+                    "unit": "fahrenheit"
+                }),
+            },
+            ToolCallEvent::ToolEnd {
+                id: "tool_1".to_string(),
+            },
+        ];
+        let mut stream = stream_to_stream(input_stream)?;
+        let mut events = Vec::new();
+        while let Some(event) = stream.next().await {
+            events.push(event);
+        }
+        assert_eq!(events, expected_events);
+        Ok(())
+    }
+
+    /// メインのストリーム変換テスト
+    ///
+    /// このテストでは以下の点を確認します：
+    /// - テキストの1文字ずつの処理
+    /// - XMLタグの適切な解析
+    /// - パラメータの収集と出力
+    /// - 改行の適切な処理
+    #[tokio::test]
+    async fn test_stream_to_stream() -> Result<()> {
+        let input = r#"明日のニューヨークの天気ですね。承知いたしました。
+
+<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+  <unit>fahrenheit</unit>
+</get_weather>
+
+結果が取得でき次第、すぐにお知らせします。"#;
+        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+
+        let expected_events = vec![
+            // 一文字ずつ返す
+            ToolCallEvent::Text("明".into()),
+            ToolCallEvent::Text("日".into()),
+            ToolCallEvent::Text("の".into()),
+            ToolCallEvent::Text("ニ".into()),
+            ToolCallEvent::Text("ュ".into()),
+            ToolCallEvent::Text("ー".into()),
+            ToolCallEvent::Text("ヨ".into()),
+            ToolCallEvent::Text("ー".into()),
+            ToolCallEvent::Text("ク".into()),
+            ToolCallEvent::Text("の".into()),
+            ToolCallEvent::Text("天".into()),
+            ToolCallEvent::Text("気".into()),
+            ToolCallEvent::Text("で".into()),
+            ToolCallEvent::Text("す".into()),
+            ToolCallEvent::Text("ね".into()),
+            ToolCallEvent::Text("。".into()),
+            ToolCallEvent::Text("承".into()),
+            ToolCallEvent::Text("知".into()),
+            ToolCallEvent::Text("い".into()),
+            ToolCallEvent::Text("た".into()),
+            ToolCallEvent::Text("し".into()),
+            ToolCallEvent::Text("ま".into()),
+            ToolCallEvent::Text("し".into()),
+            ToolCallEvent::Text("た".into()),
+            ToolCallEvent::Text("。".into()),
+            ToolCallEvent::Text("\n".into()),
+            ToolCallEvent::Text("\n".into()),
+            ToolCallEvent::ToolStart {
+                id: "tool_1".to_string(),
+                name: "get_weather".to_string(),
+            },
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({
+                    "location": "New York",
+                    "date": "tomorrow",
+                    "unit": "fahrenheit"
+                }),
+            },
+            ToolCallEvent::ToolEnd {
+                id: "tool_1".to_string(),
+            },
+            ToolCallEvent::Text("\n".into()),
+            ToolCallEvent::Text("\n".into()),
+            ToolCallEvent::Text("結".into()),
+            ToolCallEvent::Text("果".into()),
+            ToolCallEvent::Text("が".into()),
+            ToolCallEvent::Text("取".into()),
+            ToolCallEvent::Text("得".into()),
+            ToolCallEvent::Text("で".into()),
+            ToolCallEvent::Text("き".into()),
+            ToolCallEvent::Text("次".into()),
+            ToolCallEvent::Text("第".into()),
+            ToolCallEvent::Text("、".into()),
+            ToolCallEvent::Text("す".into()),
+            ToolCallEvent::Text("ぐ".into()),
+            ToolCallEvent::Text("に".into()),
+            ToolCallEvent::Text("お".into()),
+            ToolCallEvent::Text("知".into()),
+            ToolCallEvent::Text("ら".into()),
+            ToolCallEvent::Text("せ".into()),
+            ToolCallEvent::Text("し".into()),
+            ToolCallEvent::Text("ま".into()),
+            ToolCallEvent::Text("す".into()),
+            ToolCallEvent::Text("。".into()),
+        ];
+        let mut stream = stream_to_stream(input_stream)?;
+        let mut events = Vec::new();
+        while let Some(event) = stream.next().await {
+            events.push(event);
+        }
+        assert_eq!(events, expected_events);
+        Ok(())
+    }
+
+    /// メインのストリーム変換テスト
+    ///
+    /// このテストでは以下の点を確認します：
+    /// - テキストの3文字ずつの処理
+    /// - XMLタグの適切な解析
+    /// - パラメータの収集と出力
+    /// - 改行の適切な処理
+    #[tokio::test]
+    async fn test_stream_to_stream_2() -> Result<()> {
+        let input = r#"明日のニューヨークの天気ですね。承知いたしました。
+
+<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+  <unit>fahrenheit</unit>
+</get_weather>
+
+結果が取得でき次第、すぐにお知らせします。"#;
+        let input_stream = Box::pin(futures::stream::iter(
+            input
+                .chars()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .chunks(3)
+                .map(|chunk| chunk.join(""))
+                .collect::<Vec<_>>()
+                .into_iter(),
+        ));
+
+        let expected_events = vec![
+            // 一文字ずつ返す
+            ToolCallEvent::Text("明".into()),
+            ToolCallEvent::Text("日".into()),
+            ToolCallEvent::Text("の".into()),
+            ToolCallEvent::Text("ニ".into()),
+            ToolCallEvent::Text("ュ".into()),
             ToolCallEvent::Text("ー".into()),
             ToolCallEvent::Text("ヨ".into()),
             ToolCallEvent::Text("ー".into()),
@@ -866,4 +3011,1873 @@ Let me know if that looks correct."#;
         assert_eq!(events, expected_events);
         Ok(())
     }
+
+    #[test]
+    fn test_debug_state_in_parameter_tag() {
+        let mut parser = XmlStreamParser::new(Box::pin(futures::stream::empty()));
+        for c in "<get_weather><location>Tok".chars() {
+            parser.process_char(&c.to_string());
+        }
+
+        let dump = parser.debug_state();
+        assert!(dump.contains("state: InParameterTag"), "{}", dump);
+        assert!(
+            dump.contains(r#"current_tool: Some("get_weather")"#),
+            "{}",
+            dump
+        );
+        assert!(dump.contains(r#"current_id: Some("tool_1")"#), "{}", dump);
+        assert!(dump.contains(r#"param_value_buffer: "Tok""#), "{}", dump);
+        assert!(dump.contains("char_buffer_len: 0"), "{}", dump);
+    }
+
+    #[tokio::test]
+    async fn test_parameter_start_end_events() -> Result<()> {
+        let input = r#"<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+  <unit>fahrenheit</unit>
+</get_weather>"#;
+        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+
+        let param_event = |name: &str, start: bool| {
+            let (id, name) = ("tool_1".to_string(), name.to_string());
+            if start {
+                ToolCallEvent::ParameterStart { id, name }
+            } else {
+                ToolCallEvent::ParameterEnd { id, name }
+            }
+        };
+        let expected_events = vec![
+            ToolCallEvent::ToolStart {
+                id: "tool_1".to_string(),
+                name: "get_weather".to_string(),
+            },
+            param_event("location", true),
+            param_event("location", false),
+            param_event("date", true),
+            param_event("date", false),
+            param_event("unit", true),
+            param_event("unit", false),
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({
+                    "location": "New York",
+                    "date": "tomorrow",
+                    "unit": "fahrenheit"
+                }),
+            },
+            ToolCallEvent::ToolEnd {
+                id: "tool_1".to_string(),
+            },
+        ];
+        let mut stream = XmlStreamParser::new(input_stream).parameter_events(true);
+        let mut events = Vec::new();
+        while let Some(event) = stream.next().await {
+            events.push(event);
+        }
+        assert_eq!(events, expected_events);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parameter_arguments_preserve_source_order() -> Result<()> {
+        let input = r#"<get_weather>
+  <unit>fahrenheit</unit>
+  <location>New York</location>
+  <date>tomorrow</date>
+</get_weather>"#;
+        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+
+        let mut stream = stream_to_stream(input_stream)?;
+        let mut arguments = None;
+        while let Some(event) = stream.next().await {
+            if let ToolCallEvent::Parameter { arguments: a, .. } = event {
+                arguments = Some(a);
+            }
+        }
+        assert_eq!(
+            serde_json::to_string(&arguments.unwrap()).unwrap(),
+            r#"{"unit":"fahrenheit","location":"New York","date":"tomorrow"}"#
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parse_str_matches_stream() -> Result<()> {
+        let input = r#"天気を確認します。
+<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+</get_weather>
+完了しました。"#;
+        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+        let stream_events: Vec<_> = stream_to_stream(input_stream)?.collect().await;
+
+        assert_eq!(parse_str(input).collect::<Vec<_>>(), stream_events);
+        Ok(())
+    }
+
+    #[test]
+    fn test_comment_containing_close_delimiter() {
+        let tools = "<get_weather><location>Tokyo</location></get_weather>\n<get_weather><!-- x > y --><location>Osaka</location></get_weather>";
+        let input = "<get_weather><location>Tokyo</location></get_weather>\n<!-- a > b -->\n<get_weather><!-- x > y --><location>Osaka</location></get_weather>";
+
+        let events: Vec<_> = parse_str(input).collect();
+        let texts: String = events
+            .iter()
+            .filter_map(|event| match event {
+                ToolCallEvent::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts, "\n\n");
+        let tool_events: Vec<_> = events
+            .into_iter()
+            .filter(|event| !matches!(event, ToolCallEvent::Text(_)))
+            .collect();
+        let expected: Vec<_> = parse_str(tools)
+            .filter(|event| !matches!(event, ToolCallEvent::Text(_)))
+            .collect();
+        assert_eq!(tool_events, expected);
+    }
+
+    #[tokio::test]
+    async fn test_tag_names_split_across_chunks() -> Result<()> {
+        let input =
+            "天気: <get_weather><location>Tokyo</location><date>tomorrow</date></get_weather>";
+        let whole = Box::pin(futures::stream::iter([input.to_string()]));
+        let expected: Vec<_> = stream_to_stream(whole)?.collect().await;
+
+        // `<get_wea` / `ther>` や `<loca` / `tion>` のように、タグ名の途中を含むすべての位置で分割する
+        for (split, _) in input.char_indices().skip(1) {
+            let chunks = [input[..split].to_string(), input[split..].to_string()];
+            let input_stream = Box::pin(futures::stream::iter(chunks));
+            let events: Vec<_> = stream_to_stream(input_stream)?.collect().await;
+            assert_eq!(events, expected, "split at {split}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_only_opening_tool_tag() -> Result<()> {
+        // 開始タグだけで入力が終わった場合は、ToolStart のあとに何も発行せずに終了する
+        let input = "<get_weather>";
+        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+        let events: Vec<_> = stream_to_stream(input_stream)?.collect().await;
+        assert_eq!(
+            events,
+            vec![ToolCallEvent::ToolStart {
+                id: "tool_1".to_string(),
+                name: "get_weather".to_string()
+            }]
+        );
+        assert_eq!(parse_str(input).collect::<Vec<_>>(), events);
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_spans_slice_original_input() {
+        let input = "明日の天気を確認します。\n<get_weather><location>Tokyo</location></get_weather>\n結果をお知らせします。";
+
+        let events: Vec<_> = parse_str(input).text_spans().collect();
+        let texts: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                SpannedEvent::Text { start, end } => Some(&input[*start..*end]),
+                SpannedEvent::Event(_) => None,
+            })
+            .collect();
+        assert_eq!(
+            texts,
+            vec!["明日の天気を確認します。\n", "\n結果をお知らせします。"]
+        );
+        assert!(matches!(
+            events[1],
+            SpannedEvent::Event(ToolCallEvent::ToolStart { ref name, .. }) if name == "get_weather"
+        ));
+        assert_eq!(
+            events[2],
+            SpannedEvent::Event(ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({ "location": "Tokyo" }),
+            })
+        );
+        assert!(matches!(
+            events[3],
+            SpannedEvent::Event(ToolCallEvent::ToolEnd { .. })
+        ));
+        assert_eq!(events.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_collect_tool_calls_complete_and_truncated() -> Result<()> {
+        // 1つ目は完了、2つ目は <content> の途中で途切れている
+        let input = "<get_weather><location>Tokyo</location></get_weather>
+<write_to_file><path>src/main.rs</path><content>fn ma";
+        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+
+        let tool_calls = collect_tool_calls(stream_to_stream(input_stream)?).await;
+
+        assert_eq!(tool_calls.len(), 2);
+        assert!(tool_calls[0].complete);
+        assert_eq!(tool_calls[0].call.tool_name, "get_weather");
+        assert_eq!(tool_calls[0].call.parameters["location"], "Tokyo");
+        assert!(!tool_calls[1].complete);
+        assert_eq!(tool_calls[1].call.tool_name, "write_to_file");
+        Ok(())
+    }
+
+    /// 混在入力のスループットの簡易チェック
+    ///
+    /// 詳細な計測は `cargo bench --bench stream_to_stream` で行う。
+    /// ここでは1MBの入力がデバッグビルドでも十分に余裕のある時間内に処理できることのみ確認する。
+    #[tokio::test]
+    async fn test_mixed_input_throughput_sanity() -> Result<()> {
+        let block = r#"明日のニューヨークの天気を確認します。
+
+<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+</get_weather>
+
+処理が完了しました。
+"#;
+        let input = block.repeat(1024 * 1024 / block.len() + 1);
+
+        // 1文字ごとの処理が再帰や先頭からの削除になっていると、大きなチャンクでスタックを使い果たすか二乗の時間がかかる
+        for chunk_size in [256, 64 * 1024, input.len()] {
+            let chunks: Vec<String> = input
+                .chars()
+                .collect::<Vec<_>>()
+                .chunks(chunk_size)
+                .map(|chunk| chunk.iter().collect())
+                .collect();
+
+            let started = std::time::Instant::now();
+            let tool_calls =
+                collect_tool_calls(stream_to_stream(Box::pin(futures::stream::iter(chunks)))?)
+                    .await;
+            let elapsed = started.elapsed();
+
+            assert_eq!(tool_calls.len(), input.matches("</get_weather>").count());
+            assert!(
+                elapsed < std::time::Duration::from_secs(30),
+                "1MBの処理に{:?}かかりました（チャンクの大きさ: {}）",
+                elapsed,
+                chunk_size
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_id_start() {
+        let input = "<get_weather><location>Tokyo</location></get_weather><get_time></get_time>";
+        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+
+        let ids: Vec<_> = XmlStreamParser::with_id_start(input_stream, 41)
+            .filter_map(|event| match event {
+                ToolCallEvent::ToolStart { id, .. } => Some(id),
+                _ => None,
+            })
+            .collect()
+            .await;
+        assert_eq!(ids, vec!["tool_42".to_string(), "tool_43".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_name_prefixed_ids() {
+        let input = "<get_weather><location>Tokyo</location></get_weather><get_time></get_time>";
+        let input_stream = Box::pin(futures::stream::iter(vec![input.to_string()]));
+
+        let events: Vec<_> = XmlStreamParser::new(input_stream)
+            .id_scheme(IdScheme::NamePrefixed)
+            .collect()
+            .await;
+        assert_eq!(
+            events,
+            vec![
+                ToolCallEvent::ToolStart {
+                    id: "get_weather_1".to_string(),
+                    name: "get_weather".to_string(),
+                },
+                ToolCallEvent::Parameter {
+                    id: "get_weather_1".to_string(),
+                    arguments: serde_json::json!({ "location": "Tokyo" }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "get_weather_1".to_string(),
+                },
+                ToolCallEvent::ToolStart {
+                    id: "get_time_2".to_string(),
+                    name: "get_time".to_string(),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "get_time_2".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parameters_named_like_xml_reserved_words() {
+        let events: Vec<_> =
+            parse_str("<tool><xmlns>v</xmlns><xml:lang>ja</xml:lang></tool>").collect();
+        assert_eq!(
+            events[1],
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({ "xmlns": "v", "xml:lang": "ja" }),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunks_split_at_tag_open() -> Result<()> {
+        let input = "天気<get_weather><location>Tokyo</location></get_weather>";
+        let chunks = [
+            "天気<",
+            "get_weather",
+            ">",
+            "<",
+            "location>Tokyo<",
+            "/",
+            "location",
+            "><",
+            "/",
+            "get_weather",
+            ">",
+        ];
+        let input_stream = Box::pin(futures::stream::iter(chunks.map(String::from)));
+        let stream_events: Vec<_> = stream_to_stream(input_stream)?.collect().await;
+
+        assert_eq!(stream_events, parse_str(input).collect::<Vec<_>>());
+        assert!(matches!(
+            stream_events.last(),
+            Some(ToolCallEvent::ToolEnd { id }) if id == "tool_1"
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_empty_tag_names_are_not_tools() -> Result<()> {
+        let input_stream = Box::pin(futures::stream::iter(
+            ["a <", ">", " b <", "/", "> c"].map(String::from),
+        ));
+        let text: String = stream_to_stream(input_stream)?
+            .map(|event| match event {
+                ToolCallEvent::Text(text) => text,
+                other => panic!("unexpected event: {:?}", other),
+            })
+            .collect()
+            .await;
+        assert_eq!(text, "a <> b </> c");
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_text_param() {
+        let core = XmlParserCore::new().single_text_param(Some("query"));
+        let events: Vec<_> = StrEvents::new("<search>rust async</search>", core).collect();
+        assert_eq!(
+            events[1],
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({ "query": "rust async" }),
+            }
+        );
+
+        // 子要素を持つツールでは、直下のテキストは従来どおり無視する
+        let core = XmlParserCore::new().single_text_param(Some("query"));
+        let events: Vec<_> =
+            StrEvents::new("<search>ignored<limit>5</limit></search>", core).collect();
+        assert_eq!(
+            events[1],
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({ "limit": "5" }),
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttle_text_coalesces_text() {
+        // 10ms ごとに1文字ずつ届くテキストの後にツール呼び出しが続く
+        let input =
+            "こんにちは、天気を調べます。<get_weather><location>Tokyo</location></get_weather>";
+        let chars: Vec<String> = input.chars().map(|c| c.to_string()).collect();
+        let input_stream = Box::pin(futures::stream::iter(chars).then(|c| async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            c
+        }));
+        let events = XmlStreamParser::new(input_stream);
+
+        let throttled: Vec<_> = throttle_text(events, Duration::from_millis(50))
+            .collect()
+            .await;
+
+        let texts: Vec<_> = throttled
+            .iter()
+            .filter_map(|event| match event {
+                ToolCallEvent::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert!(texts.len() < 14, "{:?}", texts);
+        assert!(texts.iter().any(|text| text.chars().count() > 1));
+        assert_eq!(texts.concat(), "こんにちは、天気を調べます。");
+        assert!(matches!(
+            throttled[texts.len()],
+            ToolCallEvent::ToolStart { .. }
+        ));
+        assert!(matches!(
+            throttled.last(),
+            Some(ToolCallEvent::ToolEnd { .. })
+        ));
+    }
+
+    #[test]
+    fn test_event_ranges_are_contiguous_and_cover_input() {
+        let input = "天気<get_weather>\n<location>Tokyo</location>\n</get_weather>!";
+        let events: Vec<_> = parse_str(input).with_ranges().collect();
+
+        let mut position = 0;
+        for (_, range) in &events {
+            assert_eq!(range.start, position);
+            position = range.end;
+        }
+        assert_eq!(position, input.len());
+
+        let ranges: Vec<_> = events
+            .iter()
+            .map(|(_, range)| &input[range.clone()])
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                "天",
+                "気",
+                "<get_weather>",
+                "\n<location>Tokyo</location>\n</get_weather>",
+                "",
+                "!"
+            ]
+        );
+        assert!(matches!(events[4].0, ToolCallEvent::ToolEnd { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_chain_inputs_assembles_tool_split_across_streams() -> Result<()> {
+        let reasoning: BoxStream<'static, String> = Box::pin(futures::stream::iter(
+            ["<get_weather>", "<location>New ", "Yo"].map(String::from),
+        ));
+        let content: BoxStream<'static, String> = Box::pin(futures::stream::iter(
+            ["rk</location>", "</get_weather>"].map(String::from),
+        ));
+
+        let events = stream_to_stream(chain_inputs(vec![reasoning, content]))?;
+        let tool_calls = collect_tool_calls(events).await;
+
+        assert_eq!(tool_calls.len(), 1);
+        assert!(tool_calls[0].complete);
+        assert_eq!(tool_calls[0].call.tool_name, "get_weather");
+        assert_eq!(tool_calls[0].call.parameters["location"], "New York");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_strip_boundaries() -> Result<()> {
+        let input =
+            "前置き\r\n--frame\r\n<write_to_file>\n<path>a.txt</path>\n<content>1行目\n2行\n--fr";
+        let rest = "ame\n目</content>\n</write_to_file>\n--frame--\n";
+        let input_stream = Box::pin(futures::stream::iter([input, rest].map(String::from)));
+
+        let stripped: String = strip_boundaries(input_stream, "frame").collect().await;
+        assert_eq!(
+            stripped,
+            "前置き<write_to_file>\n<path>a.txt</path>\n<content>1行目\n2行目</content>\n</write_to_file>"
+        );
+
+        // 区切り行で分割されたツール呼び出しも1つにつながる
+        let chunks = [
+            "<get_weather><location>To",
+            "\n--frame\n",
+            "kyo</location>\n--fra",
+            "x</get_weather>",
+        ];
+        let input_stream = Box::pin(futures::stream::iter(chunks.map(String::from)));
+        let events = stream_to_stream(strip_boundaries(input_stream, "frame"))?;
+        let tool_calls = collect_tool_calls(events).await;
+        assert_eq!(tool_calls.len(), 1);
+        assert!(tool_calls[0].complete);
+        assert_eq!(tool_calls[0].call.parameters["location"], "Tokyo");
+        Ok(())
+    }
+
+    #[test]
+    fn test_unclosed_param_does_not_swallow_following_text() {
+        let input = "<get_weather><location>Tokyo</loc></get_weather>後";
+        assert_eq!(
+            parse_str(input).collect::<Vec<_>>(),
+            vec![
+                ToolCallEvent::ToolStart {
+                    id: "tool_1".to_string(),
+                    name: "get_weather".to_string(),
+                },
+                ToolCallEvent::Error(
+                    "mismatched end tag: expected </location>, found </loc>".to_string()
+                ),
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string(),
+                },
+                ToolCallEvent::Text("後".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_content_keeps_foreign_close_tags_verbatim() {
+        let input = r#"<write_to_file>
+<path>src/App.jsx</path>
+<content>
+const re = /<\/span>/;
+return <div><span>{re.source}</span></div>;
+</content>
+</write_to_file>"#;
+        let events: Vec<_> = parse_str(input).collect();
+        assert_eq!(
+            events[1],
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({
+                    "path": "src/App.jsx",
+                    "content": "const re = /<\\/span>/;\nreturn <div><span>{re.source}</span></div>;",
+                }),
+            }
+        );
+        assert_eq!(
+            events[2],
+            ToolCallEvent::ToolEnd {
+                id: "tool_1".to_string()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_single_line_input_matches_multiline() {
+        let input = r#"明日のニューヨークの天気を確認します。
+
+<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+  <unit>fahrenheit</unit>
+</get_weather>
+
+天気予報を取得しました。次に、ファイルに書き込みます。
+
+<write_to_file>
+<path>weather_report.txt</path>
+<content>
+明日のニューヨークの天気予報：晴れ時々曇り
+</content>
+</write_to_file>
+
+処理が完了しました。"#;
+        let single_line = input.replace('\n', " ");
+        assert!(!single_line.contains('\n'));
+
+        let multiline_calls = collect_tool_calls(parse_str_stream(input)).await;
+        let single_line_calls = collect_tool_calls(parse_str_stream(&single_line)).await;
+
+        assert_eq!(single_line_calls.len(), 2);
+        assert_eq!(single_line_calls, multiline_calls);
+    }
+
+    /// 文字列全体を1文字ずつのストリームとしてパースする
+    fn parse_str_stream(input: &str) -> XmlStreamParser {
+        let chars: Vec<String> = input.chars().map(|c| c.to_string()).collect();
+        XmlStreamParser::new(Box::pin(futures::stream::iter(chars)))
+    }
+
+    #[test]
+    fn test_nested_params() {
+        let core = XmlParserCore::new().nested_params(true);
+        let input = "<a><b><c>v</c><d>w</d></b><e>x</e></a>";
+        let events: Vec<_> = StrEvents::new(input, core).collect();
+        assert_eq!(
+            events,
+            vec![
+                ToolCallEvent::ToolStart {
+                    id: "tool_1".to_string(),
+                    name: "a".to_string()
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({ "b": { "c": "v", "d": "w" }, "e": "x" }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_whitespace_mode_collapse() {
+        let core = XmlParserCore::new().whitespace_mode(WhitespaceMode::Collapse);
+        let input = "<search><query>  hello    world\n  rust\t async  </query></search>";
+        let events: Vec<_> = StrEvents::new(input, core).collect();
+        assert_eq!(
+            events[1],
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({ "query": "hello world rust async" }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_whitespace_mode_preserve_keeps_whitespace_only_value() {
+        let core = XmlParserCore::new().whitespace_mode(WhitespaceMode::Preserve);
+        let input = "<set_indent><indent>    </indent><name> a </name></set_indent>";
+        let events: Vec<_> = StrEvents::new(input, core).collect();
+        assert_eq!(
+            events[1],
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({ "indent": "    ", "name": " a " }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_strip_content_fence() {
+        let input = "<write_to_file><content>```python\nprint(1)\n```</content></write_to_file>";
+        let events: Vec<_> =
+            StrEvents::new(input, XmlParserCore::new().strip_content_fence(true)).collect();
+        assert_eq!(
+            events[1],
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({ "content": "print(1)" }),
+            }
+        );
+        let events: Vec<_> = StrEvents::new(input, XmlParserCore::new()).collect();
+        assert_eq!(
+            events[1],
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({ "content": "```python\nprint(1)\n```" }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_hyphen_and_dot_names() {
+        let input = "<web-search><search-query>rust xml</search-query><max.results>5</max.results></web-search><fs.read><file.path>a.txt</file.path></fs.read>";
+        let events: Vec<_> = parse_str(input).collect();
+        assert_eq!(
+            events,
+            vec![
+                ToolCallEvent::ToolStart {
+                    id: "tool_1".to_string(),
+                    name: "web-search".to_string()
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({ "search-query": "rust xml", "max.results": "5" }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string()
+                },
+                ToolCallEvent::ToolStart {
+                    id: "tool_2".to_string(),
+                    name: "fs.read".to_string()
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_2".to_string(),
+                    arguments: serde_json::json!({ "file.path": "a.txt" }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "tool_2".to_string()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_text() {
+        let chunks = [
+            "明日の天気を",
+            "確認します。\n<get_weather><location>Tokyo</location></get_weather>\n",
+            "完了しました。",
+        ];
+        let input_stream = Box::pin(futures::stream::iter(chunks.map(str::to_string)));
+        let events: Vec<_> = XmlStreamParser::new(input_stream)
+            .coalesce_text(true)
+            .summary_event(true)
+            .collect()
+            .await;
+        assert_eq!(
+            events,
+            vec![
+                ToolCallEvent::Text("明日の天気を".to_string()),
+                ToolCallEvent::Text("確認します。\n".to_string()),
+                ToolCallEvent::ToolStart {
+                    id: "tool_1".to_string(),
+                    name: "get_weather".to_string()
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({ "location": "Tokyo" }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string()
+                },
+                // タグのあとのテキストは1文字ずつ処理する
+                ToolCallEvent::Text("\n".to_string()),
+                ToolCallEvent::Text("完了しました。".to_string()),
+                ToolCallEvent::StreamSummary {
+                    tools: 1,
+                    params: 1,
+                    text_chars: 21,
+                },
+            ]
+        );
+
+        // 同期のイテレーターでは、次のタグの開始までのテキストをまとめる
+        let input = chunks.concat();
+        let texts: Vec<_> = StrEvents::new(&input, XmlParserCore::new().coalesce_text(true))
+            .filter_map(|event| match event {
+                ToolCallEvent::Text(text) => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            texts,
+            vec!["明日の天気を確認します。\n", "\n完了しました。"]
+        );
+    }
+
+    #[test]
+    fn test_keyed_list() {
+        let core = XmlParserCore::new().keyed_list("header", "key");
+        let input = r#"<http_request><url>https://example.com</url><headers><header key="A">1</header><header key='B'>2</header></headers></http_request>"#;
+        let events: Vec<_> = StrEvents::new(input, core).collect();
+        assert_eq!(
+            events[1],
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({
+                    "url": "https://example.com",
+                    "headers": { "A": "1", "B": "2" }
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_keyed_list_with_inner_tag_policy() {
+        let input = r#"<http_request><headers><header key="A">1</header></headers><body>本文<b>強調</b></body></http_request>"#;
+        let arguments = |core| {
+            StrEvents::new(input, core)
+                .find_map(|event| match event {
+                    ToolCallEvent::Parameter { arguments, .. } => Some(arguments),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        let expected = serde_json::json!({
+            "headers": { "A": "1" },
+            "body": { "#text": "本文", "b": "強調" }
+        });
+
+        // 設定の順序によらず、keyed_list による入れ子の解析は無効にならない
+        for policy in [InnerTagPolicy::Literal, InnerTagPolicy::Parse] {
+            let core = XmlParserCore::new()
+                .keyed_list("header", "key")
+                .inner_tag_policy(policy);
+            assert_eq!(arguments(core), expected);
+            let core = XmlParserCore::new()
+                .inner_tag_policy(policy)
+                .keyed_list("header", "key");
+            assert_eq!(arguments(core), expected);
+        }
+    }
+
+    #[test]
+    fn test_nested_params_mismatched_deep_close_tag() {
+        let core = XmlParserCore::new().nested_params(true);
+        let input = "<a><b><c>v</b></c></a>";
+        let events: Vec<_> = StrEvents::new(input, core).collect();
+        assert_eq!(
+            events,
+            vec![
+                ToolCallEvent::ToolStart {
+                    id: "tool_1".to_string(),
+                    name: "a".to_string()
+                },
+                ToolCallEvent::Error("mismatched end tag: expected </c>, found </b>".to_string()),
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tool_and_parameter_with_same_name() {
+        let events: Vec<_> =
+            parse_str("<param><param>v</param><other>x</other></param>after").collect();
+        assert_eq!(
+            events,
+            vec![
+                ToolCallEvent::ToolStart {
+                    id: "tool_1".to_string(),
+                    name: "param".to_string()
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({ "param": "v", "other": "x" }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string()
+                },
+                ToolCallEvent::Text("a".to_string()),
+                ToolCallEvent::Text("f".to_string()),
+                ToolCallEvent::Text("t".to_string()),
+                ToolCallEvent::Text("e".to_string()),
+                ToolCallEvent::Text("r".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_custom_delimiters() {
+        let core = XmlParserCore::new().delimiters(Delimiters {
+            open: '«',
+            close: '»',
+            close_prefix: '/',
+        });
+        let input = "a < b «get_weather»«location»<Tokyo>«/location»«/get_weather»";
+        let events: Vec<_> = StrEvents::new(input, core).collect();
+        assert_eq!(
+            events[6..],
+            [
+                ToolCallEvent::ToolStart {
+                    id: "tool_1".to_string(),
+                    name: "get_weather".to_string()
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({ "location": "<Tokyo>" }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string()
+                },
+            ]
+        );
+        let text: String = events[..6]
+            .iter()
+            .map(|event| match event {
+                ToolCallEvent::Text(text) => text.as_str(),
+                other => panic!("unexpected event: {:?}", other),
+            })
+            .collect();
+        assert_eq!(text, "a < b ");
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_snapshot_mid_parameter() {
+        let input =
+            "天気<get_weather><location>New York</location><date>tomorrow</date></get_weather>";
+        let (first, rest) = input.split_at(input.find("York").unwrap());
+
+        let mut parser =
+            XmlStreamParser::new(Box::pin(futures::stream::iter(vec![first.to_string()])));
+        let mut events = Vec::new();
+        while let Some(event) = parser.next().await {
+            events.push(event);
+        }
+        assert!(parser.debug_state().contains("state: InParameterTag"));
+
+        let json = serde_json::to_string(&parser.snapshot()).unwrap();
+        let snapshot: ParserSnapshot = serde_json::from_str(&json).unwrap();
+        let resumed = XmlStreamParser::from_snapshot(
+            snapshot,
+            Box::pin(futures::stream::iter(vec![rest.to_string()])),
+        );
+        events.extend(resumed.collect::<Vec<_>>().await);
+
+        assert_eq!(events, parse_str(input).collect::<Vec<_>>());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_debounce_params_emits_tentative_then_final() {
+        let chunks = [
+            (0, "<write_to_file><path>a.txt</path><content>Hel"),
+            (500, "lo</con"),
+            (500, "tent></write_to_file>"),
+        ];
+        let input_stream = Box::pin(futures::stream::iter(chunks).then(
+            |(delay, chunk)| async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                chunk.to_string()
+            },
+        ));
+
+        let events: Vec<_> = XmlStreamParser::new(input_stream)
+            .debounce_params(Duration::from_millis(100))
+            .collect()
+            .await;
+
+        assert_eq!(
+            events[1..],
+            [
+                ToolCallEvent::TentativeParameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({ "path": "a.txt", "content": "Hel" }),
+                },
+                ToolCallEvent::TentativeParameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({ "path": "a.txt", "content": "Hello" }),
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({ "path": "a.txt", "content": "Hello" }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_entities_in_values_match_eager_parser() {
+        let input = "<call_api><body>{&quot;key&quot;: &apos;a &amp; b&apos;, &quot;lt&quot;: &quot;&lt;&#x3E;&#12354;&quot;} &unknown;</body></call_api>";
+        let events: Vec<_> = parse_str(input).collect();
+        let expected = "{\"key\": 'a & b', \"lt\": \"<>あ\"} &unknown;";
+        assert_eq!(
+            events[1],
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({ "body": expected }),
+            }
+        );
+
+        let input = input.replace(" &unknown;", "");
+        let tool_call = crate::parse_tool_call(&input).unwrap();
+        assert_eq!(
+            tool_call.parameters["body"],
+            expected.trim_end_matches(" &unknown;")
+        );
+    }
+
+    #[test]
+    fn test_spill_params_to_temp_file() {
+        let dir = std::env::temp_dir();
+        let content = "fn main() {}\n".repeat(1000);
+        let input = format!(
+            "<write_to_file><path>main.rs</path><content>{}</content></write_to_file>",
+            content
+        );
+        let core = XmlParserCore::new().spill_params(1024, &dir);
+        let events: Vec<_> = StrEvents::new(&input, core).collect();
+
+        let ToolCallEvent::Parameter { arguments, .. } = &events[1] else {
+            panic!("unexpected event: {:?}", events[1]);
+        };
+        let path: ParamValue = serde_json::from_value(arguments["path"].clone()).unwrap();
+        assert_eq!(path, ParamValue::Inline("main.rs".to_string()));
+
+        let spilled: ParamValue = serde_json::from_value(arguments["content"].clone()).unwrap();
+        let ParamValue::Spilled(spilled_path) = spilled else {
+            panic!("content was not spilled: {:?}", spilled);
+        };
+        assert!(spilled_path.starts_with(&dir));
+        // インラインの値と同様に前後の空白はトリムされる
+        assert_eq!(
+            std::fs::read_to_string(&spilled_path).unwrap(),
+            content.trim()
+        );
+        std::fs::remove_file(spilled_path).unwrap();
+    }
+
+    #[test]
+    fn test_spilled_value_matches_inline_value() {
+        let content = format!(
+            "\n  {}  \n",
+            "if a &lt; b &amp;&amp; c &gt; d {}\n".repeat(200)
+        );
+        let input = format!(
+            "<write_to_file><content>{}</content></write_to_file>",
+            content
+        );
+        let value = |whitespace| {
+            let inline = XmlParserCore::new().whitespace_mode(whitespace);
+            let inline = StrEvents::new(&input, inline)
+                .find_map(|event| match event {
+                    ToolCallEvent::Parameter { arguments, .. } => {
+                        Some(arguments["content"].clone())
+                    }
+                    _ => None,
+                })
+                .unwrap();
+            let spilled = XmlParserCore::new()
+                .whitespace_mode(whitespace)
+                .spill_params(100, std::env::temp_dir());
+            let spilled = StrEvents::new(&input, spilled)
+                .find_map(|event| match event {
+                    ToolCallEvent::Parameter { arguments, .. } => {
+                        serde_json::from_value::<ParamValue>(arguments["content"].clone()).ok()
+                    }
+                    _ => None,
+                })
+                .unwrap();
+            let ParamValue::Spilled(path) = spilled else {
+                panic!("content was not spilled: {:?}", spilled);
+            };
+            let written = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            (inline, written)
+        };
+
+        // 大きさによらず、同じパラメータは同じ値になる
+        for whitespace in [
+            WhitespaceMode::Trim,
+            WhitespaceMode::Collapse,
+            WhitespaceMode::Preserve,
+        ] {
+            let (inline, written) = value(whitespace);
+            assert_eq!(inline, serde_json::Value::String(written.clone()));
+            assert!(written.contains("if a < b && c > d {}"));
+        }
+    }
+
+    #[test]
+    fn test_spill_files_are_unique_per_parser() {
+        let dir = std::env::temp_dir();
+        let spill = |value: &str| {
+            let input = format!(
+                "<write_to_file><content>{}</content></write_to_file>",
+                value
+            );
+            let core = XmlParserCore::new().spill_params(16, &dir);
+            StrEvents::new(&input, core)
+                .find_map(|event| match event {
+                    ToolCallEvent::Parameter { arguments, .. } => {
+                        serde_json::from_value::<ParamValue>(arguments["content"].clone()).ok()
+                    }
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        // 同じIDのツールを処理する別々のパーサーでも、ファイルを共有しない
+        let (ParamValue::Spilled(a), ParamValue::Spilled(b)) =
+            (spill(&"A".repeat(40)), spill(&"B".repeat(40)))
+        else {
+            panic!("values were not spilled");
+        };
+        assert_ne!(a, b);
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "A".repeat(40));
+        assert_eq!(std::fs::read_to_string(&b).unwrap(), "B".repeat(40));
+        std::fs::remove_file(a).unwrap();
+        std::fs::remove_file(b).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_on_content_stream_writes_to_file() {
+        let path = std::env::temp_dir().join(format!(
+            "tool_use_with_xml_content_stream_{}.txt",
+            std::process::id()
+        ));
+        let file = tokio::fs::File::create(&path).await.unwrap();
+        let chunks = [
+            "保存します。<write_to_file><path>a.txt</path><content>Hello",
+            ", world!\n2行目 <b>太字</b>\n</con",
+            "tent></write_to_file>完了",
+        ];
+        let input_stream = Box::pin(futures::stream::iter(chunks.map(str::to_string)));
+
+        let events: Vec<_> = XmlStreamParser::new(input_stream)
+            .on_content_stream("write_to_file", "content", file)
+            .collect()
+            .await;
+
+        let tool_events: Vec<_> = events
+            .into_iter()
+            .filter(|event| !matches!(event, ToolCallEvent::Text(_)))
+            .collect();
+        assert_eq!(
+            tool_events,
+            vec![
+                ToolCallEvent::ToolStart {
+                    id: "tool_1".to_string(),
+                    name: "write_to_file".to_string()
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({ "path": "a.txt" }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string()
+                },
+            ]
+        );
+        assert_eq!(
+            tokio::fs::read_to_string(&path).await.unwrap(),
+            "Hello, world!\n2行目 <b>太字</b>\n"
+        );
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_leftover_after_cut_off_input() {
+        let input_stream = Box::pin(futures::stream::iter(vec![
+            "確認します。<get_weather><location>Tokyo</location><un".to_string(),
+        ]));
+        let mut parser = XmlStreamParser::new(input_stream);
+        while parser.next().await.is_some() {}
+        assert_eq!(parser.leftover(), "<un");
+
+        let input_stream = Box::pin(futures::stream::iter(vec![
+            "<get_weather><location>Tok".to_string(),
+        ]));
+        let mut parser = XmlStreamParser::new(input_stream);
+        while parser.next().await.is_some() {}
+        assert_eq!(parser.leftover(), "Tok");
+    }
+
+    #[test]
+    fn test_max_tag_name_len() {
+        let mut core = XmlParserCore::new().max_tag_name_len(256);
+        let mut text = String::new();
+        let input = format!("前置き<{}", "a".repeat(1024 * 1024));
+        for c in input.chars() {
+            if let Some(ToolCallEvent::Text(t)) = core.process_char(c.encode_utf8(&mut [0; 4])) {
+                text.push_str(&t);
+            }
+            assert!(core.tag_buffer.len() <= 256);
+        }
+        // 上限を超えた分はテキストとして発行され、バッファには残らない
+        assert_eq!(text.len() + core.leftover().len(), input.len());
+        assert!(core.leftover().len() <= 256 + 1);
+
+        // 打ち切ったあとも、後続のツール呼び出しは解析できる
+        let events: Vec<_> = StrEvents::new(
+            &format!(
+                "<{}\n<get_weather><location>Tokyo</location></get_weather>",
+                "x".repeat(300)
+            ),
+            XmlParserCore::new().max_tag_name_len(256),
+        )
+        .filter(|event| !matches!(event, ToolCallEvent::Text(_)))
+        .collect();
+        assert_eq!(
+            events,
+            vec![
+                ToolCallEvent::ToolStart {
+                    id: "tool_1".to_string(),
+                    name: "get_weather".to_string()
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({ "location": "Tokyo" }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_split_text_and_tools() {
+        let chunks = [
+            "天気を確認してから保存します。\n<get_weather><loca",
+            "tion>Tokyo</location></get_weather>\n保存します。\n<write_to_file>",
+            "<path>a.txt</path><content>晴れ</content></write_to_file>\n完了しました。",
+        ];
+        let input_stream = Box::pin(futures::stream::iter(chunks.map(str::to_string)));
+        let SplitStreams {
+            text_stream,
+            tool_stream,
+        } = split_text_and_tools(input_stream);
+
+        // ツール呼び出しのストリームを先に最後まで読んでも、テキストは失われない
+        let tool_calls: Vec<_> = tool_stream.collect().await;
+        let text: String = text_stream.collect().await;
+
+        assert_eq!(
+            tool_calls,
+            vec![
+                ToolCall {
+                    tool_name: "get_weather".to_string(),
+                    parameters: HashMap::from([("location".to_string(), "Tokyo".to_string())]),
+                    attributes: HashMap::new(),
+                    spans: HashMap::new(),
+                },
+                ToolCall {
+                    tool_name: "write_to_file".to_string(),
+                    parameters: HashMap::from([
+                        ("path".to_string(), "a.txt".to_string()),
+                        ("content".to_string(), "晴れ".to_string()),
+                    ]),
+                    attributes: HashMap::new(),
+                    spans: HashMap::new(),
+                },
+            ]
+        );
+        assert_eq!(
+            text,
+            "天気を確認してから保存します。\n\n保存します。\n\n完了しました。"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_parser_trait_object() {
+        let parser: Box<dyn ToolCallParser> = Box::new(XmlParserCore::new().id_start(10));
+        let input_stream = Box::pin(futures::stream::iter(vec![
+            "確認します。<get_weather><location>Tokyo</location></get_weather>".to_string(),
+        ]));
+        let events: Vec<_> = parser
+            .parse_stream(input_stream)
+            .await
+            .filter(|event| !matches!(event, ToolCallEvent::Text(_)))
+            .collect()
+            .await;
+        assert_eq!(
+            events,
+            vec![
+                ToolCallEvent::ToolStart {
+                    id: "tool_11".to_string(),
+                    name: "get_weather".to_string()
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_11".to_string(),
+                    arguments: serde_json::json!({ "location": "Tokyo" }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "tool_11".to_string()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_coalesce_param_deltas() {
+        let (tx, rx) = mpsc::channel::<String>(8);
+        let input_stream = Box::pin(futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|chunk| (chunk, rx))
+        }));
+        let events = XmlStreamParser::new(input_stream)
+            .parameter_events(true)
+            .parameter_deltas(true)
+            .debounce_params(Duration::from_millis(50));
+        let collected = tokio::spawn(coalesce_param_deltas(events).collect::<Vec<_>>());
+
+        for chunk in [
+            "<write_to_file><path>a.txt</path><content>1行目",
+            "\n2行目 &am",
+            "p; 3行目</content></write_to_file>",
+        ] {
+            tx.send(chunk.to_string()).await.unwrap();
+            // 入力が途絶えて暫定値が発行されるまで待つ
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        drop(tx);
+
+        // 値はパラメータごとに1つの Parameter イベントにまとまり、ツール全体の Parameter は発行されない
+        assert_eq!(
+            collected.await.unwrap(),
+            vec![
+                ToolCallEvent::ToolStart {
+                    id: "tool_1".to_string(),
+                    name: "write_to_file".to_string()
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({ "path": "a.txt" }),
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({ "content": "1行目\n2行目 & 3行目" }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parameter_deltas_concatenate_to_value() {
+        let chunks = ["<t><v>  a &am", "p; b  ", " c  </v><w>x</w></t>"];
+        let input_stream = Box::pin(futures::stream::iter(chunks.map(String::from)));
+        let events: Vec<_> = XmlStreamParser::new(input_stream)
+            .parameter_events(true)
+            .parameter_deltas(true)
+            .collect()
+            .await;
+
+        // 途中で切れた実体参照、末尾の空白、終了タグの一部かもしれない末尾は、確定するまで差分に含めない
+        let deltas: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                ToolCallEvent::ParameterDelta { name, delta, .. } if name == "v" => {
+                    Some(delta.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(deltas, ["a", " &", " b   c"]);
+
+        let Some(ToolCallEvent::Parameter { arguments, .. }) = events
+            .iter()
+            .find(|event| matches!(event, ToolCallEvent::Parameter { .. }))
+        else {
+            panic!("no Parameter event: {events:?}");
+        };
+        assert_eq!(arguments["v"], deltas.concat());
+
+        // 差分はパラメータの開始と終了の間に発行する
+        let position = |target: &ToolCallEvent| events.iter().position(|event| event == target);
+        let end = position(&ToolCallEvent::ParameterEnd {
+            id: "tool_1".to_string(),
+            name: "w".to_string(),
+        });
+        let delta = position(&ToolCallEvent::ParameterDelta {
+            id: "tool_1".to_string(),
+            name: "w".to_string(),
+            delta: "x".to_string(),
+        });
+        assert!(delta.is_some() && delta < end, "{events:?}");
+    }
+
+    #[test]
+    fn test_parse_chunks_matches_stream_to_stream() {
+        let input = "明日のニューヨークの天気ですね。\n\n<get_weather>\n  <location>New York</location>\n  <date>tomorrow</date>\n</get_weather>\n\n結果をお知らせします。";
+        let chars: Vec<String> = input.chars().map(|c| c.to_string()).collect();
+        let chunks: Vec<String> = chars.chunks(3).map(|chunk| chunk.join("")).collect();
+        let chunk_refs: Vec<&str> = chunks.iter().map(String::as_str).collect();
+
+        let events = parse_chunks(&chunk_refs);
+
+        // 非同期ランタイムを使わずに、3文字ずつのチャンクを流した場合と同じイベントになる
+        let input_stream = Box::pin(futures::stream::iter(chunks.clone()));
+        let streamed: Vec<_> =
+            futures::executor::block_on(XmlStreamParser::new(input_stream).collect());
+        assert_eq!(events, streamed);
+        assert_eq!(
+            events[18],
+            ToolCallEvent::ToolStart {
+                id: "tool_1".to_string(),
+                name: "get_weather".to_string()
+            }
+        );
+        assert_eq!(
+            events[19],
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({ "location": "New York", "date": "tomorrow" }),
+            }
+        );
+        assert_eq!(
+            events[20],
+            ToolCallEvent::ToolEnd {
+                id: "tool_1".to_string()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_with_context() {
+        let input_stream = Box::pin(futures::stream::iter(vec![
+            "確認します。<get_weather><location>Tokyo</location></get_weather>".to_string(),
+        ]));
+        let events: Vec<_> = XmlStreamParser::new(input_stream)
+            .with_context("req-42")
+            .filter(|event| !matches!(event.event, ToolCallEvent::Text(_)))
+            .collect()
+            .await;
+        let context: Arc<str> = Arc::from("req-42");
+        assert_eq!(
+            events,
+            vec![
+                ContextEvent {
+                    context: context.clone(),
+                    event: ToolCallEvent::ToolStart {
+                        id: "tool_1".to_string(),
+                        name: "get_weather".to_string()
+                    },
+                },
+                ContextEvent {
+                    context: context.clone(),
+                    event: ToolCallEvent::Parameter {
+                        id: "tool_1".to_string(),
+                        arguments: serde_json::json!({ "location": "Tokyo" }),
+                    },
+                },
+                ContextEvent {
+                    context,
+                    event: ToolCallEvent::ToolEnd {
+                        id: "tool_1".to_string()
+                    },
+                },
+            ]
+        );
+        // すべてのイベントが同じコンテキストを共有する
+        assert!(Arc::ptr_eq(&events[0].context, &events[2].context));
+    }
+
+    #[tokio::test]
+    async fn test_dedup_tools() {
+        let input = r#"<get_weather><location>Tokyo</location></get_weather>
+<get_weather><location>Tokyo</location></get_weather>
+<get_weather><location>Osaka</location></get_weather>
+<get_weather><location>Tokyo</location></get_weather>"#;
+        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+        let tool_calls: Vec<_> = dedup_tools(stream_by_tool(XmlStreamParser::new(input_stream)))
+            .collect()
+            .await;
+
+        let locations: Vec<_> = tool_calls
+            .iter()
+            .map(|tool_call| tool_call.parameters["location"].as_str())
+            .collect();
+        assert_eq!(locations, vec!["Tokyo", "Osaka", "Tokyo"]);
+    }
+
+    #[tokio::test]
+    async fn test_collect_bounded() {
+        let events: Vec<_> = (0..5).map(|i| ToolCallEvent::Text(i.to_string())).collect();
+
+        let kept = collect_bounded(
+            futures::stream::iter(events.clone()),
+            3,
+            OverflowPolicy::DropOldest,
+        )
+        .await
+        .unwrap();
+        assert_eq!(kept, events[2..]);
+
+        let result = collect_bounded(
+            futures::stream::iter(events.clone()),
+            3,
+            OverflowPolicy::Error,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(ToolCallStreamError::BufferOverflow(3))
+        ));
+
+        // 上限以内であればどちらの方針でもすべて集める
+        let all = collect_bounded(
+            futures::stream::iter(events.clone()),
+            5,
+            OverflowPolicy::Error,
+        )
+        .await
+        .unwrap();
+        assert_eq!(all, events);
+    }
+
+    #[tokio::test]
+    async fn test_timestamped() {
+        tokio::time::pause();
+        let chunks = [
+            (0, "<get_weather><location>To"),
+            (200, "kyo</location>"),
+            (300, "</get_weather>"),
+        ];
+        let input_stream = Box::pin(futures::stream::iter(chunks).then(
+            |(delay, chunk)| async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                chunk.to_string()
+            },
+        ));
+
+        let events: Vec<_> = timestamped(XmlStreamParser::new(input_stream))
+            .collect()
+            .await;
+
+        let elapsed: Vec<_> = events.iter().map(|(elapsed, _)| *elapsed).collect();
+        assert!(matches!(events[0].1, ToolCallEvent::ToolStart { .. }));
+        assert!(elapsed[0] < Duration::from_millis(200));
+        assert!(elapsed.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(matches!(
+            events.last().unwrap().1,
+            ToolCallEvent::ToolEnd { .. }
+        ));
+        assert!(*elapsed.last().unwrap() - elapsed[0] >= Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_inspect_events() {
+        let input = "天気を確認します。<get_weather><location>Tokyo</location></get_weather>";
+        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let inspector = seen.clone();
+        let events: Vec<_> = inspect_events(XmlStreamParser::new(input_stream), move |event| {
+            inspector.lock().unwrap().push(event.clone());
+        })
+        .collect()
+        .await;
+
+        assert!(
+            events
+                .iter()
+                .any(|event| matches!(event, ToolCallEvent::ToolEnd { .. }))
+        );
+        assert_eq!(*seen.lock().unwrap(), events);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_parser() {
+        let input = r#"明日のニューヨークの天気を確認します。
+
+<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+</get_weather>
+
+確認しました。"#;
+        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+        let mut rx = spawn_parser(input_stream);
+
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event);
+        }
+
+        let expected: Vec<_> = parse_str(input).collect();
+        assert_eq!(events, expected);
+    }
+
+    #[tokio::test]
+    async fn test_warn_suspicious_values() {
+        let input = "<write_to_file><path>a.html</path><content>本文<tag>です</content><note>a &lt;b&gt; c</note></write_to_file>";
+        let input_stream = Box::pin(futures::stream::iter(vec![input.to_string()]));
+        let events: Vec<_> = XmlStreamParser::new(input_stream)
+            .warn_suspicious_values(true)
+            .collect()
+            .await;
+        assert_eq!(
+            events,
+            vec![
+                ToolCallEvent::ToolStart {
+                    id: "tool_1".to_string(),
+                    name: "write_to_file".to_string()
+                },
+                ToolCallEvent::SuspiciousValue {
+                    id: "tool_1".to_string(),
+                    param: "content".to_string(),
+                    snippet: "<tag>です".to_string(),
+                },
+                // 実体参照を展開した値も対象とする
+                ToolCallEvent::SuspiciousValue {
+                    id: "tool_1".to_string(),
+                    param: "note".to_string(),
+                    snippet: "<b> c".to_string(),
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({
+                        "path": "a.html",
+                        "content": "本文<tag>です",
+                        "note": "a <b> c",
+                    }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string()
+                },
+            ]
+        );
+
+        // 既定では発行しない
+        assert!(
+            !parse_str(input).any(|event| matches!(event, ToolCallEvent::SuspiciousValue { .. }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_text_adjacent_to_tool_keeps_document_order() {
+        let input = "abc<get_weather><location>Tokyo</location></get_weather>de";
+        let expected = vec![
+            ToolCallEvent::Text("a".to_string()),
+            ToolCallEvent::Text("b".to_string()),
+            ToolCallEvent::Text("c".to_string()),
+            ToolCallEvent::ToolStart {
+                id: "tool_1".to_string(),
+                name: "get_weather".to_string(),
+            },
+            ToolCallEvent::Parameter {
+                id: "tool_1".to_string(),
+                arguments: serde_json::json!({ "location": "Tokyo" }),
+            },
+            ToolCallEvent::ToolEnd {
+                id: "tool_1".to_string(),
+            },
+            ToolCallEvent::Text("d".to_string()),
+            ToolCallEvent::Text("e".to_string()),
+        ];
+
+        // どの位置でチャンクが分かれても、タグに隣接するテキストはツールのイベントの前後に発行される
+        for chunk_size in 1..=input.len() {
+            let chunks: Vec<String> = input
+                .as_bytes()
+                .chunks(chunk_size)
+                .map(|chunk| String::from_utf8(chunk.to_vec()).unwrap())
+                .collect();
+            let input_stream = Box::pin(futures::stream::iter(chunks));
+            let events: Vec<_> = XmlStreamParser::new(input_stream).collect().await;
+            assert_eq!(events, expected, "chunk_size: {chunk_size}");
+        }
+        assert_eq!(parse_str(input).collect::<Vec<_>>(), expected);
+
+        // テキストをまとめる場合も順序は変わらない
+        let chunks = ["abc<get_", &input["abc<get_".len()..]].map(str::to_string);
+        let input_stream = Box::pin(futures::stream::iter(chunks));
+        let events: Vec<_> = XmlStreamParser::new(input_stream)
+            .coalesce_text(true)
+            .collect()
+            .await;
+        assert_eq!(events[0], ToolCallEvent::Text("abc".to_string()));
+        assert_eq!(events[1..4], expected[3..6]);
+        assert_eq!(events[4..], [ToolCallEvent::Text("de".to_string())]);
+    }
+
+    #[test]
+    fn test_validate_tool() {
+        let content = "x".repeat(10_000);
+        let input = format!(
+            "前<delete_file><path>/</path><content>{content}</content></delete_file>後<get_weather><location>Tokyo</location></get_weather>"
+        );
+        let mut core = XmlParserCore::new().validate_tool(|name| name != "delete_file");
+        let mut events = Vec::new();
+        let mut max_buffered = 0;
+        for (i, c) in input.char_indices() {
+            events.extend(core.take_pending_event());
+            events.extend(core.process_char(&input[i..i + c.len_utf8()]));
+            max_buffered = max_buffered.max(core.leftover().len());
+        }
+        events.extend(core.take_pending_event());
+
+        assert_eq!(
+            events,
+            vec![
+                ToolCallEvent::Text("前".to_string()),
+                ToolCallEvent::Error("rejected tool: delete_file".to_string()),
+                ToolCallEvent::Text("後".to_string()),
+                ToolCallEvent::ToolStart {
+                    id: "tool_1".to_string(),
+                    name: "get_weather".to_string(),
+                },
+                ToolCallEvent::Parameter {
+                    id: "tool_1".to_string(),
+                    arguments: serde_json::json!({ "location": "Tokyo" }),
+                },
+                ToolCallEvent::ToolEnd {
+                    id: "tool_1".to_string(),
+                },
+            ]
+        );
+        // 拒否したツールのパラメータの値はバッファしない
+        assert!(max_buffered < "</delete_file>".len() + "<get_weather".len());
+    }
+
+    #[test]
+    fn test_inner_tag_policy_ignores_comparison_operators() {
+        let input = "<run><code>if (a<b && c>d) { x < y }</code></run>";
+        let expected = vec![ToolCallEvent::Parameter {
+            id: "tool_1".to_string(),
+            arguments: serde_json::json!({ "code": "if (a<b && c>d) { x < y }" }),
+        }];
+        for policy in [
+            InnerTagPolicy::Literal,
+            InnerTagPolicy::Parse,
+            InnerTagPolicy::Error,
+        ] {
+            let events: Vec<_> =
+                StrEvents::new(input, XmlParserCore::new().inner_tag_policy(policy))
+                    .filter(|event| {
+                        matches!(
+                            event,
+                            ToolCallEvent::Parameter { .. } | ToolCallEvent::Error(_)
+                        )
+                    })
+                    .collect();
+            assert_eq!(events, expected, "{policy:?}");
+        }
+
+        // 属性付きの開始タグは引き続きタグとして扱う
+        let input = r#"<run><code>x<b class="y">z</b></code></run>"#;
+        let events: Vec<_> = StrEvents::new(
+            input,
+            XmlParserCore::new().inner_tag_policy(InnerTagPolicy::Error),
+        )
+        .filter(|event| matches!(event, ToolCallEvent::Error(_)))
+        .collect();
+        assert_eq!(
+            events,
+            vec![ToolCallEvent::Error(
+                r#"unexpected tag <b class="y"> inside parameter code"#.to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_inner_tag_policy() {
+        let input = "<write_to_file><content>本文<b>強調</b></content></write_to_file>";
+        let parameter = |arguments| ToolCallEvent::Parameter {
+            id: "tool_1".to_string(),
+            arguments,
+        };
+        let parse = |policy| {
+            StrEvents::new(input, XmlParserCore::new().inner_tag_policy(policy))
+                .filter(|event| {
+                    matches!(
+                        event,
+                        ToolCallEvent::Parameter { .. } | ToolCallEvent::Error(_)
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // 既定ではタグを値の一部として保持する
+        let literal = parameter(serde_json::json!({ "content": "本文<b>強調</b>" }));
+        assert_eq!(parse(InnerTagPolicy::Literal), vec![literal.clone()]);
+        assert_eq!(
+            parse_str(input)
+                .filter(|event| matches!(event, ToolCallEvent::Parameter { .. }))
+                .collect::<Vec<_>>(),
+            vec![literal.clone()]
+        );
+
+        // タグと並ぶテキストも失わない
+        assert_eq!(
+            parse(InnerTagPolicy::Parse),
+            vec![parameter(
+                serde_json::json!({ "content": { "#text": "本文", "b": "強調" } })
+            )]
+        );
+
+        // 値は保持したまま、開始タグについてのみエラーを発行する
+        assert_eq!(
+            parse(InnerTagPolicy::Error),
+            vec![
+                ToolCallEvent::Error("unexpected tag <b> inside parameter content".to_string()),
+                literal,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_summary_event() {
+        let input = r#"明日のニューヨークの天気を確認します。
+
+<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+  <unit>fahrenheit</unit>
+</get_weather>
+
+天気予報を取得しました。次に、ファイルに書き込みます。
+
+<write_to_file>
+<path>weather_report.txt</path>
+<content>
+明日のニューヨークの天気予報：晴れ時々曇り
+</content>
+</write_to_file>
+
+処理が完了しました。"#;
+        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+        let events: Vec<_> = XmlStreamParser::new(input_stream)
+            .summary_event(true)
+            .collect()
+            .await;
+
+        let text_chars = "明日のニューヨークの天気を確認します。\n\n\n\n天気予報を取得しました。次に、ファイルに書き込みます。\n\n\n\n処理が完了しました。"
+            .chars()
+            .count();
+        assert_eq!(
+            events.last(),
+            Some(&ToolCallEvent::StreamSummary {
+                tools: 2,
+                params: 5,
+                text_chars,
+            })
+        );
+        let summaries = events
+            .iter()
+            .filter(|event| matches!(event, ToolCallEvent::StreamSummary { .. }))
+            .count();
+        assert_eq!(summaries, 1);
+
+        // 既定では発行しない
+        assert!(
+            !parse_str(input).any(|event| matches!(event, ToolCallEvent::StreamSummary { .. }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_for_each_tool() {
+        let input = r#"明日のニューヨークの天気を確認します。
+
+<get_weather>
+  <location>New York</location>
+  <date>tomorrow</date>
+  <unit>fahrenheit</unit>
+</get_weather>
+
+天気予報を取得しました。次に、ファイルに書き込みます。
+
+<write_to_file>
+<path>weather_report.txt</path>
+<content>
+明日のニューヨークの天気予報：晴れ時々曇り
+</content>
+</write_to_file>
+
+処理が完了しました。"#;
+        let input_stream = Box::pin(futures::stream::iter(input.chars().map(|c| c.to_string())));
+
+        let mut tool_calls = Vec::new();
+        XmlStreamParser::new(input_stream)
+            .for_each_tool(|tool_call| tool_calls.push(tool_call))
+            .await;
+
+        assert_eq!(
+            tool_calls,
+            vec![
+                ToolCall {
+                    tool_name: "get_weather".to_string(),
+                    parameters: HashMap::from([
+                        ("location".to_string(), "New York".to_string()),
+                        ("date".to_string(), "tomorrow".to_string()),
+                        ("unit".to_string(), "fahrenheit".to_string()),
+                    ]),
+                    attributes: HashMap::new(),
+                    spans: HashMap::new(),
+                },
+                ToolCall {
+                    tool_name: "write_to_file".to_string(),
+                    parameters: HashMap::from([
+                        ("path".to_string(), "weather_report.txt".to_string()),
+                        (
+                            "content".to_string(),
+                            "明日のニューヨークの天気予報：晴れ時々曇り".to_string()
+                        ),
+                    ]),
+                    attributes: HashMap::new(),
+                    spans: HashMap::new(),
+                },
+            ]
+        );
+    }
 }
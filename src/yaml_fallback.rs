@@ -0,0 +1,64 @@
+//! XMLの代わりに出力されたYAML風のツール呼び出しブロックを解釈するフォールバック
+//!
+//! 本格的なYAMLパーサーではなく、次の形式のみを対象とした簡易実装です。
+//!
+//! ```text
+//! tool: get_weather
+//! params:
+//!   location: Tokyo
+//!   date: tomorrow
+//! ```
+
+use crate::ToolCall;
+use std::collections::HashMap;
+use std::iter::Peekable;
+
+/// テキストから最初の `tool:` / `params:` ブロックを探してパースする
+pub(crate) fn parse_yaml_tool_call(text: &str) -> Option<ToolCall> {
+    next_tool_call(&mut text.lines().peekable())
+}
+
+/// テキストに含まれるすべての `tool:` / `params:` ブロックを順番にパースする
+pub(crate) fn parse_yaml_tool_calls(text: &str) -> Vec<ToolCall> {
+    let mut lines = text.lines().peekable();
+    std::iter::from_fn(|| next_tool_call(&mut lines)).collect()
+}
+
+/// 次の `tool:` 行から1つのブロックを読み取る。後続のブロックの行は読み進めない
+fn next_tool_call<'a>(lines: &mut Peekable<impl Iterator<Item = &'a str>>) -> Option<ToolCall> {
+    let tool_name = loop {
+        let name = unquote(lines.next()?.strip_prefix("tool:").unwrap_or_default());
+        if !name.is_empty() {
+            break name;
+        }
+    };
+
+    while lines.next_if(|line| line.trim().is_empty()).is_some() {}
+    let mut parameters = HashMap::new();
+    if lines.next_if(|line| line.trim_end() == "params:").is_some() {
+        // インデントされた `key: value` 行をパラメータとして読み取る
+        while let Some(line) = lines.next_if(|line| line.starts_with([' ', '\t'])) {
+            let Some((key, value)) = line.trim().split_once(':') else {
+                break;
+            };
+            parameters.insert(key.trim().to_string(), unquote(value));
+        }
+    }
+
+    Some(ToolCall {
+        tool_name,
+        parameters,
+        attributes: HashMap::new(),
+        spans: HashMap::new(),
+    })
+}
+
+/// 前後の空白と、値全体を囲む引用符を取り除く
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    ['"', '\'']
+        .iter()
+        .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+        .unwrap_or(value)
+        .to_string()
+}
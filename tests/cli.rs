@@ -0,0 +1,79 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn test_cli_reads_stdin_and_prints_jsonl() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tool_use_with_xml"))
+        .arg("--json")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(
+            "確認します。\n<get_weather>\n<location>Tokyo</location>\n</get_weather>\n".as_bytes(),
+        )
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let events: Vec<serde_json::Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let tool_events: Vec<_> = events
+        .iter()
+        .filter(|event| event["type"] != "text")
+        .collect();
+    assert_eq!(
+        tool_events,
+        [
+            &serde_json::json!({"type": "tool_start", "data": {"id": "tool_1", "name": "get_weather"}}),
+            &serde_json::json!({"type": "parameter", "data": {"id": "tool_1", "arguments": {"location": "Tokyo"}}}),
+            &serde_json::json!({"type": "tool_end", "data": {"id": "tool_1"}}),
+        ]
+    );
+    let text: String = events
+        .iter()
+        .filter_map(|event| event["data"].as_str())
+        .collect();
+    assert_eq!(text, "確認します。\n\n");
+}
+
+#[test]
+fn test_cli_handles_single_long_line() {
+    // 1行が非常に長い入力（改行のない1MBのパラメータ）でもスタックを使い果たさない
+    let content = "x".repeat(1 << 20);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tool_use_with_xml"))
+        .arg("--json")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let input = format!("<write_to_file><content>{content}</content></write_to_file>");
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()).unwrap());
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap();
+    assert!(output.status.success(), "{:?}", output.status);
+
+    let events: Vec<serde_json::Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let parameter = events
+        .iter()
+        .find(|event| event["type"] == "parameter")
+        .unwrap();
+    assert_eq!(
+        parameter["data"]["arguments"]["content"]
+            .as_str()
+            .map(str::len),
+        Some(content.len())
+    );
+}
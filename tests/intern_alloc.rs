@@ -0,0 +1,50 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tool_use_with_xml::ToolParser;
+use tool_use_with_xml::intern::KeyInterner;
+
+// 確保の回数を数えるアロケーター
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn count_allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+#[test]
+fn test_parse_interned_does_not_allocate_keys() {
+    let parser = ToolParser::new();
+    let text = "<get_weather><location>Tokyo</location><date>tomorrow</date><unit>celsius</unit></get_weather>";
+    let mut interner = KeyInterner::new();
+    parser.parse_interned(text, &mut interner).unwrap();
+
+    // パース後にインターンする場合と比べ、3つのパラメータ名の `String` の分だけ確保が減る
+    let parse_then_intern = count_allocations(|| {
+        let tool_call = parser.parse(text).unwrap();
+        interner.intern_call(tool_call);
+    });
+    let interned = count_allocations(|| {
+        parser.parse_interned(text, &mut interner).unwrap();
+    });
+    assert!(
+        interned + 3 <= parse_then_intern,
+        "parse_interned: {interned}, parse + intern_call: {parse_then_intern}"
+    );
+}